@@ -0,0 +1,399 @@
+mod error;
+pub mod octo;
+mod parser;
+
+pub use crate::error::AsmError;
+use crate::parser::{parse_line, Expr, Line, Operand, Statement, Term};
+use emulator::instruction::{Address, Instruction};
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/// Programs are loaded right after the interpreter
+pub const ORIGIN: Address = 0x200;
+const MAX_INCLUDE_DEPTH: usize = 16;
+
+const MNEMONICS: &[&str] = &[
+    "CLS", "RET", "SYS", "JP", "CALL", "SE", "SNE", "LD", "ADD", "OR", "AND", "XOR", "SUB",
+    "SUBN", "SHR", "SHL", "RND", "DRW", "SKP", "SKNP", "SCD", "SCU", "SCR", "SCL", "EXIT",
+    "LOW", "HIGH", "SAVE", "LOAD", "PLANE", "AUDIO", "PITCH",
+];
+
+/* error message and the column it applies to */
+type Located<T> = Result<T, (usize, String)>;
+
+struct SourceLine {
+    file: String,
+    number: usize,
+    line: Line,
+}
+
+impl SourceLine {
+    fn error(&self, (column, message): (usize, String)) -> AsmError {
+        AsmError { file: self.file.clone(), line: self.number, column, message }
+    }
+}
+
+/// Assemble source text, included files are read relative to the current directory
+pub fn assemble(source: &str) -> Result<Vec<u8>, AsmError> {
+    assemble_with_loader("<input>", source, |path| fs::read_to_string(path))
+}
+
+/// Assemble a source file, included files are read relative to it
+pub fn assemble_file(path: &Path) -> Result<Vec<u8>, AsmError> {
+    let name = path.display().to_string();
+    let source = fs::read_to_string(path)
+        .map_err(|err| AsmError { file: name.clone(), line: 0, column: 0, message: err.to_string() })?;
+
+    assemble_with_loader(&name, &source, |path| fs::read_to_string(path))
+}
+
+/// Assemble `source`, named `name` in errors, reading included files with `load`
+pub fn assemble_with_loader<F>(name: &str, source: &str, load: F) -> Result<Vec<u8>, AsmError>
+    where F: Fn(&Path) -> io::Result<String>
+{
+    let mut lines = Vec::new();
+
+    read_lines(name, source, &load, 0, &mut lines)?;
+    let symbols = resolve_symbols(&lines)?;
+    encode_lines(&lines, &symbols)
+}
+
+/// Parse every line, replacing includes with the lines of the included file
+fn read_lines<F>(file: &str, source: &str, load: &F, depth: usize, out: &mut Vec<SourceLine>) -> Result<(), AsmError>
+    where F: Fn(&Path) -> io::Result<String>
+{
+    for (idx, text) in source.lines().enumerate() {
+        let mut src = SourceLine { file: file.to_string(), number: idx + 1, line: Line::default() };
+
+        src.line = parse_line(text).map_err(|err| src.error((err.column, err.message)))?;
+        let (path, column) = match &src.line.statement {
+            Some((Statement::Include(path), column)) => (path.clone(), *column),
+            _ => {
+                out.push(src);
+                continue;
+            },
+        };
+        if depth == MAX_INCLUDE_DEPTH {
+            return Err(src.error((column, "includes are nested too deep".to_string())));
+        }
+        let path = Path::new(file).parent().unwrap_or_else(|| Path::new("")).join(path);
+        let included = load(&path)
+            .map_err(|err| src.error((column, format!("cannot include {}: {}", path.display(), err))))?;
+
+        // a label on the include line points to the start of the included code
+        src.line.statement = None;
+        out.push(src);
+        read_lines(&path.display().to_string(), &included, load, depth + 1, out)?;
+    }
+    Ok(())
+}
+
+fn size(statement: &Statement) -> usize {
+    match statement {
+        Statement::Instruction { mnemonic, operands, .. } => match (mnemonic.as_str(), &operands[..]) {
+            ("LD", [Operand::I, Operand::Long(_)]) => 4,
+            _ => 2,
+        },
+        Statement::Bytes(exprs) => exprs.len(),
+        Statement::Words(exprs) => 2 * exprs.len(),
+        Statement::Include(_) | Statement::Constant(..) => 0,
+    }
+}
+
+/// First pass, give every label its address. Constants can only use
+/// symbols defined before them.
+fn resolve_symbols(lines: &[SourceLine]) -> Result<HashMap<String, i64>, AsmError> {
+    let mut symbols = HashMap::new();
+    let mut addr = ORIGIN as i64;
+    let define = |symbols: &mut HashMap<String, i64>, name: &str, value: i64, column: usize| {
+        if symbols.insert(name.to_string(), value).is_some() {
+            Err((column, format!("`{}` is already defined", name)))
+        } else {
+            Ok(())
+        }
+    };
+
+    for src in lines {
+        if let Some((label, column)) = &src.line.label {
+            define(&mut symbols, label, addr, *column).map_err(|err| src.error(err))?;
+        }
+        if let Some((statement, column)) = &src.line.statement {
+            if let Statement::Constant(name, expr) = statement {
+                let value = eval(expr, &symbols).map_err(|err| src.error(err))?;
+                define(&mut symbols, name, value, *column).map_err(|err| src.error(err))?;
+            }
+            addr += size(statement) as i64;
+        }
+    }
+    Ok(symbols)
+}
+
+fn eval(expr: &Expr, symbols: &HashMap<String, i64>) -> Located<i64> {
+    let mut total = 0;
+
+    for signed in expr.terms.iter() {
+        let value = match &signed.term {
+            Term::Number(val) => *val as i64,
+            Term::Symbol(name) => *symbols.get(name)
+                .ok_or_else(|| (signed.column, format!("undefined symbol `{}`", name)))?,
+        };
+        total += if signed.negative { -value } else { value };
+    }
+    Ok(total)
+}
+
+/// Evaluate to a `bits` wide value, negative values are stored as two's complement
+fn value(expr: &Expr, bits: u32, symbols: &HashMap<String, i64>) -> Located<u32> {
+    let val = eval(expr, symbols)?;
+
+    if val >= (1 << bits) || val < -(1 << (bits - 1)) {
+        return Err((expr.column(), format!("value {} doesn't fit in {} bits", val, bits)));
+    }
+    Ok((val & ((1 << bits) - 1)) as u32)
+}
+
+/// Second pass, produce the bytes of every statement
+fn encode_lines(lines: &[SourceLine], symbols: &HashMap<String, i64>) -> Result<Vec<u8>, AsmError> {
+    let mut out = Vec::new();
+
+    for src in lines {
+        let bytes = match &src.line.statement {
+            Some((Statement::Instruction { mnemonic, operands, .. }, column)) =>
+                encode(mnemonic, operands, *column, symbols),
+            Some((Statement::Bytes(exprs), _)) =>
+                exprs.iter().map(|expr| value(expr, 8, symbols).map(|val| val as u8)).collect(),
+            Some((Statement::Words(exprs), _)) => exprs.iter()
+                .map(|expr| value(expr, 16, symbols).map(|val| (val as u16).to_be_bytes()))
+                .collect::<Located<Vec<[u8; 2]>>>()
+                .map(|words| words.concat()),
+            _ => continue,
+        };
+        out.extend(bytes.map_err(|err| src.error(err))?);
+    }
+    Ok(out)
+}
+
+fn encode(mnemonic: &str, operands: &[Operand], column: usize, symbols: &HashMap<String, i64>) -> Located<Vec<u8>> {
+    use Operand::*;
+
+    let addr = |expr| value(expr, 12, symbols).map(|val| val as Address);
+    let byte = |expr| value(expr, 8, symbols).map(|val| val as u8);
+    let nibble = |expr| value(expr, 4, symbols).map(|val| val as u8);
+
+    let instr = match (mnemonic, operands) {
+        ("CLS", []) => Instruction::Cls,
+        ("RET", []) => Instruction::Ret,
+        ("SYS", [Value(a)]) => Instruction::Sys(addr(a)?),
+        ("JP", [Value(a)]) => Instruction::Jump(addr(a)?),
+        ("JP", [Register(0), Value(a)]) => Instruction::JumpRel(addr(a)?),
+        ("CALL", [Value(a)]) => Instruction::Call(addr(a)?),
+        ("SE", [Register(x), Value(v)]) => Instruction::SkipValEq(*x, byte(v)?),
+        ("SE", [Register(x), Register(y)]) => Instruction::SkipEq(*x, *y),
+        ("SNE", [Register(x), Value(v)]) => Instruction::SkipValNotEq(*x, byte(v)?),
+        ("SNE", [Register(x), Register(y)]) => Instruction::SkipNotEq(*x, *y),
+        ("LD", [Register(x), Value(v)]) => Instruction::LoadVal(*x, byte(v)?),
+        ("LD", [Register(x), Register(y)]) => Instruction::Load(*x, *y),
+        ("LD", [I, Value(a)]) => Instruction::LoadAddr(addr(a)?),
+        ("LD", [I, Long(a)]) => {
+            let mut bytes = Instruction::LoadLongAddr.asm().to_vec();
+            bytes.extend_from_slice(&(value(a, 16, symbols)? as u16).to_be_bytes());
+            return Ok(bytes);
+        },
+        ("LD", [Register(x), DelayTimer]) => Instruction::LoadDelayTimer(*x),
+        ("LD", [Register(x), Key]) => Instruction::LoadKey(*x),
+        ("LD", [DelayTimer, Register(x)]) => Instruction::SetDelayTimer(*x),
+        ("LD", [SoundTimer, Register(x)]) => Instruction::SetSoundTimer(*x),
+        ("LD", [Font, Register(x)]) => Instruction::LoadSprite(*x),
+        ("LD", [BigFont, Register(x)]) => Instruction::LoadBigSprite(*x),
+        ("LD", [Bcd, Register(x)]) => Instruction::Bcd(*x),
+        ("LD", [IndirectI, Register(x)]) => Instruction::StoreRegs(*x),
+        ("LD", [Register(x), IndirectI]) => Instruction::LoadRegs(*x),
+        ("LD", [Flags, Register(x)]) => Instruction::StoreFlags(*x),
+        ("LD", [Register(x), Flags]) => Instruction::LoadFlags(*x),
+        ("ADD", [Register(x), Value(v)]) => Instruction::AddVal(*x, byte(v)?),
+        ("ADD", [Register(x), Register(y)]) => Instruction::Add(*x, *y),
+        ("ADD", [I, Register(x)]) => Instruction::AddI(*x),
+        ("OR", [Register(x), Register(y)]) => Instruction::Or(*x, *y),
+        ("AND", [Register(x), Register(y)]) => Instruction::And(*x, *y),
+        ("XOR", [Register(x), Register(y)]) => Instruction::Xor(*x, *y),
+        ("SUB", [Register(x), Register(y)]) => Instruction::Sub(*x, *y),
+        ("SUBN", [Register(x), Register(y)]) => Instruction::SubN(*x, *y),
+        ("SHR", [Register(x)]) => Instruction::ShiftRight(*x, *x),
+        ("SHR", [Register(x), Register(y)]) => Instruction::ShiftRight(*x, *y),
+        ("SHL", [Register(x)]) => Instruction::ShiftLeft(*x, *x),
+        ("SHL", [Register(x), Register(y)]) => Instruction::ShiftLeft(*x, *y),
+        ("RND", [Register(x), Value(v)]) => Instruction::Random(*x, byte(v)?),
+        ("DRW", [Register(x), Register(y), Value(n)]) => Instruction::Draw(*x, *y, nibble(n)?),
+        ("SKP", [Register(x)]) => Instruction::SkipKeyPressed(*x),
+        ("SKNP", [Register(x)]) => Instruction::SkipKeyNotPressed(*x),
+        ("SCD", [Value(n)]) => Instruction::ScrollDown(nibble(n)?),
+        ("SCU", [Value(n)]) => Instruction::ScrollUp(nibble(n)?),
+        ("SCR", []) => Instruction::ScrollRight,
+        ("SCL", []) => Instruction::ScrollLeft,
+        ("EXIT", []) => Instruction::Exit,
+        ("LOW", []) => Instruction::LowRes,
+        ("HIGH", []) => Instruction::HighRes,
+        ("SAVE", [Range(x, y)]) | ("SAVE", [Register(x), Register(y)]) => Instruction::SaveRange(*x, *y),
+        ("LOAD", [Range(x, y)]) | ("LOAD", [Register(x), Register(y)]) => Instruction::LoadRange(*x, *y),
+        ("PLANE", [Value(n)]) => Instruction::SelectPlanes(nibble(n)?),
+        ("AUDIO", []) => Instruction::LoadAudio,
+        ("PITCH", [Register(x)]) => Instruction::SetPitch(*x),
+        (name, _) if MNEMONICS.contains(&name) =>
+            return Err((column, format!("invalid operands for {}", name))),
+        (name, _) => return Err((column, format!("unknown instruction `{}`", name))),
+    };
+    Ok(instr.asm().to_vec())
+}
+
+#[cfg(test)]
+mod test_assembler {
+    use super::*;
+
+    fn error(source: &str) -> (usize, usize, String) {
+        let err = assemble(source).unwrap_err();
+        (err.line, err.column, err.message)
+    }
+
+    #[test]
+    fn test_instructions() {
+        let source = "
+            CLS
+            RET
+            SYS 0x123
+            JP 0x234
+            JP V0, 0x300
+            CALL 0x345
+            SE V4, 0x56
+            SE V6, V7
+            SNE V5, 0x67
+            SNE VA, VB
+            LD V7, 0x89
+            LD V1, V2
+            LD I, 0xBCD
+            LD V0, DT
+            LD V0, K
+            LD DT, V0
+            LD ST, V0
+            LD F, V1
+            LD HF, V2
+            LD B, V1
+            LD [I], V1
+            LD V1, [I]
+            LD R, V7
+            LD V7, R
+            ADD V8, 0x90
+            ADD V1, V2
+            ADD I, V0
+            OR V1, V2
+            AND V1, V2
+            XOR V1, V2
+            SUB V1, V2
+            SUBN V1, V2
+            SHR V1, V2
+            SHL V1
+            RND VD, 0xEF
+            DRW V1, V2, 3
+            SKP VF
+            SKNP VF
+            SCD 4
+            SCU 4
+            SCR
+            SCL
+            EXIT
+            LOW
+            HIGH
+            SAVE V1-V4
+            LOAD V4, V1
+            PLANE 3
+            AUDIO
+            PITCH V5
+        ";
+        let expected = Instruction::assemble(&[
+            Instruction::Cls, Instruction::Ret, Instruction::Sys(0x123), Instruction::Jump(0x234),
+            Instruction::JumpRel(0x300), Instruction::Call(0x345), Instruction::SkipValEq(4, 0x56),
+            Instruction::SkipEq(6, 7), Instruction::SkipValNotEq(5, 0x67), Instruction::SkipNotEq(0xA, 0xB),
+            Instruction::LoadVal(7, 0x89), Instruction::Load(1, 2), Instruction::LoadAddr(0xBCD),
+            Instruction::LoadDelayTimer(0), Instruction::LoadKey(0), Instruction::SetDelayTimer(0),
+            Instruction::SetSoundTimer(0), Instruction::LoadSprite(1), Instruction::LoadBigSprite(2),
+            Instruction::Bcd(1), Instruction::StoreRegs(1), Instruction::LoadRegs(1),
+            Instruction::StoreFlags(7), Instruction::LoadFlags(7), Instruction::AddVal(8, 0x90),
+            Instruction::Add(1, 2), Instruction::AddI(0), Instruction::Or(1, 2), Instruction::And(1, 2),
+            Instruction::Xor(1, 2), Instruction::Sub(1, 2), Instruction::SubN(1, 2),
+            Instruction::ShiftRight(1, 2), Instruction::ShiftLeft(1, 1), Instruction::Random(0xD, 0xEF),
+            Instruction::Draw(1, 2, 3), Instruction::SkipKeyPressed(0xF), Instruction::SkipKeyNotPressed(0xF),
+            Instruction::ScrollDown(4), Instruction::ScrollUp(4), Instruction::ScrollRight,
+            Instruction::ScrollLeft, Instruction::Exit, Instruction::LowRes, Instruction::HighRes,
+            Instruction::SaveRange(1, 4), Instruction::LoadRange(4, 1), Instruction::SelectPlanes(3),
+            Instruction::LoadAudio, Instruction::SetPitch(5),
+        ]);
+        assert_eq!(assemble(source), Ok(expected));
+    }
+
+    #[test]
+    fn test_labels_and_constants() {
+        let source = "
+            SPEED EQU 3
+            start:  LD V0, SPEED
+            loop:   ADD V0, -1
+                    LD I, sprite + 1
+                    LD I, LONG far
+                    SE V0, 0
+                    JP loop
+                    JP start
+            sprite: DB 0xF0, %10010000, $90
+                    DW 0xABCD, start
+            far     EQU 0x1234
+        ";
+        let mut expected = Instruction::assemble(&[
+            Instruction::LoadVal(0, 3),
+            Instruction::AddVal(0, 0xFF),
+            Instruction::LoadAddr(0x211),
+            Instruction::LoadLongAddr,
+        ]);
+        expected.extend_from_slice(&[0x12, 0x34]);
+        expected.extend(Instruction::assemble(&[
+            Instruction::SkipValEq(0, 0),
+            Instruction::Jump(0x202),
+            Instruction::Jump(0x200),
+        ]));
+        expected.extend_from_slice(&[0xF0, 0x90, 0x90, 0xAB, 0xCD, 0x02, 0x00]);
+
+        assert_eq!(assemble(source), Ok(expected));
+    }
+
+    #[test]
+    fn test_include() {
+        let load = |path: &Path| match path.to_str() {
+            Some("lib/font.asm") => Ok("digit: DB 1, 2\ninclude \"more.asm\"".to_string()),
+            Some("lib/more.asm") => Ok("DB 3".to_string()),
+            Some("lib/bad.asm") => Ok("\n  JP nowhere".to_string()),
+            _ => Err(io::Error::new(io::ErrorKind::NotFound, "not found")),
+        };
+
+        let source = "LD I, digit\nfont: include \"font.asm\"\nJP font";
+        let mut expected = Instruction::assemble(&[Instruction::LoadAddr(0x202)]);
+        expected.extend_from_slice(&[1, 2, 3]);
+        expected.extend(Instruction::assemble(&[Instruction::Jump(0x202)]));
+        assert_eq!(assemble_with_loader("lib/main.asm", source, load), Ok(expected));
+
+        let err = assemble_with_loader("lib/main.asm", "include \"bad.asm\"", load).unwrap_err();
+        assert_eq!(err.to_string(), "lib/bad.asm:2:6: undefined symbol `nowhere`");
+
+        let err = assemble_with_loader("lib/main.asm", "\n include \"none.asm\"", load).unwrap_err();
+        assert_eq!(err.to_string(), "lib/main.asm:2:2: cannot include lib/none.asm: not found");
+    }
+
+    #[test]
+    fn test_errors() {
+        assert_eq!(error("CLS\nFOO V1"), (2, 1, "unknown instruction `FOO`".to_string()));
+        assert_eq!(error("  LD V1"), (1, 3, "invalid operands for LD".to_string()));
+        assert_eq!(error("LD V1, 0x100"), (1, 8, "value 256 doesn't fit in 8 bits".to_string()));
+        assert_eq!(error("DRW V1, V2, 16"), (1, 13, "value 16 doesn't fit in 4 bits".to_string()));
+        assert_eq!(error("a: CLS\na: CLS"), (2, 1, "`a` is already defined".to_string()));
+        // constants can only use symbols defined before them
+        assert_eq!(error("A EQU B + 1\nB EQU 1"), (1, 7, "undefined symbol `B`".to_string()));
+        assert_eq!(error("DB 1,, 2"), (1, 5, "unexpected `,, 2`".to_string()));
+    }
+}
@@ -0,0 +1,34 @@
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process;
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+
+    let (input, output) = match args.len() {
+        2 => (PathBuf::from(&args[1]), Path::new(&args[1]).with_extension("ch8")),
+        4 if args[2] == "-o" => (PathBuf::from(&args[1]), PathBuf::from(&args[3])),
+        _ => {
+            eprintln!("usage: {} <source.asm|source.8o> [-o <output.ch8>]", args[0]);
+            process::exit(2);
+        },
+    };
+    // Octo sources use the .8o extension
+    let result = if input.extension().is_some_and(|ext| ext == "8o") {
+        assembler::octo::compile_file(&input).map(|program| program.rom)
+    } else {
+        assembler::assemble_file(&input)
+    };
+    let rom = match result {
+        Ok(rom) => rom,
+        Err(err) => {
+            eprintln!("{}", err);
+            process::exit(1);
+        },
+    };
+    if let Err(reason) = fs::write(&output, &rom) {
+        eprintln!("failed to write {}: {}", output.display(), reason);
+        process::exit(1);
+    }
+}
@@ -0,0 +1,881 @@
+//! Compiler for the Octo language, see
+//! https://github.com/JohnEarnest/Octo/blob/gh-pages/docs/Manual.md
+
+use crate::{AsmError, ORIGIN};
+use emulator::instruction::{Address, Instruction, Register};
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::Path;
+
+/* stop runaway recursive macros */
+const MAX_EXPANSIONS: usize = 100_000;
+
+/// Compiled ROM, with the address of every label for debugging
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Program {
+    pub rom: Vec<u8>,
+    pub symbols: BTreeMap<String, Address>,
+}
+
+#[derive(Debug, Clone)]
+struct Token {
+    text: String,
+    line: usize,
+    column: usize,
+}
+
+impl Token {
+    fn error(&self, message: String) -> AsmError {
+        AsmError { file: String::new(), line: self.line, column: self.column, message }
+    }
+}
+
+struct Macro {
+    args: Vec<String>,
+    body: Vec<Token>,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Fixup {
+    /* 12-bit address in the low bits of the opcode */
+    Short,
+    /* 16-bit address following F000 */
+    Long,
+}
+
+enum Block {
+    Loop { start: usize, exits: Vec<usize> },
+    /* address of the jump taken when the condition is false */
+    If { jump: usize },
+}
+
+enum Rhs {
+    Register(Register),
+    Value(u8),
+}
+
+enum Cond {
+    Key(Register, bool),
+    Equal(Register, Rhs, bool),
+    /* VF holds the carry of a - b */
+    Compare { a: Rhs, b: Rhs, carry: u8 },
+}
+
+/// Split source in whitespace separated tokens, dropping `#` comments
+fn tokenize(source: &str) -> Vec<Token> {
+    let mut tokens = Vec::new();
+
+    for (idx, line) in source.lines().enumerate() {
+        let code = line.split('#').next().unwrap_or("");
+        let mut start = None;
+
+        for (column, c) in code.char_indices().chain(std::iter::once((code.len(), ' '))) {
+            match (c.is_whitespace(), start) {
+                (true, Some(begin)) => {
+                    tokens.push(Token { text: code[begin..column].to_string(), line: idx + 1, column: begin + 1 });
+                    start = None;
+                },
+                (false, None) => start = Some(column),
+                _ => {},
+            }
+        }
+    }
+    tokens
+}
+
+fn number(text: &str) -> Option<i64> {
+    let (negative, digits) = match text.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, text),
+    };
+    let value = if let Some(hex) = digits.strip_prefix("0x") {
+        i64::from_str_radix(hex, 16).ok()?
+    } else if let Some(bin) = digits.strip_prefix("0b") {
+        i64::from_str_radix(bin, 2).ok()?
+    } else {
+        digits.parse().ok()?
+    };
+    Some(if negative { -value } else { value })
+}
+
+struct Compiler {
+    /* remaining tokens, in reverse order */
+    tokens: Vec<Token>,
+    last: Token,
+    rom: Vec<u8>,
+    here: usize,
+    labels: BTreeMap<String, Address>,
+    consts: HashMap<String, f64>,
+    aliases: HashMap<String, Register>,
+    macros: HashMap<String, Macro>,
+    fixups: Vec<(Token, usize, Fixup)>,
+    blocks: Vec<Block>,
+    expansions: usize,
+}
+
+impl Compiler {
+    fn new(mut tokens: Vec<Token>) -> Self {
+        tokens.reverse();
+        Compiler {
+            last: tokens.first().cloned().unwrap_or(Token { text: String::new(), line: 1, column: 1 }),
+            tokens,
+            rom: Vec::new(),
+            here: ORIGIN as usize,
+            labels: BTreeMap::new(),
+            consts: HashMap::new(),
+            aliases: HashMap::new(),
+            macros: HashMap::new(),
+            fixups: Vec::new(),
+            blocks: Vec::new(),
+            expansions: 0,
+        }
+    }
+
+    fn run(mut self) -> Result<Program, AsmError> {
+        let starts_with_main = self.tokens.len() >= 2
+            && self.tokens[self.tokens.len() - 1].text == ":"
+            && self.tokens[self.tokens.len() - 2].text == "main";
+
+        // execution starts at main
+        if !starts_with_main {
+            let main = Token { text: "main".to_string(), line: 1, column: 1 };
+            self.fixups.push((main, self.here, Fixup::Short));
+            self.emit(Instruction::Jump(0))?;
+        }
+        while let Some(token) = self.next() {
+            self.statement(token)?;
+        }
+        if let Some(block) = self.blocks.last() {
+            let name = match block {
+                Block::Loop { .. } => "loop",
+                Block::If { .. } => "begin",
+            };
+            return Err(self.last.error(format!("`{}` is never closed", name)));
+        }
+        for (token, addr, fixup) in std::mem::take(&mut self.fixups) {
+            let target = match self.labels.get(&token.text) {
+                Some(target) => *target,
+                None if token.text == "main" => return Err(token.error("the program has no `main` label".to_string())),
+                None => return Err(token.error(format!("undefined label `{}`", token.text))),
+            };
+            let offset = addr - ORIGIN as usize;
+            match fixup {
+                Fixup::Short => {
+                    if target > 0xFFF {
+                        return Err(token.error(format!("`{}` is out of 12-bit range", token.text)));
+                    }
+                    self.rom[offset] |= (target >> 8) as u8;
+                    self.rom[offset + 1] = target as u8;
+                },
+                Fixup::Long => self.rom[offset + 2..offset + 4].copy_from_slice(&target.to_be_bytes()),
+            }
+        }
+        Ok(Program { rom: self.rom, symbols: self.labels })
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.pop()?;
+        self.last = token.clone();
+        Some(token)
+    }
+    fn expect_token(&mut self) -> Result<Token, AsmError> {
+        let last = self.last.clone();
+        self.next().ok_or_else(|| last.error("unexpected end of file".to_string()))
+    }
+    fn expect(&mut self, text: &str) -> Result<Token, AsmError> {
+        let token = self.expect_token()?;
+        if token.text != text {
+            return Err(token.error(format!("expected `{}`, found `{}`", text, token.text)));
+        }
+        Ok(token)
+    }
+    fn peek(&self) -> Option<&str> {
+        self.tokens.last().map(|token| token.text.as_str())
+    }
+
+    fn write(&mut self, addr: usize, bytes: &[u8]) {
+        let offset = addr - ORIGIN as usize;
+
+        if self.rom.len() < offset + bytes.len() {
+            self.rom.resize(offset + bytes.len(), 0);
+        }
+        self.rom[offset..offset + bytes.len()].copy_from_slice(bytes);
+    }
+    fn emit_bytes(&mut self, bytes: &[u8]) -> Result<(), AsmError> {
+        if self.here + bytes.len() > 0x10000 {
+            return Err(self.last.error("program doesn't fit in memory".to_string()));
+        }
+        self.write(self.here, bytes);
+        self.here += bytes.len();
+        Ok(())
+    }
+    fn emit(&mut self, instr: Instruction) -> Result<(), AsmError> {
+        self.emit_bytes(&instr.asm())
+    }
+    /// Emit an instruction whose address is `target`, resolved at the end if
+    /// the label isn't defined yet
+    fn emit_addr(&mut self, target: Token, instr: fn(Address) -> Instruction) -> Result<(), AsmError> {
+        let addr = match self.known_value(&target) {
+            Some(addr) => self.range(&target, addr, 12)? as Address,
+            None => {
+                self.fixups.push((target, self.here, Fixup::Short));
+                0
+            },
+        };
+        self.emit(instr(addr))
+    }
+    fn jump_here(&mut self, at: usize) -> Result<(), AsmError> {
+        if self.here > 0xFFF {
+            return Err(self.last.error("jump target is out of 12-bit range".to_string()));
+        }
+        self.write(at, &Instruction::Jump(self.here as Address).asm());
+        Ok(())
+    }
+
+    fn define_label(&mut self, name: Token, addr: usize) -> Result<(), AsmError> {
+        if self.labels.contains_key(&name.text) || self.consts.contains_key(&name.text) {
+            return Err(name.error(format!("`{}` is already defined", name.text)));
+        }
+        self.labels.insert(name.text, addr as Address);
+        Ok(())
+    }
+    fn register(&self, token: &Token) -> Option<Register> {
+        if let Some(reg) = self.aliases.get(&token.text) {
+            return Some(*reg);
+        }
+        let mut chars = token.text.chars();
+        match (chars.next(), chars.next(), chars.next()) {
+            (Some('v'), Some(digit), None) | (Some('V'), Some(digit), None) =>
+                digit.to_digit(16).map(|reg| reg as Register),
+            _ => None,
+        }
+    }
+    fn expect_register(&mut self) -> Result<Register, AsmError> {
+        let token = self.expect_token()?;
+        self.register(&token).ok_or_else(|| token.error(format!("expected a register, found `{}`", token.text)))
+    }
+    /// Value of a number, constant or already defined label
+    fn known_value(&self, token: &Token) -> Option<i64> {
+        number(&token.text)
+            .or_else(|| self.consts.get(&token.text).map(|val| val.floor() as i64))
+            .or_else(|| self.labels.get(&token.text).map(|addr| *addr as i64))
+    }
+    fn value(&self, token: &Token) -> Result<i64, AsmError> {
+        self.known_value(token).ok_or_else(|| token.error(format!("undefined name `{}`", token.text)))
+    }
+    /// Check `val` fits in `bits`, negative values are stored as two's complement
+    fn range(&self, token: &Token, val: i64, bits: u32) -> Result<u32, AsmError> {
+        if val >= (1 << bits) || val < -(1 << (bits - 1)) {
+            return Err(token.error(format!("value {} doesn't fit in {} bits", val, bits)));
+        }
+        Ok((val & ((1 << bits) - 1)) as u32)
+    }
+    fn expect_value(&mut self, bits: u32) -> Result<u32, AsmError> {
+        let token = self.expect_token()?;
+        let val = self.value(&token)?;
+        self.range(&token, val, bits)
+    }
+    fn expect_rhs(&mut self) -> Result<Rhs, AsmError> {
+        let token = self.expect_token()?;
+        match self.register(&token) {
+            Some(reg) => Ok(Rhs::Register(reg)),
+            None => {
+                let val = self.value(&token)?;
+                Ok(Rhs::Value(self.range(&token, val, 8)? as u8))
+            },
+        }
+    }
+
+    fn statement(&mut self, token: Token) -> Result<(), AsmError> {
+        match token.text.as_str() {
+            ":" => {
+                let name = self.expect_token()?;
+                self.define_label(name, self.here)?;
+            },
+            ":next" => {
+                // points to the second byte of the next instruction
+                let name = self.expect_token()?;
+                self.define_label(name, self.here + 1)?;
+            },
+            ":alias" => {
+                let name = self.expect_token()?;
+                let reg = self.expect_register()?;
+                self.aliases.insert(name.text, reg);
+            },
+            ":const" => {
+                let name = self.expect_token()?;
+                let val = self.expect_token()?;
+                let val = self.value(&val)?;
+                self.consts.insert(name.text, val as f64);
+            },
+            ":calc" => {
+                let name = self.expect_token()?;
+                self.expect("{")?;
+                let val = self.calc()?;
+                self.expect("}")?;
+                self.consts.insert(name.text, val);
+            },
+            ":macro" => self.define_macro()?,
+            ":org" => {
+                let addr = self.expect_token()?;
+                let val = self.value(&addr)?;
+                if val < ORIGIN as i64 || val > 0xFFFF {
+                    return Err(addr.error(format!("can't place code at {}", val)));
+                }
+                self.here = val as usize;
+            },
+            ":byte" => {
+                let val = if self.peek() == Some("{") {
+                    let open = self.expect("{")?;
+                    let val = self.calc()?;
+                    self.expect("}")?;
+                    self.range(&open, val.floor() as i64, 8)?
+                } else {
+                    self.expect_value(8)?
+                };
+                self.emit_bytes(&[val as u8])?;
+            },
+            "loop" => self.blocks.push(Block::Loop { start: self.here, exits: Vec::new() }),
+            "while" => {
+                let cond = self.condition()?;
+
+                self.skip(cond, true)?;
+                // comparisons take several instructions before the skip
+                let exit = self.here;
+                match self.blocks.iter_mut().rev().find_map(|block| match block {
+                    Block::Loop { exits, .. } => Some(exits),
+                    Block::If { .. } => None,
+                }) {
+                    Some(exits) => exits.push(exit),
+                    None => return Err(token.error("`while` outside of a loop".to_string())),
+                }
+                self.emit(Instruction::Jump(0))?;
+            },
+            "again" => match self.blocks.pop() {
+                Some(Block::Loop { start, exits }) => {
+                    self.emit(Instruction::Jump(start as Address))?;
+                    for exit in exits {
+                        self.jump_here(exit)?;
+                    }
+                },
+                _ => return Err(token.error("`again` without `loop`".to_string())),
+            },
+            "if" => {
+                let cond = self.condition()?;
+                let kind = self.expect_token()?;
+                match kind.text.as_str() {
+                    // the next statement only runs when the condition holds
+                    "then" => self.skip(cond, false)?,
+                    "begin" => {
+                        self.skip(cond, true)?;
+                        self.blocks.push(Block::If { jump: self.here });
+                        self.emit(Instruction::Jump(0))?;
+                    },
+                    _ => return Err(kind.error(format!("expected `then` or `begin`, found `{}`", kind.text))),
+                }
+            },
+            "else" => match self.blocks.pop() {
+                Some(Block::If { jump }) => {
+                    let end = self.here;
+                    self.emit(Instruction::Jump(0))?;
+                    self.jump_here(jump)?;
+                    self.blocks.push(Block::If { jump: end });
+                },
+                _ => return Err(token.error("`else` without `begin`".to_string())),
+            },
+            "end" => match self.blocks.pop() {
+                Some(Block::If { jump }) => self.jump_here(jump)?,
+                _ => return Err(token.error("`end` without `begin`".to_string())),
+            },
+            "clear" => self.emit(Instruction::Cls)?,
+            "return" | ";" => self.emit(Instruction::Ret)?,
+            "hires" => self.emit(Instruction::HighRes)?,
+            "lores" => self.emit(Instruction::LowRes)?,
+            "exit" => self.emit(Instruction::Exit)?,
+            "scroll-left" => self.emit(Instruction::ScrollLeft)?,
+            "scroll-right" => self.emit(Instruction::ScrollRight)?,
+            "scroll-down" => {
+                let n = self.expect_value(4)?;
+                self.emit(Instruction::ScrollDown(n as u8))?;
+            },
+            "scroll-up" => {
+                let n = self.expect_value(4)?;
+                self.emit(Instruction::ScrollUp(n as u8))?;
+            },
+            "plane" => {
+                let n = self.expect_value(2)?;
+                self.emit(Instruction::SelectPlanes(n as u8))?;
+            },
+            "audio" => self.emit(Instruction::LoadAudio)?,
+            "bcd" => {
+                let reg = self.expect_register()?;
+                self.emit(Instruction::Bcd(reg))?;
+            },
+            "saveflags" => {
+                let reg = self.expect_register()?;
+                self.emit(Instruction::StoreFlags(reg))?;
+            },
+            "loadflags" => {
+                let reg = self.expect_register()?;
+                self.emit(Instruction::LoadFlags(reg))?;
+            },
+            "save" | "load" => {
+                let first = self.expect_register()?;
+                let instr = if self.peek() == Some("-") {
+                    self.next();
+                    let last = self.expect_register()?;
+                    if token.text == "save" {
+                        Instruction::SaveRange(first, last)
+                    } else {
+                        Instruction::LoadRange(first, last)
+                    }
+                } else if token.text == "save" {
+                    Instruction::StoreRegs(first)
+                } else {
+                    Instruction::LoadRegs(first)
+                };
+                self.emit(instr)?;
+            },
+            "sprite" => {
+                let x = self.expect_register()?;
+                let y = self.expect_register()?;
+                let n = self.expect_value(4)?;
+                self.emit(Instruction::Draw(x, y, n as u8))?;
+            },
+            "jump" => {
+                let target = self.expect_token()?;
+                self.emit_addr(target, Instruction::Jump)?;
+            },
+            "jump0" => {
+                let target = self.expect_token()?;
+                self.emit_addr(target, Instruction::JumpRel)?;
+            },
+            "native" => {
+                let target = self.expect_token()?;
+                self.emit_addr(target, Instruction::Sys)?;
+            },
+            "delay" | "buzzer" | "pitch" => {
+                self.expect(":=")?;
+                let reg = self.expect_register()?;
+                self.emit(match token.text.as_str() {
+                    "delay" => Instruction::SetDelayTimer(reg),
+                    "buzzer" => Instruction::SetSoundTimer(reg),
+                    _ => Instruction::SetPitch(reg),
+                })?;
+            },
+            "i" => self.index_statement()?,
+            _ => {
+                if let Some(reg) = self.register(&token) {
+                    self.register_statement(reg)?;
+                } else if self.macros.contains_key(&token.text) {
+                    self.expand_macro(token)?;
+                } else if let Some(val) = number(&token.text).or_else(|| self.consts.get(&token.text).map(|val| *val as i64)) {
+                    // sprite and other inline data
+                    let byte = self.range(&token, val, 8)?;
+                    self.emit_bytes(&[byte as u8])?;
+                } else if token.text.starts_with(':') {
+                    return Err(token.error(format!("unsupported directive `{}`", token.text)));
+                } else {
+                    // any other name calls a subroutine
+                    self.emit_addr(token, Instruction::Call)?;
+                }
+            },
+        }
+        Ok(())
+    }
+
+    fn index_statement(&mut self) -> Result<(), AsmError> {
+        let op = self.expect_token()?;
+        match op.text.as_str() {
+            ":=" => {
+                let target = self.expect_token()?;
+                match target.text.as_str() {
+                    "hex" | "bighex" => {
+                        let reg = self.expect_register()?;
+                        self.emit(if target.text == "hex" {
+                            Instruction::LoadSprite(reg)
+                        } else {
+                            Instruction::LoadBigSprite(reg)
+                        })
+                    },
+                    "long" => {
+                        let target = self.expect_token()?;
+                        let addr = match self.known_value(&target) {
+                            Some(addr) => self.range(&target, addr, 16)? as u16,
+                            None => {
+                                self.fixups.push((target, self.here, Fixup::Long));
+                                0
+                            },
+                        };
+                        self.emit(Instruction::LoadLongAddr)?;
+                        self.emit_bytes(&addr.to_be_bytes())
+                    },
+                    _ => self.emit_addr(target, Instruction::LoadAddr),
+                }
+            },
+            "+=" => {
+                let reg = self.expect_register()?;
+                self.emit(Instruction::AddI(reg))
+            },
+            _ => Err(op.error(format!("unexpected `{}` after `i`", op.text))),
+        }
+    }
+
+    fn register_statement(&mut self, dst: Register) -> Result<(), AsmError> {
+        let op = self.expect_token()?;
+        let rhs = self.expect_token()?;
+        let src = self.register(&rhs);
+
+        let instr = match (op.text.as_str(), src) {
+            (":=", Some(src)) => Instruction::Load(dst, src),
+            (":=", None) if rhs.text == "delay" => Instruction::LoadDelayTimer(dst),
+            (":=", None) if rhs.text == "key" => Instruction::LoadKey(dst),
+            (":=", None) if rhs.text == "random" => Instruction::Random(dst, self.expect_value(8)? as u8),
+            (":=", None) => Instruction::LoadVal(dst, self.range(&rhs, self.value(&rhs)?, 8)? as u8),
+            ("+=", Some(src)) => Instruction::Add(dst, src),
+            ("+=", None) => Instruction::AddVal(dst, self.range(&rhs, self.value(&rhs)?, 8)? as u8),
+            ("-=", Some(src)) => Instruction::Sub(dst, src),
+            ("-=", None) => Instruction::AddVal(dst, self.range(&rhs, -self.value(&rhs)?, 8)? as u8),
+            ("=-", Some(src)) => Instruction::SubN(dst, src),
+            ("|=", Some(src)) => Instruction::Or(dst, src),
+            ("&=", Some(src)) => Instruction::And(dst, src),
+            ("^=", Some(src)) => Instruction::Xor(dst, src),
+            (">>=", Some(src)) => Instruction::ShiftRight(dst, src),
+            ("<<=", Some(src)) => Instruction::ShiftLeft(dst, src),
+            ("=-", None) | ("|=", None) | ("&=", None) | ("^=", None) | (">>=", None) | ("<<=", None) =>
+                return Err(rhs.error(format!("expected a register, found `{}`", rhs.text))),
+            _ => return Err(op.error(format!("unknown operator `{}`", op.text))),
+        };
+        self.emit(instr)
+    }
+
+    fn condition(&mut self) -> Result<Cond, AsmError> {
+        let lhs = self.expect_register()?;
+        let op = self.expect_token()?;
+
+        Ok(match op.text.as_str() {
+            "key" => Cond::Key(lhs, true),
+            "-key" => Cond::Key(lhs, false),
+            "==" => Cond::Equal(lhs, self.expect_rhs()?, true),
+            "!=" => Cond::Equal(lhs, self.expect_rhs()?, false),
+            // carry is set when there is no borrow: a >= b
+            "<" => Cond::Compare { a: Rhs::Register(lhs), b: self.expect_rhs()?, carry: 0 },
+            ">=" => Cond::Compare { a: Rhs::Register(lhs), b: self.expect_rhs()?, carry: 1 },
+            ">" => Cond::Compare { a: self.expect_rhs()?, b: Rhs::Register(lhs), carry: 0 },
+            "<=" => Cond::Compare { a: self.expect_rhs()?, b: Rhs::Register(lhs), carry: 1 },
+            _ => return Err(op.error(format!("unknown comparison `{}`", op.text))),
+        })
+    }
+    /// Emit the instructions skipping the next one when the condition is `when`
+    fn skip(&mut self, cond: Cond, when: bool) -> Result<(), AsmError> {
+        let instr = match cond {
+            Cond::Key(reg, pressed) if pressed == when => Instruction::SkipKeyPressed(reg),
+            Cond::Key(reg, _) => Instruction::SkipKeyNotPressed(reg),
+            Cond::Equal(reg, Rhs::Register(src), equal) if equal == when => Instruction::SkipEq(reg, src),
+            Cond::Equal(reg, Rhs::Register(src), _) => Instruction::SkipNotEq(reg, src),
+            Cond::Equal(reg, Rhs::Value(val), equal) if equal == when => Instruction::SkipValEq(reg, val),
+            Cond::Equal(reg, Rhs::Value(val), _) => Instruction::SkipValNotEq(reg, val),
+            Cond::Compare { a, b, carry } => {
+                // VF := a - b, leaving only the carry in VF
+                match (a, b) {
+                    (Rhs::Register(a), Rhs::Register(b)) => {
+                        self.emit(Instruction::Load(0xF, a))?;
+                        self.emit(Instruction::Sub(0xF, b))?;
+                    },
+                    (Rhs::Register(a), Rhs::Value(b)) => {
+                        self.emit(Instruction::LoadVal(0xF, b))?;
+                        self.emit(Instruction::SubN(0xF, a))?;
+                    },
+                    (Rhs::Value(a), Rhs::Register(b)) => {
+                        self.emit(Instruction::LoadVal(0xF, a))?;
+                        self.emit(Instruction::Sub(0xF, b))?;
+                    },
+                    (Rhs::Value(_), Rhs::Value(_)) => unreachable!(),
+                }
+                if when {
+                    Instruction::SkipValEq(0xF, carry)
+                } else {
+                    Instruction::SkipValNotEq(0xF, carry)
+                }
+            },
+        };
+        self.emit(instr)
+    }
+
+    fn define_macro(&mut self) -> Result<(), AsmError> {
+        let name = self.expect_token()?;
+        let mut args = Vec::new();
+
+        loop {
+            let token = self.expect_token()?;
+            if token.text == "{" {
+                break;
+            }
+            args.push(token.text);
+        }
+        let mut body = Vec::new();
+        let mut depth = 0;
+        loop {
+            let token = self.expect_token()?;
+            match token.text.as_str() {
+                "{" => depth += 1,
+                "}" if depth == 0 => break,
+                "}" => depth -= 1,
+                _ => {},
+            }
+            body.push(token);
+        }
+        self.macros.insert(name.text, Macro { args, body });
+        Ok(())
+    }
+    fn expand_macro(&mut self, name: Token) -> Result<(), AsmError> {
+        self.expansions += 1;
+        if self.expansions > MAX_EXPANSIONS {
+            return Err(name.error("too many macro expansions".to_string()));
+        }
+        let count = self.macros[&name.text].args.len();
+        let mut values = HashMap::new();
+        for idx in 0..count {
+            let value = self.expect_token()?;
+            values.insert(self.macros[&name.text].args[idx].clone(), value.text);
+        }
+        for token in self.macros[&name.text].body.iter().rev() {
+            let mut token = token.clone();
+            if let Some(value) = values.get(&token.text) {
+                token.text = value.clone();
+            }
+            self.tokens.push(token);
+        }
+        Ok(())
+    }
+
+    /// Evaluate a `:calc` expression, binary operators are right associative
+    /// and all have the same precedence
+    fn calc(&mut self) -> Result<f64, AsmError> {
+        let lhs = self.calc_term()?;
+        let op = match self.peek() {
+            Some(op) if is_binary(op) => self.expect_token()?,
+            _ => return Ok(lhs),
+        };
+        let rhs = self.calc()?;
+        let (a, b) = (lhs as i64, rhs as i64);
+        let bool = |val: bool| if val { 1.0 } else { 0.0 };
+
+        Ok(match op.text.as_str() {
+            "+" => lhs + rhs,
+            "-" => lhs - rhs,
+            "*" => lhs * rhs,
+            "/" => lhs / rhs,
+            "%" => lhs % rhs,
+            "&" => (a & b) as f64,
+            "|" => (a | b) as f64,
+            "^" => (a ^ b) as f64,
+            "<<" => (a << b) as f64,
+            ">>" => (a >> b) as f64,
+            "pow" => lhs.powf(rhs),
+            "min" => lhs.min(rhs),
+            "max" => lhs.max(rhs),
+            "<" => bool(lhs < rhs),
+            ">" => bool(lhs > rhs),
+            "<=" => bool(lhs <= rhs),
+            ">=" => bool(lhs >= rhs),
+            "==" => bool(lhs == rhs),
+            _ => bool(lhs != rhs),
+        })
+    }
+    fn calc_term(&mut self) -> Result<f64, AsmError> {
+        let token = self.expect_token()?;
+
+        Ok(match token.text.as_str() {
+            "(" => {
+                let val = self.calc()?;
+                self.expect(")")?;
+                val
+            },
+            "-" => -self.calc_term()?,
+            "~" => !(self.calc_term()? as i64) as f64,
+            "!" => if self.calc_term()? == 0.0 { 1.0 } else { 0.0 },
+            "abs" => self.calc_term()?.abs(),
+            "sqrt" => self.calc_term()?.sqrt(),
+            "floor" => self.calc_term()?.floor(),
+            "ceil" => self.calc_term()?.ceil(),
+            "HERE" => self.here as f64,
+            _ => match self.consts.get(&token.text) {
+                Some(val) => *val,
+                None => self.value(&token)? as f64,
+            },
+        })
+    }
+}
+
+fn is_binary(op: &str) -> bool {
+    ["+", "-", "*", "/", "%", "&", "|", "^", "<<", ">>", "pow", "min", "max",
+     "<", ">", "<=", ">=", "==", "!="].contains(&op)
+}
+
+fn compile_named(file: &str, source: &str) -> Result<Program, AsmError> {
+    Compiler::new(tokenize(source)).run().map_err(|mut err| {
+        err.file = file.to_string();
+        err
+    })
+}
+
+/// Compile Octo source text
+pub fn compile(source: &str) -> Result<Program, AsmError> {
+    compile_named("<input>", source)
+}
+
+/// Compile an Octo source file
+pub fn compile_file(path: &Path) -> Result<Program, AsmError> {
+    let name = path.display().to_string();
+    let source = fs::read_to_string(path)
+        .map_err(|err| AsmError { file: name.clone(), line: 0, column: 0, message: err.to_string() })?;
+
+    compile_named(&name, &source)
+}
+
+#[cfg(test)]
+mod test_octo {
+    use super::*;
+
+    fn error(source: &str) -> (usize, usize, String) {
+        let err = compile(source).unwrap_err();
+        (err.line, err.column, err.message)
+    }
+
+    #[test]
+    fn test_statements() {
+        let program = compile("
+            : main
+                v0 := 5 v1 := v0 v2 := random 0x0F
+                v1 += 1 v1 -= 1 v1 += v2 v1 -= v2 v1 =- v2
+                v1 |= v2 v1 &= v2 v1 ^= v2 v1 >>= v2 v1 <<= v1
+                v3 := delay v3 := key delay := v3 buzzer := v3
+                i := 0x300 i += v3 i := hex v3 i := bighex v3
+                bcd v3 save v3 load v3 save v1 - v2 load v2 - v1
+                sprite v0 v1 5 clear hires lores scroll-down 2 scroll-left exit
+                jump0 0x300 native 0x100
+                plane 3 audio pitch := v1 saveflags v4 loadflags v4
+                return
+        ").unwrap();
+
+        assert_eq!(program.rom, Instruction::assemble(&[
+            Instruction::LoadVal(0, 5), Instruction::Load(1, 0), Instruction::Random(2, 0x0F),
+            Instruction::AddVal(1, 1), Instruction::AddVal(1, 0xFF), Instruction::Add(1, 2),
+            Instruction::Sub(1, 2), Instruction::SubN(1, 2),
+            Instruction::Or(1, 2), Instruction::And(1, 2), Instruction::Xor(1, 2),
+            Instruction::ShiftRight(1, 2), Instruction::ShiftLeft(1, 1),
+            Instruction::LoadDelayTimer(3), Instruction::LoadKey(3), Instruction::SetDelayTimer(3),
+            Instruction::SetSoundTimer(3),
+            Instruction::LoadAddr(0x300), Instruction::AddI(3), Instruction::LoadSprite(3),
+            Instruction::LoadBigSprite(3),
+            Instruction::Bcd(3), Instruction::StoreRegs(3), Instruction::LoadRegs(3),
+            Instruction::SaveRange(1, 2), Instruction::LoadRange(2, 1),
+            Instruction::Draw(0, 1, 5), Instruction::Cls, Instruction::HighRes, Instruction::LowRes,
+            Instruction::ScrollDown(2), Instruction::ScrollLeft, Instruction::Exit,
+            Instruction::JumpRel(0x300), Instruction::Sys(0x100),
+            Instruction::SelectPlanes(3), Instruction::LoadAudio, Instruction::SetPitch(1),
+            Instruction::StoreFlags(4), Instruction::LoadFlags(4),
+            Instruction::Ret,
+        ]));
+        assert_eq!(program.symbols.get("main"), Some(&0x200));
+    }
+
+    #[test]
+    fn test_control_flow() {
+        let program = compile("
+            : main
+                loop
+                    v0 += 1
+                    while v0 != 10
+                    if v1 key then v2 := 1
+                    if v0 == v1 begin
+                        v3 := 1
+                    else
+                        v3 := 2
+                    end
+                again
+                if v0 < 3 then exit
+        ").unwrap();
+
+        assert_eq!(program.rom, Instruction::assemble(&[
+            Instruction::AddVal(0, 1),              // 0x200
+            Instruction::SkipValNotEq(0, 10),
+            Instruction::Jump(0x216),
+            Instruction::SkipKeyNotPressed(1),
+            Instruction::LoadVal(2, 1),
+            Instruction::SkipEq(0, 1),              // 0x20A
+            Instruction::Jump(0x212),
+            Instruction::LoadVal(3, 1),
+            Instruction::Jump(0x214),
+            Instruction::LoadVal(3, 2),             // 0x212
+            Instruction::Jump(0x200),               // 0x214
+            Instruction::LoadVal(0xF, 3),           // 0x216
+            Instruction::SubN(0xF, 0),
+            Instruction::SkipValNotEq(0xF, 0),
+            Instruction::Exit,
+        ]));
+
+        let program = compile(": main loop v0 += 1 while v0 < 10 again").unwrap();
+        assert_eq!(program.rom, Instruction::assemble(&[
+            Instruction::AddVal(0, 1),
+            Instruction::LoadVal(0xF, 10),
+            Instruction::SubN(0xF, 0),
+            Instruction::SkipValEq(0xF, 0),
+            Instruction::Jump(0x20C),
+            Instruction::Jump(0x200),
+        ]));
+    }
+
+    #[test]
+    fn test_definitions() {
+        let program = compile("
+            :alias x v4
+            :const SIZE 3
+            :calc TWICE { SIZE * 2 + 1 }
+            :macro plot X Y { x := X v5 := Y sprite x v5 SIZE }
+            : sprite_data 0b11100000 0xA0 TWICE
+            : main
+                i := sprite_data
+                plot 1 2
+                :next target v6 := 0
+                i := long target
+                draw
+                jump main
+            : draw
+                return
+        ").unwrap();
+
+        // calc evaluates right to left, TWICE is 3 * (2 + 1)
+        let mut rom = vec![0x12, 0x05, 0xE0, 0xA0, 0x09];
+        rom.extend(Instruction::assemble(&[
+            Instruction::LoadAddr(0x202),           // 0x205
+            Instruction::LoadVal(4, 1),
+            Instruction::LoadVal(5, 2),
+            Instruction::Draw(4, 5, 3),
+            Instruction::LoadVal(6, 0),             // 0x20D
+            Instruction::LoadLongAddr,
+        ]));
+        rom.extend_from_slice(&[0x02, 0x0E]);
+        rom.extend(Instruction::assemble(&[
+            Instruction::Call(0x217),
+            Instruction::Jump(0x205),
+            Instruction::Ret,
+        ]));
+        assert_eq!(program.rom, rom);
+        assert_eq!(program.symbols.get("sprite_data"), Some(&0x202));
+        assert_eq!(program.symbols.get("target"), Some(&0x20E));
+        assert_eq!(program.symbols.get("draw"), Some(&0x217));
+    }
+
+    #[test]
+    fn test_errors() {
+        assert_eq!(error(": main\n  jump nowhere"), (2, 8, "undefined label `nowhere`".to_string()));
+        assert_eq!(error(": start\n  exit"), (1, 1, "the program has no `main` label".to_string()));
+        assert_eq!(error(": main\n  loop v0 += 1"), (2, 14, "`loop` is never closed".to_string()));
+        assert_eq!(error(": main v0 := 256"), (1, 14, "value 256 doesn't fit in 8 bits".to_string()));
+        assert_eq!(error(": main if v0 = 1 then exit"), (1, 14, "unknown comparison `=`".to_string()));
+        assert_eq!(error(": main : main"), (1, 10, "`main` is already defined".to_string()));
+        assert_eq!(error(": main :unpack 1 x"), (1, 8, "unsupported directive `:unpack`".to_string()));
+    }
+}
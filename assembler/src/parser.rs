@@ -0,0 +1,316 @@
+use emulator::instruction::Register;
+use nom::branch::alt;
+use nom::bytes::complete::{tag_no_case, take_till, take_while1};
+use nom::character::complete::{char, digit1, hex_digit1, one_of, satisfy, space0, space1};
+use nom::combinator::{map, map_res, not, opt, peek, recognize, value};
+use nom::multi::{many0, separated_list1};
+use nom::sequence::{delimited, pair, preceded, terminated, tuple};
+use nom::IResult;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Term {
+    Number(u32),
+    Symbol(String),
+}
+
+/// Term of a sum, `column` is where it starts on the line
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Signed {
+    pub negative: bool,
+    pub term: Term,
+    pub column: usize,
+}
+
+/// Sum of numbers and symbols, like `sprites + 5`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Expr {
+    pub terms: Vec<Signed>,
+}
+
+impl Expr {
+    pub fn column(&self) -> usize {
+        self.terms[0].column
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Operand {
+    Register(Register),
+    /* XO-CHIP Vx-Vy */
+    Range(Register, Register),
+    I,
+    IndirectI,
+    DelayTimer,
+    SoundTimer,
+    Key,
+    Font,
+    BigFont,
+    Bcd,
+    Flags,
+    /* XO-CHIP LONG addr, 16-bit address */
+    Long(Expr),
+    Value(Expr),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Statement {
+    Instruction { mnemonic: String, operands: Vec<Operand>, columns: Vec<usize> },
+    Bytes(Vec<Expr>),
+    Words(Vec<Expr>),
+    Include(String),
+    Constant(String, Expr),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Line {
+    /* label and the column it starts at */
+    pub label: Option<(String, usize)>,
+    /* statement and the column it starts at */
+    pub statement: Option<(Statement, usize)>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseError {
+    pub column: usize,
+    pub message: String,
+}
+
+/// Column of `rest` inside `line`, starting at 1
+fn column(line: &str, rest: &str) -> usize {
+    rest.as_ptr() as usize - line.as_ptr() as usize + 1
+}
+
+fn ident_char(input: &str) -> IResult<&str, char> {
+    satisfy(|c| c.is_ascii_alphanumeric() || c == '_')(input)
+}
+
+fn identifier(input: &str) -> IResult<&str, &str> {
+    recognize(pair(
+        satisfy(|c| c.is_ascii_alphabetic() || c == '_'),
+        many0(ident_char),
+    ))(input)
+}
+
+/// Case insensitive word, not followed by more identifier characters
+fn keyword<'a>(word: &'static str) -> impl FnMut(&'a str) -> IResult<&'a str, &'a str> {
+    terminated(tag_no_case(word), not(peek(ident_char)))
+}
+
+fn number(input: &str) -> IResult<&str, u32> {
+    let binary = |input| take_while1(|c| c == '0' || c == '1')(input);
+
+    alt((
+        map_res(preceded(tag_no_case("0x"), hex_digit1), |s| u32::from_str_radix(s, 16)),
+        map_res(preceded(char('$'), hex_digit1), |s| u32::from_str_radix(s, 16)),
+        map_res(preceded(tag_no_case("0b"), binary), |s| u32::from_str_radix(s, 2)),
+        map_res(preceded(char('%'), binary), |s| u32::from_str_radix(s, 2)),
+        map_res(digit1, |s: &str| s.parse::<u32>()),
+    ))(input)
+}
+
+fn register(input: &str) -> IResult<&str, Register> {
+    map(
+        terminated(preceded(one_of("vV"), satisfy(|c| c.is_ascii_hexdigit())), not(peek(ident_char))),
+        |c| c.to_digit(16).unwrap() as Register,
+    )(input)
+}
+
+fn comma(input: &str) -> IResult<&str, char> {
+    delimited(space0, char(','), space0)(input)
+}
+
+fn expr<'a>(line: &'a str) -> impl FnMut(&'a str) -> IResult<&'a str, Expr> {
+    move |input| {
+        let parse_term = |input: &'a str| -> IResult<&'a str, (Term, usize)> {
+            let col = column(line, input);
+            let (rest, term) = alt((
+                map(number, Term::Number),
+                map(identifier, |s| Term::Symbol(s.to_string())),
+            ))(input)?;
+            Ok((rest, (term, col)))
+        };
+        let (rest, negative) = opt(terminated(char('-'), space0))(input)?;
+        let (mut rest, (term, col)) = parse_term(rest)?;
+        let mut terms = vec![Signed { negative: negative.is_some(), term, column: col }];
+
+        while let Ok((after, sign)) = delimited(space0, one_of::<_, _, nom::error::Error<&str>>("+-"), space0)(rest) {
+            let (after, (term, col)) = parse_term(after)?;
+            terms.push(Signed { negative: sign == '-', term, column: col });
+            rest = after;
+        }
+        Ok((rest, Expr { terms }))
+    }
+}
+
+fn operand<'a>(line: &'a str) -> impl FnMut(&'a str) -> IResult<&'a str, Operand> {
+    move |input| {
+        let range = preceded(delimited(space0, char('-'), space0), register);
+
+        alt((
+            value(Operand::IndirectI, tuple((char('['), space0, tag_no_case("I"), space0, char(']')))),
+            map(pair(register, opt(range)), |(x, y)| match y {
+                Some(y) => Operand::Range(x, y),
+                None => Operand::Register(x),
+            }),
+            map(preceded(pair(keyword("LONG"), space1), expr(line)), Operand::Long),
+            value(Operand::I, keyword("I")),
+            value(Operand::DelayTimer, keyword("DT")),
+            value(Operand::SoundTimer, keyword("ST")),
+            value(Operand::Key, keyword("K")),
+            value(Operand::BigFont, keyword("HF")),
+            value(Operand::Font, keyword("F")),
+            value(Operand::Bcd, keyword("B")),
+            value(Operand::Flags, keyword("R")),
+            map(expr(line), Operand::Value),
+        ))(input)
+    }
+}
+
+fn statement<'a>(line: &'a str, input: &'a str) -> IResult<&'a str, Statement> {
+    let list = |input: &'a str| separated_list1(comma, expr(line))(input);
+
+    if let Ok((rest, exprs)) = preceded(pair(keyword("DB"), space1), list)(input) {
+        return Ok((rest, Statement::Bytes(exprs)));
+    }
+    if let Ok((rest, exprs)) = preceded(pair(keyword("DW"), space1), list)(input) {
+        return Ok((rest, Statement::Words(exprs)));
+    }
+    if let Ok((rest, path)) = preceded(
+        pair(keyword("INCLUDE"), space1),
+        delimited(char('"'), take_till(|c| c == '"'), char('"')),
+    )(input) {
+        return Ok((rest, Statement::Include(path.to_string())));
+    }
+
+    let (mut rest, mnemonic) = identifier(input)?;
+    let mut operands = Vec::new();
+    let mut columns = Vec::new();
+
+    if let Ok((start, _)) = space1::<_, nom::error::Error<&str>>(rest) {
+        if !start.is_empty() {
+            columns.push(column(line, start));
+            let (after, first) = operand(line)(start)?;
+            operands.push(first);
+            rest = after;
+            while let Ok((after, _)) = comma(rest) {
+                columns.push(column(line, after));
+                let (after, next) = operand(line)(after)?;
+                operands.push(next);
+                rest = after;
+            }
+        }
+    }
+    Ok((rest, Statement::Instruction { mnemonic: mnemonic.to_uppercase(), operands, columns }))
+}
+
+/// Parse a single source line:
+/// `[label:] [statement] [; comment]` or `NAME EQU value`
+pub fn parse_line(line: &str) -> Result<Line, ParseError> {
+    let error = |rest: &str, message: &str| ParseError { column: column(line, rest), message: message.to_string() };
+    let code = match line.find(';') {
+        Some(end) => &line[..end],
+        None => line,
+    };
+    let mut rest = code.trim_start();
+    let mut parsed = Line::default();
+
+    if let Ok((after, name)) = identifier(rest) {
+        if let Ok((after, _)) = preceded(space0, char::<_, nom::error::Error<&str>>(':'))(after) {
+            parsed.label = Some((name.to_string(), column(line, rest)));
+            rest = after.trim_start();
+        } else if let Ok((after, _)) = preceded(space1, keyword("EQU"))(after) {
+            let start = column(line, rest);
+            let after = after.trim_start();
+            let (after, value) = expr(line)(after).map_err(|_| error(after, "expected a value"))?;
+            if !after.trim().is_empty() {
+                return Err(error(after.trim_start(), "unexpected characters after constant"));
+            }
+            parsed.statement = Some((Statement::Constant(name.to_string(), value), start));
+            return Ok(parsed);
+        }
+    }
+    if rest.trim().is_empty() {
+        return Ok(parsed);
+    }
+
+    let start = column(line, rest);
+    match statement(line, rest) {
+        Ok((after, statement)) => {
+            let after = after.trim_start();
+            if !after.is_empty() {
+                return Err(error(after, &format!("unexpected `{}`", after.trim_end())));
+            }
+            parsed.statement = Some((statement, start));
+            Ok(parsed)
+        },
+        Err(nom::Err::Error(err)) | Err(nom::Err::Failure(err)) if err.input.is_empty() =>
+            Err(error(err.input, "unexpected end of line")),
+        Err(nom::Err::Error(err)) | Err(nom::Err::Failure(err)) =>
+            Err(error(err.input, "syntax error")),
+        Err(nom::Err::Incomplete(_)) => Err(error(rest, "unexpected end of line")),
+    }
+}
+
+#[cfg(test)]
+mod test_parser {
+    use super::*;
+
+    fn number_expr(val: u32, column: usize) -> Expr {
+        Expr { terms: vec![Signed { negative: false, term: Term::Number(val), column }] }
+    }
+
+    #[test]
+    fn test_numbers() {
+        for (text, val) in &[("0x1F", 0x1F), ("$1f", 0x1F), ("0b101", 5), ("%11", 3), ("42", 42)] {
+            assert_eq!(number(text), Ok(("", *val)));
+        }
+    }
+
+    #[test]
+    fn test_instruction() {
+        let line = parse_line("loop: LD V3, 0x0C ; comment").unwrap();
+        assert_eq!(line.label, Some(("loop".to_string(), 1)));
+        assert_eq!(line.statement, Some((Statement::Instruction {
+            mnemonic: "LD".to_string(),
+            operands: vec![Operand::Register(3), Operand::Value(number_expr(0x0C, 14))],
+            columns: vec![10, 14],
+        }, 7)));
+
+        let line = parse_line("  ld [i], vF").unwrap();
+        assert_eq!(line.statement, Some((Statement::Instruction {
+            mnemonic: "LD".to_string(),
+            operands: vec![Operand::IndirectI, Operand::Register(0xF)],
+            columns: vec![6, 11],
+        }, 3)));
+
+        let line = parse_line("SAVE V1-V4").unwrap();
+        match line.statement {
+            Some((Statement::Instruction { operands, .. }, _)) => assert_eq!(operands, vec![Operand::Range(1, 4)]),
+            other => panic!("unexpected statement {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_directives() {
+        let line = parse_line("sprite: DB 0xF0, %1001").unwrap();
+        assert_eq!(line.statement, Some((Statement::Bytes(vec![number_expr(0xF0, 12), number_expr(9, 18)]), 9)));
+
+        let line = parse_line("SPEED EQU start - 2").unwrap();
+        assert_eq!(line.statement, Some((Statement::Constant("SPEED".to_string(), Expr { terms: vec![
+            Signed { negative: false, term: Term::Symbol("start".to_string()), column: 11 },
+            Signed { negative: true, term: Term::Number(2), column: 19 },
+        ]}), 1)));
+
+        let line = parse_line("include \"font.asm\"").unwrap();
+        assert_eq!(line.statement, Some((Statement::Include("font.asm".to_string()), 1)));
+
+        assert_eq!(parse_line("   ; nothing"), Ok(Line::default()));
+    }
+
+    #[test]
+    fn test_errors() {
+        assert_eq!(parse_line("LD V1, 2 3"), Err(ParseError { column: 10, message: "unexpected `3`".to_string() }));
+        assert_eq!(parse_line("DB 1,").map_err(|err| err.column), Err(5));
+        assert_eq!(parse_line("X EQU").map_err(|err| err.column), Err(6));
+    }
+}
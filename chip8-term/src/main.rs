@@ -1,35 +1,36 @@
 mod terminal;
 
-use emulator::Emulator;
-use emulator::DisplaySize;
+use emulator::options;
 use emulator::ui::Screen;
 use terminal::Terminal;
 
 use std::env;
 use std::path::Path;
-use std::{io::Read, fs::File};
+use std::fs;
+use std::time::{SystemTime, UNIX_EPOCH};
 
 fn main() {
-    let mut emu : Emulator = Emulator::new(DisplaySize::Basic64x32);
+    let seed = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |time| time.as_nanos() as u64);
     let args: Vec<String> = env::args().collect();
 
-    match args.len() {
-        1 => panic!("pass an argument"),
-        2 => {
-            let mut rom = [0; 4096 - 0x200];
-            let path = Path::new(&args[1]);
-            let mut file = match File::open(&path) {
-                Ok(file) => file,
-                Err(reason) => panic!("failed to open file: {}", reason),
-            };
-            match file.read(&mut rom) {
-                Ok(_) => {
-                    emu.mem_load_bin(rom.to_vec());
-                },
-                Err(reason) => panic!("failed to read file: {}", reason)
-            }
-        },
-        _ => panic!("too many arguments"),
+    if args.len() < 2 {
+        panic!("pass an argument");
+    }
+    let rom = match fs::read(Path::new(&args[1])) {
+        Ok(rom) => rom,
+        Err(reason) => panic!("failed to read file: {}", reason),
+    };
+    let (builder, info) = match options::configure(&args[2..], &rom) {
+        Ok(config) => config,
+        Err(reason) => panic!("{}", reason),
+    };
+    let emu = match builder.seed(seed).build() {
+        Ok(emu) => emu,
+        Err(reason) => panic!("invalid configuration: {}", reason),
+    };
+    if let Some(info) = &info {
+        // terminal title
+        print!("\x1B]0;{}\x07", info.title);
     }
     let ui = Terminal::new(emu);
     ui.run();
@@ -1,5 +1,13 @@
 use emulator::ui::Screen;
 use emulator::Emulator;
+use std::io::{self, Write};
+use std::thread;
+use std::time::{Duration, Instant};
+
+// character used for each plane combination
+const PIXELS: [&str; 4] = [" ", "*", "+", "#"];
+
+const FRAME: Duration = Duration::from_nanos(1_000_000_000 / 60);
 
 pub struct Terminal {
     emu: Emulator,
@@ -9,30 +17,60 @@ impl Screen for Terminal {
         Terminal { emu }
     }
     fn run(mut self) {
-        loop {
-            self.emu.cpu_one_cycle();
-            if self.emu.redraw {
-                print!("\x1B[{};{}H", 1, 1);
+        let mut next_frame = Instant::now();
+        let mut size = (0, 0);
+
+        while !self.emu.exited {
+            let frame = match self.emu.run_frame() {
+                Ok(frame) => frame,
+                Err(err) => {
+                    println!("halted: {}", err);
+                    break;
+                },
+            };
+            if self.emu.resolution != size {
+                // new screen size, draw everything with the border
+                size = self.emu.resolution;
+                print!("\x1B[2J\x1B[{};{}H", 1, 1);
                 print!("\u{250C}");
-                for _ in 0..self.emu.resolution.0 {
+                for _ in 0..size.0 {
                     print!("\u{2500}");
                 }
                 println!("\u{2510}");
 
 
-                for y in 0..self.emu.resolution.1 {
+                for y in 0..size.1 {
                     print!("\u{2502}");
-                    for x in 0..self.emu.resolution.0 {
-                        print!("{}", if self.emu.screen[x][y] { "*" } else { " " });
+                    for x in 0..size.0 {
+                        print!("{}", PIXELS[self.emu.screen.get(x, y) as usize]);
                     }
                     println!("\u{2502}");
                 }
 
                 print!("\u{2514}");
-                for _ in 0..self.emu.resolution.0 {
+                for _ in 0..size.0 {
                     print!("\u{2500}");
                 }
                 println!("\u{2518}");
+                self.emu.screen.clear_dirty();
+            }
+            if frame.redraw && self.emu.screen.is_dirty() {
+                // only rewrite the rows that changed, inside the border
+                for rect in self.emu.screen.dirty_rects() {
+                    for y in rect.y..rect.y + rect.height {
+                        print!("\x1B[{};{}H", y + 2, rect.x + 2);
+                        for x in rect.x..rect.x + rect.width {
+                            print!("{}", PIXELS[self.emu.screen.get(x, y) as usize]);
+                        }
+                    }
+                }
+                print!("\x1B[{};{}H", size.1 + 3, 1);
+                let _ = io::stdout().flush();
+                self.emu.screen.clear_dirty();
+            }
+            next_frame += FRAME;
+            if let Some(delay) = next_frame.checked_duration_since(Instant::now()) {
+                thread::sleep(delay);
             }
         }
     }
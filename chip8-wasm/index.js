@@ -12,6 +12,21 @@ $(function() {
     $("#reset").click(function() {
       stop = true;
     });
+    $("#save-state").click(function() {
+      if (emu) {
+        emu.save_state(parseInt($("#slot").val()));
+      }
+    });
+    $("#load-state").click(function() {
+      if (emu) {
+        try {
+          emu.load_state(parseInt($("#slot").val()));
+          $("#status").text("");
+        } catch (err) {
+          $("#status").text("load failed: " + err);
+        }
+      }
+    });
     $("#rom-file").change(function() {
       if (this.files) {
         this.files[0].arrayBuffer().then(function(data) {
@@ -36,8 +51,14 @@ $(function() {
             emu.reload(data);
           }
           stop = false;
+          $("#status").text("");
           function render() {
-            emu.run();
+            try {
+              emu.run();
+            } catch (err) {
+              $("#status").text("halted: " + err);
+              stop = true;
+            }
             if (!stop) {
               requestAnimationFrame(render);
             }
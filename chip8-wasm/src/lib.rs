@@ -3,6 +3,7 @@ use wasm_bindgen::{JsCast, JsValue};
 use web_sys::{
     WebGlShader,
     WebGlProgram,
+    WebGlTexture,
 };
 use web_sys::WebGlRenderingContext;
 use js_sys::{
@@ -10,41 +11,101 @@ use js_sys::{
 };
 
 use emulator::Emulator;
+use emulator::framebuffer::Rect;
 use emulator::DisplaySize;
-use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use emulator::Mode;
+use emulator::preset::Preset;
+use emulator::profile::Profile;
+use emulator::quirks::Quirks;
+use emulator::romdb::{RomDatabase, RomInfo};
+use std::cell::RefCell;
 mod shaders;
 
+// RGB colour of each plane combination
+const PALETTE: [[u8; 4]; 4] = [
+    [0x00, 0x00, 0x00, 0xFF],
+    [0xFF, 0xFF, 0xFF, 0xFF],
+    [0x99, 0x99, 0x99, 0xFF],
+    [0x4C, 0x4C, 0x4C, 0xFF],
+];
+
 #[wasm_bindgen]
 pub struct UICanvas {
     emu: Emulator,
     context: WebGlRenderingContext,
+    texture: WebGlTexture,
+    /* RGBA pixels uploaded to the texture */
+    pixels: Vec<u8>,
+    slots: Vec<Option<Vec<u8>>>,
+    info: Option<RomInfo>,
+    palette: [[u8; 4]; 4],
 }
 
-fn perf_to_system(amt: f64) -> SystemTime {
-    let secs = (amt as u64) / 1_000;
-    let nanos = ((amt as u32) % 1_000) * 1_000_000;
-    UNIX_EPOCH + Duration::new(secs, nanos)
+thread_local! {
+    /* identifies the ROMs started afterwards */
+    static DATABASE: RefCell<Option<RomDatabase>> = const { RefCell::new(None) };
+}
+
+/// Use the contents of the chip-8-database programs.json and
+/// sha1-hashes.json to identify ROMs
+#[wasm_bindgen]
+pub fn load_database(programs: &str, hashes: &str) -> Result<(), JsValue> {
+    let database = RomDatabase::from_json(programs, hashes)
+        .map_err(|err| JsValue::from_str(&err.to_string()))?;
+
+    DATABASE.with(|db| *db.borrow_mut() = Some(database));
+    Ok(())
+}
+
+fn lookup(rom: &[u8]) -> Option<RomInfo> {
+    DATABASE.with(|db| db.borrow().as_ref().and_then(|database| database.lookup(rom)))
 }
 
 #[wasm_bindgen]
 pub fn start(data: &JsValue) -> UICanvas {
+    start_emulator(data, |_| {})
+}
+
+/// Same as `start` with a profile name: chip8, hires or eti660
+#[wasm_bindgen]
+pub fn start_with_profile(data: &JsValue, name: &str) -> Result<UICanvas, JsValue> {
+    match Profile::from_name(name) {
+        Some(profile) => Ok(start_emulator(data, |emu| {
+            emu.set_profile(profile).expect("built-in profiles fit in memory")
+        })),
+        None => Err(JsValue::from_str(&format!("unknown profile {}", name))),
+    }
+}
+
+/// Same as `start` with a preset name: vip, chip48, schip10, schip11, schip,
+/// xochip or eti660
+#[wasm_bindgen]
+pub fn start_with_preset(data: &JsValue, name: &str) -> Result<UICanvas, JsValue> {
+    match Preset::from_name(name) {
+        Some(preset) => Ok(start_emulator(data, |emu| emu.apply_preset(preset))),
+        None => Err(JsValue::from_str(&format!("unknown preset {}", name))),
+    }
+}
+
+fn start_emulator(data: &JsValue, configure: impl FnOnce(&mut Emulator)) -> UICanvas {
     console_error_panic_hook::set_once();
 
-    let data = Uint8Array::new(&data);
+    let data = Uint8Array::new(data);
     let mut rom_bin: Vec<u8> = vec![0; data.length() as usize];
     data.copy_to(&mut rom_bin);
 
-    let window = web_sys::window().expect("global window does not exists");
-
-    let performance = window
-        .performance()
-        .expect("performance should be available");
-    let mut emu = Emulator::new_with_time(DisplaySize::Basic64x32,
-        perf_to_system(performance.now()));
-    emu.mem_load_bin(rom_bin);
-    let renderer = UICanvas::new(emu);
-
-    renderer
+    let seed = js_sys::Date::now() as u64;
+    let mut emu = Emulator::new(DisplaySize::Basic64x32, Quirks::default(), seed);
+    emu.set_mode(Mode::detect(&rom_bin));
+    let info = lookup(&rom_bin);
+    if let Some(info) = &info {
+        emu.apply_rom_info(info);
+    }
+    configure(&mut emu);
+    emu.load_rom(&rom_bin);
+    let mut ui = UICanvas::new(emu);
+    ui.set_rom_info(info);
+    ui
 }
 
 fn compile_shader(gl: &WebGlRenderingContext, shader_type: u32, source: &str) -> Result<WebGlShader, String> {
@@ -78,6 +139,8 @@ fn link_program(
 
     context.attach_shader(&program, vert_shader);
     context.attach_shader(&program, frag_shader);
+    context.bind_attrib_location(&program, 0, "aPosition");
+    context.bind_attrib_location(&program, 1, "aTexCoord");
     context.link_program(&program);
 
     if context
@@ -135,79 +198,140 @@ impl UICanvas {
         let program = link_program(&context, &vertex_shader, &frag_shader).unwrap();
         context.use_program(Some(&program));
 
+        let texture = context.create_texture().expect("failed to create texture");
+        context.bind_texture(WebGlRenderingContext::TEXTURE_2D, Some(&texture));
+        // screen sizes aren't all powers of two
+        for (param, val) in [
+            (WebGlRenderingContext::TEXTURE_MIN_FILTER, WebGlRenderingContext::NEAREST),
+            (WebGlRenderingContext::TEXTURE_MAG_FILTER, WebGlRenderingContext::NEAREST),
+            (WebGlRenderingContext::TEXTURE_WRAP_S, WebGlRenderingContext::CLAMP_TO_EDGE),
+            (WebGlRenderingContext::TEXTURE_WRAP_T, WebGlRenderingContext::CLAMP_TO_EDGE),
+        ].iter() {
+            context.tex_parameteri(WebGlRenderingContext::TEXTURE_2D, *param, *val as i32);
+        }
+
         UICanvas {
             emu,
             context,
+            texture,
+            pixels: Vec::new(),
+            slots: vec![None; 4],
+            info: None,
+            palette: PALETTE,
+        }
+    }
+    fn set_rom_info(&mut self, info: Option<RomInfo>) {
+        self.palette = PALETTE;
+        if let Some(info) = &info {
+            for (color, rgb) in self.palette.iter_mut().zip(&info.colors) {
+                color[..3].copy_from_slice(rgb);
+            }
         }
+        self.info = info;
+        // repaint with the new colours
+        let (width, height) = self.emu.resolution;
+        self.emu.screen.mark_dirty(Rect { x: 0, y: 0, width, height });
+    }
+    /// Title of the ROM if it is in the database
+    pub fn title(&self) -> Option<String> {
+        self.info.as_ref().map(|info| info.title.clone())
+    }
+    pub fn authors(&self) -> Option<String> {
+        self.info.as_ref().map(|info| info.authors.join(", "))
+    }
+    pub fn description(&self) -> Option<String> {
+        self.info.as_ref().and_then(|info| info.description.clone())
+    }
+    pub fn save_state(&mut self, slot: usize) -> Result<(), JsValue> {
+        let state = self.emu.save_state();
+
+        *self.slots.get_mut(slot).ok_or("invalid slot")? = Some(state);
+        Ok(())
+    }
+    pub fn load_state(&mut self, slot: usize) -> Result<(), JsValue> {
+        let state = self.slots.get(slot).ok_or("invalid slot")?
+            .as_ref().ok_or("empty slot")?;
+
+        self.emu.load_state(state).map_err(|err| JsValue::from_str(&err.to_string()))
     }
     pub fn reload(&mut self, data: &JsValue) {
-        let data = Uint8Array::new(&data);
+        let data = Uint8Array::new(data);
         let mut rom_bin: Vec<u8> = vec![0; data.length() as usize];
         data.copy_to(&mut rom_bin);
+        // XO-CHIP was picked by a preset, it can't be detected
+        if self.emu.mode() != Mode::XoChip {
+            self.emu.set_mode(Mode::detect(&rom_bin));
+        }
+        let info = lookup(&rom_bin);
+        if let Some(info) = &info {
+            self.emu.apply_rom_info(info);
+        }
         self.emu.reset();
-        self.emu.mem_load_bin(rom_bin);
+        self.emu.load_rom(&rom_bin);
+        self.set_rom_info(info);
     }
-    pub fn run(&mut self) {
-        let window = web_sys::window().expect("global window does not exists");
-
-        let performance = window
-            .performance()
-            .expect("performance should be available");
-
-        for _ in 0..10 {
-            self.emu.cpu_one_cycle_with_time(perf_to_system(performance.now()));
+    pub fn run(&mut self) -> Result<(), JsValue> {
+        if self.emu.exited {
+            return Ok(());
         }
-        if self.emu.redraw {
-            self.emu.redraw = false;
-            let buffer = self.context.create_buffer().ok_or("failed to create buffer").unwrap();
-            self.context.bind_buffer(WebGlRenderingContext::ARRAY_BUFFER, Some(&buffer));
-
-            let mut vertices: Vec<f32> = Vec::new();
-            for x in 0..self.emu.resolution.0 {
-                let x_f = x as f32 / 64.0 * 2.0 - 1.0;
-                let xnext_f = (x as f32 + 1.0) / 64.0 * 2.0 - 1.0;
-
-                for y in 0..self.emu.resolution.1 {
-                    if self.emu.screen[x][y] {
-                        let y_f = (64.0 - y as f32) / 64.0 * 2.0 - 1.0;
-                        let ynext_f = (64.0 - (y as f32 + 1.0)) / 64.0 *2.0 - 1.0;
-
-                        let mut rect = vec![
-                            x_f, y_f, 0.0,
-                            xnext_f, y_f, 0.0,
-                            xnext_f, ynext_f, 0.0,
-
-                            xnext_f, ynext_f, 0.0,
-                            x_f, ynext_f, 0.0,
-                            x_f, y_f, 0.0,
-                        ];
-
-                        vertices.append(&mut rect);
-                    }
-                }
-            }
+        // called on each animation frame
+        let frame = self.emu.run_frame()
+            .map_err(|err| JsValue::from_str(&err.to_string()))?;
+        if frame.redraw && self.emu.screen.is_dirty() {
+            self.draw()?;
+        }
+        Ok(())
+    }
+    fn draw(&mut self) -> Result<(), JsValue> {
+        let (width, height) = self.emu.resolution;
+        self.pixels.resize(width * height * 4, 0);
+        self.emu.screen.to_rgba(&self.palette, &mut self.pixels);
+        self.emu.screen.clear_dirty();
 
-            unsafe {
-                let vert_array = js_sys::Float32Array::view(&vertices);
-        
-                self.context.buffer_data_with_array_buffer_view(
-                    WebGlRenderingContext::ARRAY_BUFFER,
-                    &vert_array,
-                    WebGlRenderingContext::STATIC_DRAW,
-                );
-            }
+        self.context.bind_texture(WebGlRenderingContext::TEXTURE_2D, Some(&self.texture));
+        self.context.tex_image_2d_with_i32_and_i32_and_i32_and_format_and_type_and_opt_u8_array(
+            WebGlRenderingContext::TEXTURE_2D,
+            0,
+            WebGlRenderingContext::RGBA as i32,
+            width as i32,
+            height as i32,
+            0,
+            WebGlRenderingContext::RGBA,
+            WebGlRenderingContext::UNSIGNED_BYTE,
+            Some(&self.pixels),
+        )?;
 
-            self.context.vertex_attrib_pointer_with_i32(0, 3, WebGlRenderingContext::FLOAT, false, 0, 0);
-            self.context.enable_vertex_attrib_array(0);
+        // square pixels, the screen width fills the canvas
+        let bottom = 1.0 - 2.0 * height as f32 / width as f32;
+        let vertices: [f32; 24] = [
+            -1.0, 1.0, 0.0, 0.0,
+            1.0, 1.0, 1.0, 0.0,
+            1.0, bottom, 1.0, 1.0,
 
-            self.context.clear_color(0.0, 0.0, 0.0, 1.0);
-            self.context.clear(WebGlRenderingContext::COLOR_BUFFER_BIT);
+            1.0, bottom, 1.0, 1.0,
+            -1.0, bottom, 0.0, 1.0,
+            -1.0, 1.0, 0.0, 0.0,
+        ];
+        let buffer = self.context.create_buffer().ok_or("failed to create buffer")?;
+        self.context.bind_buffer(WebGlRenderingContext::ARRAY_BUFFER, Some(&buffer));
+        unsafe {
+            let vert_array = js_sys::Float32Array::view(&vertices);
 
-            self.context.draw_arrays(
-                WebGlRenderingContext::TRIANGLES,
-                0,
-                (vertices.len() / 3) as i32,
+            self.context.buffer_data_with_array_buffer_view(
+                WebGlRenderingContext::ARRAY_BUFFER,
+                &vert_array,
+                WebGlRenderingContext::STATIC_DRAW,
             );
         }
+
+        self.context.vertex_attrib_pointer_with_i32(0, 2, WebGlRenderingContext::FLOAT, false, 16, 0);
+        self.context.enable_vertex_attrib_array(0);
+        self.context.vertex_attrib_pointer_with_i32(1, 2, WebGlRenderingContext::FLOAT, false, 16, 8);
+        self.context.enable_vertex_attrib_array(1);
+
+        self.context.clear_color(0.0, 0.0, 0.0, 1.0);
+        self.context.clear(WebGlRenderingContext::COLOR_BUFFER_BIT);
+        self.context.draw_arrays(WebGlRenderingContext::TRIANGLES, 0, 6);
+        Ok(())
     }
 }
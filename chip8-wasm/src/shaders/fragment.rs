@@ -1,8 +1,10 @@
-// all pixels are white
+// the screen is uploaded as an RGBA texture
 pub const SHADER: &str = r#"
     precision mediump float;
+    uniform sampler2D uScreen;
+    varying vec2 vTexCoord;
 
     void main() {
-        gl_FragColor = vec4(1.0, 1.0, 1.0, 1.0);
+        gl_FragColor = texture2D(uScreen, vTexCoord);
     }
 "#;
\ No newline at end of file
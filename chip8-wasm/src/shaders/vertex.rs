@@ -1,9 +1,10 @@
 pub const SHADER: &str = r#"
     attribute vec4 aPosition;
-    //uniform mat4 uTransform;
+    attribute vec2 aTexCoord;
+    varying vec2 vTexCoord;
 
     void main() {
-        //gl_Position = uTransform * aPosition;
         gl_Position = aPosition;
+        vTexCoord = aTexCoord;
     }
 "#;
\ No newline at end of file
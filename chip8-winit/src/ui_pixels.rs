@@ -5,21 +5,60 @@ use winit::event_loop::{ControlFlow, EventLoop};
 use winit::window::WindowBuilder;
 use winit_input_helper::WinitInputHelper;
 
+use emulator::rewind::Rewind;
+use emulator::romdb::RomInfo;
 use emulator::ui::Screen;
 use emulator::Emulator;
-use std::time::{Duration, SystemTime};
+use std::time::{Duration, Instant};
 
-const WIDTH: u32 = 64;
+// snapshot every 2 frames, keeping about 20s of history
+const REWIND_INTERVAL: usize = 2;
+const REWIND_CAPACITY: usize = 600;
+
+const WIDTH: u32 = 128;
 const HEIGHT: u32 = 64;
 
+const FRAME: Duration = Duration::from_nanos(1_000_000_000 / 60);
+
+// RGBA colour of each plane combination
+const PALETTE: [[u8; 4]; 4] = [
+    [0xFF, 0xFF, 0xFF, 0xFF],
+    [0x00, 0x00, 0x00, 0xFF],
+    [0x99, 0x99, 0x99, 0xFF],
+    [0x55, 0x55, 0x55, 0xFF],
+];
+
 pub struct UIPixels {
     emu: Emulator,
+    info: Option<RomInfo>,
+}
+
+impl UIPixels {
+    /// Use the title, colours and key mapping of a known ROM
+    pub fn with_rom_info(mut self, info: Option<RomInfo>) -> Self {
+        self.info = info;
+        self
+    }
+}
+
+/// Keyboard key for a database action
+fn action_key(action: &str) -> Option<VirtualKeyCode> {
+    match action {
+        "up" => Some(VirtualKeyCode::Up),
+        "down" => Some(VirtualKeyCode::Down),
+        "left" => Some(VirtualKeyCode::Left),
+        "right" => Some(VirtualKeyCode::Right),
+        "a" => Some(VirtualKeyCode::Space),
+        "b" => Some(VirtualKeyCode::Return),
+        _ => None,
+    }
 }
 
 impl Screen for UIPixels {
     fn new(emu: Emulator) -> Self {
         UIPixels {
-            emu
+            emu,
+            info: None,
         }
     }
     fn run(mut self) {
@@ -41,12 +80,35 @@ impl Screen for UIPixels {
             VirtualKeyCode::E,
             VirtualKeyCode::F,
         ];
+        // F1-F4 load a save slot, shift+F1-F4 save to it
+        let slot_keys = [
+            VirtualKeyCode::F1,
+            VirtualKeyCode::F2,
+            VirtualKeyCode::F3,
+            VirtualKeyCode::F4,
+        ];
+        let mut slots: Vec<Option<Vec<u8>>> = vec![None; slot_keys.len()];
+        // arrows, space and return as the database says
+        let mut actions: Vec<(VirtualKeyCode, usize)> = Vec::new();
+        let mut palette = PALETTE;
+        let mut title = "Hello Pixels".to_string();
+        if let Some(info) = &self.info {
+            for (action, key) in info.keys.iter() {
+                if let Some(code) = action_key(action) {
+                    actions.push((code, *key as usize & 0xF));
+                }
+            }
+            for (color, rgb) in palette.iter_mut().zip(info.colors.iter()) {
+                color[..3].copy_from_slice(rgb);
+            }
+            title = info.title.clone();
+        }
         let event_loop = EventLoop::new();
         let mut input = WinitInputHelper::new();
         let window = {
-            let size = LogicalSize::new((WIDTH * 10) as f64, (HEIGHT * 10) as f64);
+            let size = LogicalSize::new((WIDTH * 5) as f64, (HEIGHT * 5) as f64);
             WindowBuilder::new()
-                .with_title("Hello Pixels")
+                .with_title(title)
                 .with_inner_size(size)
                 .with_min_inner_size(size)
                 .build(&event_loop)
@@ -59,43 +121,68 @@ impl Screen for UIPixels {
             Pixels::new(WIDTH, HEIGHT, surface_texture).unwrap()
         };
 
-        let mut now = SystemTime::now();
+        let mut now = Instant::now();
         let mut instruction_count = 0;
         let mut draw_count = 0;
+        let mut halted = false;
+        let mut rewind = Rewind::new(REWIND_INTERVAL, REWIND_CAPACITY);
+        let mut rewinding = false;
+        let mut next_frame = Instant::now();
         event_loop.run(move |event, _, control_flow| {
-            if let Ok(val) = now.elapsed() {
-                if val > Duration::from_secs(1) {
-                    println!("{} ips, {} dps", instruction_count, draw_count);
-                    now = SystemTime::now();
-                    instruction_count = 0;
-                    draw_count = 0;
+            if now.elapsed() > Duration::from_secs(1) {
+                println!("{} ips, {} dps", instruction_count, draw_count);
+                now = Instant::now();
+                instruction_count = 0;
+                draw_count = 0;
+            }
+            if Instant::now() >= next_frame {
+                // don't try to catch up after a stall
+                next_frame = (next_frame + FRAME).max(Instant::now());
+                if rewinding {
+                    if rewind.step_back(&mut self.emu) {
+                        halted = false;
+                        window.request_redraw();
+                    }
+                } else if !halted {
+                    rewind.record(&self.emu);
+                    match self.emu.run_frame() {
+                        Ok(frame) => if frame.redraw {
+                            window.request_redraw();
+                        },
+                        // keep the last screen displayed when the program crashes
+                        Err(err) => {
+                            window.set_title(&format!("halted: {}", err));
+                            halted = true;
+                        },
+                    }
+                    instruction_count += self.emu.instructions_per_frame;
                 }
             }
-            self.emu.cpu_one_cycle();
-            instruction_count += 1;
-            //if self.emu.redraw {
-            //    window.request_redraw();
-            //    self.emu.redraw = false;
-            //}
-            if instruction_count % 30 == 0 {
-                window.request_redraw();
+            *control_flow = ControlFlow::WaitUntil(next_frame);
+            if self.emu.exited {
+                *control_flow = ControlFlow::Exit;
+                return;
             }
 
             if let Event::RedrawRequested(_) = event {
                 let frame = pixels.get_frame();
+                // lores modes are scaled up to fill the hires frame
+                let scale = (WIDTH as usize / self.emu.resolution.0)
+                    .min(HEIGHT as usize / self.emu.resolution.1);
 
-                for (i, pixel) in frame.chunks_exact_mut(4).enumerate() {
-                    let x = (i % WIDTH as usize) as usize;
-                    let y = (i / WIDTH as usize) as usize;
+                // only the areas that changed since the last redraw
+                for rect in self.emu.screen.dirty_rects() {
+                    for (x, y) in rect.points() {
+                        let color = palette[self.emu.screen.get(x, y) as usize];
 
-                    if x < self.emu.resolution.0 && y < self.emu.resolution.1 {
-                        if self.emu.screen[x][y] {
-                            pixel.copy_from_slice(&[0x00, 0x00, 0x00, 0xFF]);
-                        } else {
-                            pixel.copy_from_slice(&[0xFF, 0xFF, 0xFF, 0xFF]);
+                        for row in y * scale..(y + 1) * scale {
+                            let start = (row * WIDTH as usize + x * scale) * 4;
+                            frame[start..start + scale * 4].chunks_exact_mut(4)
+                                .for_each(|pixel| pixel.copy_from_slice(&color));
                         }
                     }
                 }
+                self.emu.screen.clear_dirty();
                 draw_count += 1;
                 if pixels
                 .render()
@@ -112,7 +199,34 @@ impl Screen for UIPixels {
                     *control_flow = ControlFlow::Exit;
                     return;
                 }
+                // rewind while backspace is held
+                rewinding = input.key_held(VirtualKeyCode::Back);
+
+                for (idx, k) in slot_keys.iter().enumerate() {
+                    if !input.key_pressed(*k) {
+                        continue;
+                    }
+                    if input.held_shift() {
+                        slots[idx] = Some(self.emu.save_state());
+                        println!("saved slot {}", idx + 1);
+                    } else if let Some(state) = &slots[idx] {
+                        match self.emu.load_state(state) {
+                            Ok(()) => {
+                                println!("loaded slot {}", idx + 1);
+                                halted = false;
+                            },
+                            Err(reason) => println!("failed to load slot {}: {}", idx + 1, reason),
+                        }
+                    }
+                }
 
+                for (k, idx) in actions.iter() {
+                    if input.key_pressed(*k) {
+                        self.emu.keys[*idx] = true;
+                    } else if input.key_released(*k) {
+                        self.emu.keys[*idx] = false;
+                    }
+                }
                 for (idx, k) in keys.iter().enumerate() {
                     if self.emu.keys[idx] && input.key_released(*k) {
                         //println!("key released: {:?}", *k);
@@ -0,0 +1,45 @@
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+use emulator::instruction::Instruction;
+use emulator::Emulator;
+
+const INSTRUCTIONS_PER_FRAME: usize = 1000;
+
+/// Endless loop of arithmetic, BCD, register loads and drawing
+fn program() -> Vec<u8> {
+    Instruction::assemble(&[
+        Instruction::LoadVal(0, 0),
+        Instruction::LoadVal(1, 0),
+        Instruction::AddVal(0, 1),          // 0x204
+        Instruction::Add(1, 0),
+        Instruction::LoadAddr(0x300),
+        Instruction::Bcd(0),
+        Instruction::LoadRegs(2),
+        Instruction::LoadSprite(0),
+        Instruction::Draw(1, 2, 5),
+        Instruction::ShiftRight(1, 1),
+        Instruction::Xor(2, 1),
+        Instruction::SkipValEq(0, 0xFF),
+        Instruction::Jump(0x204),
+        Instruction::Jump(0x200),
+    ])
+}
+
+fn headless(c: &mut Criterion) {
+    let mut group = c.benchmark_group("interpreter");
+
+    group.throughput(Throughput::Elements(INSTRUCTIONS_PER_FRAME as u64));
+    for (name, dynarec) in [("run_frame", false), ("run_frame_dynarec", true)].iter() {
+        let mut emu = Emulator::builder()
+            .instructions_per_frame(INSTRUCTIONS_PER_FRAME)
+            .dynarec(*dynarec)
+            .rom(&program())
+            .build()
+            .unwrap();
+
+        group.bench_function(*name, |b| b.iter(|| emu.run_frame().unwrap()));
+    }
+    group.finish();
+}
+
+criterion_group!(benches, headless);
+criterion_main!(benches);
@@ -0,0 +1,167 @@
+use crate::disassembler::{Disassembly, Item};
+use crate::instruction::{Address, Instruction};
+use crate::preset::Preset;
+use crate::profile::Profile;
+use crate::Mode;
+use std::fmt;
+
+/// What a ROM needs from the interpreter, found by following its control
+/// flow from the entry point. Each list holds the addresses of the
+/// reachable instructions of that kind.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Analysis {
+    /// begins with the jump to the two-page hires patch at 0x260
+    pub hires: bool,
+    /// scrolling, big sprites, resolution switches, exit and flags
+    pub schip: Vec<Address>,
+    /// bitplanes, audio, long addresses, register ranges and 16 flags
+    pub xochip: Vec<Address>,
+    /// 0NNN calls into CDP1802 code
+    pub machine_code: Vec<Address>,
+    /// 8XY6 and 8XYE, shifting VY or VX
+    pub shifts: Vec<Address>,
+    /// FX55 and FX65, incrementing I or not
+    pub load_store: Vec<Address>,
+    /// BNNN, adding V0 or VX
+    pub jump_rel: Vec<Address>,
+}
+
+impl Analysis {
+    /// Analyze `rom` as loaded at 0x200
+    pub fn new(rom: &[u8]) -> Self {
+        let hires = Mode::detect(rom) == Mode::HiresChip8;
+        let profile = if hires { Profile::hires_chip8() } else { Profile::chip8() };
+        let disasm = Disassembly::with_entry(rom, profile.load_address, profile.entry_point);
+        let mut analysis = Analysis { hires, ..Analysis::default() };
+
+        for item in disasm.items() {
+            let (addr, instr) = match item {
+                Item::Code { addr, instr, .. } => (*addr, *instr),
+                Item::Data { .. } => continue,
+            };
+            let list = match instr {
+                Instruction::Sys(0x230) if hires => continue,
+                Instruction::Sys(_) => &mut analysis.machine_code,
+                Instruction::ShiftRight(..) | Instruction::ShiftLeft(..) => &mut analysis.shifts,
+                Instruction::StoreRegs(_) | Instruction::LoadRegs(_) => &mut analysis.load_store,
+                Instruction::JumpRel(_) => &mut analysis.jump_rel,
+                // SUPER-CHIP only saves V0-V7
+                Instruction::StoreFlags(reg) | Instruction::LoadFlags(reg) if reg > 7 => &mut analysis.xochip,
+                Instruction::ScrollDown(_) | Instruction::ScrollRight | Instruction::ScrollLeft
+                | Instruction::Exit | Instruction::LowRes | Instruction::HighRes
+                | Instruction::LoadBigSprite(_) | Instruction::StoreFlags(_) | Instruction::LoadFlags(_)
+                | Instruction::Draw(_, _, 0) => &mut analysis.schip,
+                Instruction::ScrollUp(_) | Instruction::SaveRange(..) | Instruction::LoadRange(..)
+                | Instruction::LoadLongAddr | Instruction::SelectPlanes(_) | Instruction::LoadAudio
+                | Instruction::SetPitch(_) => &mut analysis.xochip,
+                _ => continue,
+            };
+            list.push(addr);
+        }
+        analysis
+    }
+    /// Best guess of the machine the ROM was written for. Hires ROMs have
+    /// none, `Mode::detect` already sets them up.
+    pub fn preset(&self) -> Option<Preset> {
+        if self.hires {
+            None
+        } else if !self.xochip.is_empty() {
+            Some(Preset::XoChip)
+        } else if !self.schip.is_empty() {
+            Some(Preset::ModernSchip)
+        } else {
+            Some(Preset::VipChip8)
+        }
+    }
+}
+
+impl fmt::Display for Analysis {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.hires {
+            writeln!(f, "hires CHIP-8 header")?;
+        }
+        let lists = [
+            ("SUPER-CHIP", &self.schip),
+            ("XO-CHIP", &self.xochip),
+            ("machine code", &self.machine_code),
+            ("shifts", &self.shifts),
+            ("load/store", &self.load_store),
+            ("jump V0", &self.jump_rel),
+        ];
+        for (name, addrs) in lists.iter() {
+            if !addrs.is_empty() {
+                let addrs: Vec<String> = addrs.iter().map(|addr| format!("{:03X}", addr)).collect();
+                writeln!(f, "{}: {}", name, addrs.join(" "))?;
+            }
+        }
+        match self.preset() {
+            Some(preset) => writeln!(f, "preset: {}", preset.name()),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test_analyzer {
+    use super::*;
+
+    #[test]
+    fn test_chip8() {
+        let rom = Instruction::assemble(&[
+            Instruction::Call(0x206),
+            Instruction::ShiftLeft(1, 1),
+            Instruction::JumpRel(0x20A),
+            Instruction::StoreRegs(3),      // 0x206
+            Instruction::Ret,
+            Instruction::LoadRegs(2),       // 0x20A, runs when V0 is 0
+            Instruction::ShiftRight(2, 2),
+            Instruction::Jump(0x20E),
+            Instruction::Sys(0x300),        // unreachable
+            Instruction::SelectPlanes(3),
+        ]);
+        let analysis = Analysis::new(&rom);
+
+        assert_eq!(analysis, Analysis {
+            shifts: vec![0x202, 0x20C],
+            load_store: vec![0x206, 0x20A],
+            jump_rel: vec![0x204],
+            ..Analysis::default()
+        });
+        assert_eq!(analysis.preset(), Some(Preset::VipChip8));
+        assert_eq!(analysis.to_string(), "shifts: 202 20C\nload/store: 206 20A\njump V0: 204\npreset: vip\n");
+    }
+
+    #[test]
+    fn test_extensions() {
+        let schip = Instruction::assemble(&[
+            Instruction::HighRes,
+            Instruction::Draw(0, 0, 0),
+            Instruction::StoreFlags(7),
+            Instruction::Exit,
+        ]);
+        let analysis = Analysis::new(&schip);
+        assert_eq!(analysis.schip, vec![0x200, 0x202, 0x204, 0x206]);
+        assert_eq!(analysis.preset(), Some(Preset::ModernSchip));
+
+        let xochip = Instruction::assemble(&[
+            Instruction::HighRes,
+            Instruction::LoadLongAddr,
+            Instruction::Invalid,
+            Instruction::StoreFlags(15),
+            Instruction::Call(0x500),
+        ]);
+        let analysis = Analysis::new(&xochip);
+        assert_eq!(analysis.schip, vec![0x200]);
+        assert_eq!(analysis.xochip, vec![0x202, 0x206]);
+        assert_eq!(analysis.preset(), Some(Preset::XoChip));
+
+        // the patch area isn't followed, 0x230 clears the screen
+        let mut hires = Instruction::assemble(&[Instruction::Jump(0x260)]);
+        hires.resize(0xC0, 0);
+        hires.extend(Instruction::assemble(&[Instruction::Sys(0x230), Instruction::Sys(0x400)]));
+        let analysis = Analysis::new(&hires);
+        assert!(analysis.hires);
+        assert_eq!(analysis.machine_code, vec![0x2C2]);
+        assert_eq!(analysis.preset(), None);
+    }
+}
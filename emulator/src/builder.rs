@@ -0,0 +1,227 @@
+use crate::cdp1802::VIP_MEMORY_SIZE;
+use crate::clock::Clock;
+use crate::error::ConfigError;
+use crate::preset::Preset;
+use crate::profile::Profile;
+use crate::quirks::Quirks;
+use crate::random::RandomSource;
+use crate::timing::Timing;
+use crate::{Emulator, Mode, FONT_SIZE, INSTRUCTIONS_PER_FRAME};
+
+/// Configures an emulator, checking everything fits together before it is
+/// built
+pub struct EmulatorBuilder {
+    mode: Mode,
+    /* the mode decides when not given */
+    profile: Option<Profile>,
+    quirks: Quirks,
+    instructions_per_frame: usize,
+    timing: Timing,
+    dynarec: bool,
+    stack_depth: usize,
+    /* the mode decides when not given */
+    memory_size: Option<usize>,
+    seed: u64,
+    rng: Option<Box<dyn RandomSource>>,
+    clock: Option<Box<dyn Clock>>,
+    rom: Option<Vec<u8>>,
+}
+
+impl EmulatorBuilder {
+    pub fn new() -> Self {
+        EmulatorBuilder {
+            mode: Mode::Chip8,
+            profile: None,
+            quirks: Quirks::default(),
+            instructions_per_frame: INSTRUCTIONS_PER_FRAME,
+            timing: Timing::default(),
+            dynarec: false,
+            stack_depth: 16,
+            memory_size: None,
+            seed: 0,
+            rng: None,
+            clock: None,
+            rom: None,
+        }
+    }
+    pub fn mode(mut self, mode: Mode) -> Self {
+        self.mode = mode;
+        self
+    }
+    pub fn profile(mut self, profile: Profile) -> Self {
+        self.profile = Some(profile);
+        self
+    }
+    /// Mode, profile, quirks and speed of the preset, later calls override
+    /// them
+    pub fn preset(mut self, preset: Preset) -> Self {
+        self.mode = preset.mode();
+        self.profile = Some(preset.profile());
+        self.quirks = preset.quirks();
+        self.instructions_per_frame = preset.instructions_per_frame();
+        self
+    }
+    pub fn quirks(mut self, quirks: Quirks) -> Self {
+        self.quirks = quirks;
+        self
+    }
+    pub fn instructions_per_frame(mut self, instructions: usize) -> Self {
+        self.instructions_per_frame = instructions;
+        self
+    }
+    pub fn timing(mut self, timing: Timing) -> Self {
+        self.timing = timing;
+        self
+    }
+    /// Run straight-line code as translated blocks
+    pub fn dynarec(mut self, dynarec: bool) -> Self {
+        self.dynarec = dynarec;
+        self
+    }
+    /// Nested calls allowed before a stack overflow, the VIP had 12
+    pub fn stack_depth(mut self, depth: usize) -> Self {
+        self.stack_depth = depth;
+        self
+    }
+    /// Bytes of memory, instead of what the mode needs
+    pub fn memory_size(mut self, size: usize) -> Self {
+        self.memory_size = Some(size);
+        self
+    }
+    /// Seed of the default random source
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.seed = seed;
+        self
+    }
+    pub fn random_source(mut self, rng: Box<dyn RandomSource>) -> Self {
+        self.rng = Some(rng);
+        self
+    }
+    pub fn clock(mut self, clock: Box<dyn Clock>) -> Self {
+        self.clock = Some(clock);
+        self
+    }
+    /// ROM loaded by `build`
+    pub fn rom(mut self, rom: &[u8]) -> Self {
+        self.rom = Some(rom.to_vec());
+        self
+    }
+    fn validate(&self, profile: &Profile, memory_size: usize) -> Result<(), ConfigError> {
+        let load_address = profile.load_address as usize;
+
+        if self.stack_depth == 0 || self.stack_depth > 16 {
+            return Err(ConfigError::StackDepth(self.stack_depth));
+        }
+        if memory_size == 0 || memory_size > 0x10000 {
+            return Err(ConfigError::MemorySize(memory_size));
+        }
+        profile.check(memory_size)?;
+        if self.quirks.machine_code && memory_size < VIP_MEMORY_SIZE {
+            return Err(ConfigError::MachineCodeMemory(memory_size));
+        }
+        if let Some(rom) = &self.rom {
+            let space = memory_size - load_address;
+            let font = profile.font_address as usize..profile.font_address as usize + FONT_SIZE;
+
+            if rom.len() > space {
+                return Err(ConfigError::RomTooLarge { len: rom.len(), space });
+            }
+            if load_address < font.end && font.start < load_address + rom.len() {
+                return Err(ConfigError::RomOverlapsFont);
+            }
+        }
+        Ok(())
+    }
+    pub fn build(self) -> Result<Emulator, ConfigError> {
+        let profile = match self.profile {
+            Some(profile) => profile,
+            None if self.mode == Mode::HiresChip8 => Profile::hires_chip8(),
+            None => Profile::chip8(),
+        };
+        let memory_size = self.memory_size.unwrap_or_else(|| self.mode.memory_size());
+        self.validate(&profile, memory_size)?;
+
+        let mut emu = Emulator::new(profile.display, self.quirks, self.seed);
+        emu.set_mode(self.mode);
+        emu.memory.resize(memory_size, 0);
+        emu.replace_profile(profile);
+        emu.instructions_per_frame = self.instructions_per_frame;
+        emu.timing = self.timing;
+        emu.dynarec = self.dynarec;
+        emu.stack_depth = self.stack_depth;
+        emu.pc_reg = profile.entry_point;
+        if let Some(rng) = self.rng {
+            emu.set_random_source(rng);
+        }
+        if let Some(clock) = self.clock {
+            emu.set_clock(clock);
+        }
+        if let Some(rom) = &self.rom {
+            emu.load_rom(rom);
+        }
+        Ok(emu)
+    }
+}
+
+impl Default for EmulatorBuilder {
+    fn default() -> Self {
+        EmulatorBuilder::new()
+    }
+}
+
+impl Emulator {
+    pub fn builder() -> EmulatorBuilder {
+        EmulatorBuilder::new()
+    }
+}
+
+#[cfg(test)]
+mod test_builder {
+    use super::*;
+    use crate::error::EmulatorError;
+    use crate::instruction::Instruction;
+
+    #[test]
+    fn test_build() {
+        let rom = Instruction::assemble(&[Instruction::Call(0x600)]);
+        let mut emu = Emulator::builder()
+            .preset(Preset::Eti660)
+            .stack_depth(2)
+            .memory_size(0x800)
+            .rom(&rom)
+            .build()
+            .unwrap();
+
+        assert_eq!(emu.memory.len(), 0x800);
+        assert_eq!(emu.resolution, (64, 48));
+        assert!(emu.quirks.shift_uses_vy);
+        assert_eq!(emu.pc_reg, 0x600);
+        emu.cpu_one_cycle().unwrap();
+        emu.cpu_one_cycle().unwrap();
+        assert_eq!(emu.cpu_one_cycle(), Err(EmulatorError::StackOverflow { pc: 0x600, opcode: 0x2600 }));
+
+        let emu = Emulator::builder().mode(Mode::HiresChip8).build().unwrap();
+        assert_eq!((emu.pc_reg, emu.resolution), (0x2C0, (64, 64)));
+    }
+
+    #[test]
+    fn test_rejected() {
+        let build = |builder: EmulatorBuilder| builder.build().err();
+
+        assert_eq!(build(Emulator::builder().stack_depth(17)), Some(ConfigError::StackDepth(17)));
+        assert_eq!(build(Emulator::builder().memory_size(0x10001)), Some(ConfigError::MemorySize(0x10001)));
+        assert_eq!(build(Emulator::builder().memory_size(0x200)), Some(ConfigError::LoadAddress(0x200)));
+        assert_eq!(build(Emulator::builder().preset(Preset::Eti660).memory_size(0x601)),
+            Some(ConfigError::EntryPoint(0x600)));
+        assert_eq!(build(Emulator::builder().profile(Profile { font_address: 0xFF0, ..Profile::chip8() })),
+            Some(ConfigError::FontAddress(0xFF0)));
+        assert_eq!(build(Emulator::builder().rom(&[0; 0xE01])),
+            Some(ConfigError::RomTooLarge { len: 0xE01, space: 0xE00 }));
+        assert_eq!(build(Emulator::builder().profile(Profile { font_address: 0x220, ..Profile::chip8() }).rom(&[0; 0x21])),
+            Some(ConfigError::RomOverlapsFont));
+        assert!(Emulator::builder().profile(Profile { font_address: 0x220, ..Profile::chip8() }).rom(&[0; 0x20]).build().is_ok());
+        assert_eq!(build(Emulator::builder().preset(Preset::VipChip8).memory_size(0x800).rom(&[0x03, 0x00])),
+            Some(ConfigError::MachineCodeMemory(0x800)));
+        assert!(Emulator::builder().preset(Preset::Eti660).memory_size(0x800).build().is_ok());
+    }
+}
@@ -0,0 +1,378 @@
+use crate::error::Fault;
+use crate::instruction::Address;
+use crate::timing::Timing;
+use crate::Emulator;
+
+// where the VIP interpreter keeps its state in a 4K machine
+pub(crate) const VIP_MEMORY_SIZE: usize = 0x1000;
+const VIP_STACK: u16 = 0x0ECF;
+const VIP_REGISTERS: usize = 0x0EF0;
+const VIP_DISPLAY: usize = 0x0F00;
+// give up on subroutines running for more than a second
+const MACHINE_CODE_CYCLES: u64 = 220_000;
+
+/// RCA CDP1802 CPU, the processor of the COSMAC VIP.
+///
+/// It runs against a plain byte array, addresses wrap around its length.
+/// Nothing is wired to the I/O lines: OUT discards its byte, INP reads 0 and
+/// the EF flags are whatever the caller puts in `ef`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Cdp1802 {
+    /// scratchpad registers R0-RF
+    pub r: [u16; 16],
+    pub d: u8,
+    pub df: bool,
+    /// program counter designator
+    pub p: u8,
+    /// data pointer designator
+    pub x: u8,
+    /// X and P saved by MARK and interrupts
+    pub t: u8,
+    /// interrupt enable
+    pub ie: bool,
+    pub q: bool,
+    /// external flags EF1-EF4
+    pub ef: [bool; 4],
+    /// IDL was executed, waiting for an interrupt or DMA
+    pub idle: bool,
+    /// machine cycles executed so far, 8 clock cycles each
+    pub cycles: u64,
+}
+
+impl Cdp1802 {
+    pub fn new() -> Self {
+        Cdp1802::default()
+    }
+
+    fn read(memory: &[u8], addr: u16) -> u8 {
+        memory[addr as usize % memory.len()]
+    }
+    fn write(memory: &mut [u8], addr: u16, val: u8) {
+        let len = memory.len();
+        memory[addr as usize % len] = val;
+    }
+    fn rp(&mut self) -> &mut u16 {
+        &mut self.r[self.p as usize]
+    }
+    fn rx(&mut self) -> &mut u16 {
+        &mut self.r[self.x as usize]
+    }
+    /// Read the byte after the opcode, moving past it
+    fn immediate(&mut self, memory: &[u8]) -> u8 {
+        let val = Cdp1802::read(memory, *self.rp());
+        *self.rp() = self.rp().wrapping_add(1);
+        val
+    }
+    fn short_branch(&mut self, memory: &[u8], taken: bool) {
+        if taken {
+            let low = Cdp1802::read(memory, *self.rp());
+            *self.rp() = (*self.rp() & 0xFF00) | low as u16;
+        } else {
+            *self.rp() = self.rp().wrapping_add(1);
+        }
+    }
+    fn long_branch(&mut self, memory: &[u8], taken: bool) {
+        if taken {
+            let high = Cdp1802::read(memory, *self.rp());
+            let low = Cdp1802::read(memory, self.rp().wrapping_add(1));
+            *self.rp() = (high as u16) << 8 | low as u16;
+        } else {
+            *self.rp() = self.rp().wrapping_add(2);
+        }
+    }
+    fn long_skip(&mut self, taken: bool) {
+        if taken {
+            *self.rp() = self.rp().wrapping_add(2);
+        }
+    }
+    fn add(&mut self, a: u8, b: u8, carry: bool) {
+        let sum = a as u16 + b as u16 + carry as u16;
+        self.d = sum as u8;
+        self.df = sum > 0xFF;
+    }
+    /// a - b, DF is set when there is no borrow
+    fn sub(&mut self, a: u8, b: u8, borrow: bool) {
+        let diff = a as i16 - b as i16 - borrow as i16;
+        self.d = diff as u8;
+        self.df = diff >= 0;
+    }
+
+    /// Execute a single instruction, does nothing while idle
+    pub fn step(&mut self, memory: &mut [u8]) {
+        if self.idle {
+            self.cycles += 1;
+            return;
+        }
+        let opcode = self.immediate(memory);
+        let n = (opcode & 0x0F) as usize;
+        self.cycles += 2;
+
+        match opcode >> 4 {
+            0x0 if n == 0 => self.idle = true,
+            0x0 => self.d = Cdp1802::read(memory, self.r[n]),
+            0x1 => self.r[n] = self.r[n].wrapping_add(1),
+            0x2 => self.r[n] = self.r[n].wrapping_sub(1),
+            0x3 => {
+                let taken = match n {
+                    0x0 => true,
+                    0x1 => self.q,
+                    0x2 => self.d == 0,
+                    0x3 => self.df,
+                    0x4..=0x7 => self.ef[n - 0x4],
+                    0x8 => false,
+                    0x9 => !self.q,
+                    0xA => self.d != 0,
+                    0xB => !self.df,
+                    _ => !self.ef[n - 0xC],
+                };
+                self.short_branch(memory, taken);
+            },
+            0x4 => {
+                self.d = Cdp1802::read(memory, self.r[n]);
+                self.r[n] = self.r[n].wrapping_add(1);
+            },
+            0x5 => Cdp1802::write(memory, self.r[n], self.d),
+            0x6 => match n {
+                0x0 => *self.rx() = self.rx().wrapping_add(1),
+                // OUT 1-7, the byte goes nowhere
+                0x1..=0x7 => *self.rx() = self.rx().wrapping_add(1),
+                // INP 1-7, nothing drives the bus
+                0x9..=0xF => {
+                    self.d = 0;
+                    Cdp1802::write(memory, *self.rx(), 0);
+                },
+                _ => {},
+            },
+            0x7 => match n {
+                0x0 | 0x1 => {
+                    let val = Cdp1802::read(memory, *self.rx());
+                    *self.rx() = self.rx().wrapping_add(1);
+                    self.x = val >> 4;
+                    self.p = val & 0x0F;
+                    self.ie = n == 0x0;
+                },
+                0x2 => {
+                    self.d = Cdp1802::read(memory, *self.rx());
+                    *self.rx() = self.rx().wrapping_add(1);
+                },
+                0x3 => {
+                    Cdp1802::write(memory, *self.rx(), self.d);
+                    *self.rx() = self.rx().wrapping_sub(1);
+                },
+                0x4 => {
+                    let val = Cdp1802::read(memory, *self.rx());
+                    self.add(val, self.d, self.df);
+                },
+                0x5 => {
+                    let val = Cdp1802::read(memory, *self.rx());
+                    self.sub(val, self.d, !self.df);
+                },
+                0x6 => {
+                    let carry = self.df;
+                    self.df = self.d & 0x01 != 0;
+                    self.d = self.d >> 1 | (carry as u8) << 7;
+                },
+                0x7 => {
+                    let val = Cdp1802::read(memory, *self.rx());
+                    self.sub(self.d, val, !self.df);
+                },
+                0x8 => Cdp1802::write(memory, *self.rx(), self.t),
+                0x9 => {
+                    self.t = self.x << 4 | self.p;
+                    Cdp1802::write(memory, self.r[2], self.t);
+                    self.x = self.p;
+                    self.r[2] = self.r[2].wrapping_sub(1);
+                },
+                0xA => self.q = false,
+                0xB => self.q = true,
+                0xC => {
+                    let val = self.immediate(memory);
+                    self.add(val, self.d, self.df);
+                },
+                0xD => {
+                    let val = self.immediate(memory);
+                    self.sub(val, self.d, !self.df);
+                },
+                0xE => {
+                    let carry = self.df;
+                    self.df = self.d & 0x80 != 0;
+                    self.d = self.d << 1 | carry as u8;
+                },
+                _ => {
+                    let val = self.immediate(memory);
+                    self.sub(self.d, val, !self.df);
+                },
+            },
+            0x8 => self.d = self.r[n] as u8,
+            0x9 => self.d = (self.r[n] >> 8) as u8,
+            0xA => self.r[n] = (self.r[n] & 0xFF00) | self.d as u16,
+            0xB => self.r[n] = (self.r[n] & 0x00FF) | (self.d as u16) << 8,
+            0xC => {
+                self.cycles += 1;
+                match n {
+                    0x0 => self.long_branch(memory, true),
+                    0x1 => self.long_branch(memory, self.q),
+                    0x2 => self.long_branch(memory, self.d == 0),
+                    0x3 => self.long_branch(memory, self.df),
+                    0x4 => {},
+                    0x5 => self.long_skip(!self.q),
+                    0x6 => self.long_skip(self.d != 0),
+                    0x7 => self.long_skip(!self.df),
+                    0x8 => self.long_skip(true),
+                    0x9 => self.long_branch(memory, !self.q),
+                    0xA => self.long_branch(memory, self.d != 0),
+                    0xB => self.long_branch(memory, !self.df),
+                    0xC => self.long_skip(self.ie),
+                    0xD => self.long_skip(self.q),
+                    0xE => self.long_skip(self.d == 0),
+                    _ => self.long_skip(self.df),
+                }
+            },
+            0xD => self.p = n as u8,
+            0xE => self.x = n as u8,
+            _ => {
+                // F8-FF take their operand from the program instead of M(R(X))
+                let val = if n >= 0x8 && n != 0xE {
+                    self.immediate(memory)
+                } else {
+                    Cdp1802::read(memory, *self.rx())
+                };
+                match n & 0x7 {
+                    0x0 => self.d = val,
+                    0x1 => self.d |= val,
+                    0x2 => self.d &= val,
+                    0x3 => self.d ^= val,
+                    0x4 => self.add(val, self.d, false),
+                    0x5 => self.sub(val, self.d, false),
+                    0x6 if n == 0x6 => {
+                        self.df = self.d & 0x01 != 0;
+                        self.d >>= 1;
+                    },
+                    0x6 => {
+                        self.df = self.d & 0x80 != 0;
+                        self.d <<= 1;
+                    },
+                    _ => self.sub(self.d, val, false),
+                }
+            },
+        }
+    }
+}
+
+impl Emulator {
+    /// 0NNN, call 1802 code the way the VIP interpreter does: R3 is the
+    /// program counter and D4 (SEP R4) returns to the interpreter. The
+    /// registers, I and a 64x32 screen are mirrored where the VIP keeps them.
+    pub(crate) fn machine_code(&mut self, addr: Address) -> Result<(), Fault> {
+        if self.memory.len() < VIP_MEMORY_SIZE {
+            return Err(Fault::MemoryOutOfRange(self.memory.len()));
+        }
+        self.memory[VIP_REGISTERS..VIP_REGISTERS + 16].copy_from_slice(&self.regs);
+        let mirror_screen = self.resolution == (64, 32);
+        if mirror_screen {
+            for y in 0..32 {
+                self.memory[VIP_DISPLAY + y * 8..VIP_DISPLAY + y * 8 + 8].copy_from_slice(self.screen.row(0, y));
+            }
+        }
+
+        let mut cpu = Cdp1802::new();
+        cpu.p = 3;
+        cpu.x = 2;
+        cpu.r[2] = VIP_STACK;
+        cpu.r[3] = addr;
+        cpu.r[0xA] = self.i_reg;
+        // the 1802 may write anywhere
+        self.memory_replaced();
+        while cpu.p != 4 {
+            if cpu.cycles > MACHINE_CODE_CYCLES {
+                return Err(Fault::MachineCodeTimeout);
+            }
+            // the display interrupt wakes IDL up
+            cpu.idle = false;
+            cpu.step(&mut self.memory);
+        }
+
+        self.regs.copy_from_slice(&self.memory[VIP_REGISTERS..VIP_REGISTERS + 16]);
+        self.i_reg = cpu.r[0xA];
+        if mirror_screen {
+            for y in 0..32 {
+                self.screen.set_row(0, y, &self.memory[VIP_DISPLAY + y * 8..VIP_DISPLAY + y * 8 + 8]);
+            }
+            self.screen_draw();
+        }
+        if self.timing == Timing::Vip {
+            self.vip_cycles -= cpu.cycles as i32;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test_cdp1802 {
+    use super::*;
+
+    fn run(program: &[u8], steps: usize) -> (Cdp1802, Vec<u8>) {
+        let mut cpu = Cdp1802::new();
+        let mut memory = vec![0; 0x100];
+        memory[..program.len()].copy_from_slice(program);
+        for _ in 0..steps {
+            cpu.step(&mut memory);
+        }
+        (cpu, memory)
+    }
+
+    #[test]
+    fn test_arithmetic() {
+        // LDI 0xF0, ADI 0x20, PLO R5, SMI 0x20, PHI R5, SHLC
+        let (cpu, _) = run(&[0xF8, 0xF0, 0xFC, 0x20, 0xA5, 0xFF, 0x20, 0xB5, 0x7E], 6);
+        assert_eq!(cpu.r[5], 0xF010);
+        assert_eq!((cpu.d, cpu.df), (0xE0, true));
+        assert_eq!(cpu.cycles, 12);
+
+        // LDI 0x10, SDI 0x05: 5 - 16 borrows
+        let (cpu, _) = run(&[0xF8, 0x10, 0xFD, 0x05], 2);
+        assert_eq!((cpu.d, cpu.df), (0xF5, false));
+    }
+
+    #[test]
+    fn test_memory_and_branches() {
+        let program = [
+            0xF8, 0x40, // LDI 0x40
+            0xA6,       // PLO R6
+            0xE6,       // SEX R6
+            0xF8, 0x03, // LDI 3
+            0x73,       // STXD
+            0x60,       // IRX
+            0xF0,       // LDX
+            0xFF, 0x01, // SMI 1
+            0x3A, 0x09, // BNZ 0x09
+            0xC0, 0x00, 0x20, // LBR 0x20
+        ];
+        let (cpu, memory) = run(&program, 14);
+        assert_eq!(memory[0x40], 3);
+        assert_eq!(cpu.d, 0);
+        assert_eq!(cpu.r[0], 0x20);
+        assert_eq!(cpu.r[6], 0x40);
+    }
+
+    #[test]
+    fn test_mark_and_ret() {
+        let program = [
+            0xF8, 0x80, 0xA2, // LDI 0x80, PLO R2
+            0xE2,             // SEX R2
+            0x79,             // MARK
+            0x7B,             // SEQ
+            0xE2,             // SEX R2
+            0x60,             // IRX
+            0x70,             // RET
+        ];
+        let (cpu, memory) = run(&program, 4);
+        assert_eq!(memory[0x80], 0x20);
+        assert_eq!((cpu.x, cpu.r[2]), (0, 0x7F));
+
+        let (cpu, _) = run(&program, 8);
+        assert!(cpu.q);
+        assert!(cpu.ie);
+        assert_eq!((cpu.x, cpu.p, cpu.r[2]), (2, 0, 0x81));
+    }
+}
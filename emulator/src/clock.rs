@@ -0,0 +1,35 @@
+use std::cell::Cell;
+use std::rc::Rc;
+
+/// Source of time for the 60Hz delay and sound timers.
+///
+/// The emulator never reads the OS clock itself, frontends provide one
+/// based on whatever timer their platform has.
+pub trait Clock {
+    /// Number of 60Hz frames elapsed since an arbitrary origin
+    fn frame(&self) -> u64;
+}
+
+/// Clock only moving when told to, for headless runs and tests.
+///
+/// Clones share the same time, so one can be kept to drive the clock given to
+/// the emulator.
+#[derive(Debug, Clone, Default)]
+pub struct ManualClock {
+    frame: Rc<Cell<u64>>,
+}
+
+impl ManualClock {
+    pub fn new() -> Self {
+        ManualClock::default()
+    }
+    pub fn advance(&self, frames: u64) {
+        self.frame.set(self.frame.get() + frames);
+    }
+}
+
+impl Clock for ManualClock {
+    fn frame(&self) -> u64 {
+        self.frame.get()
+    }
+}
@@ -0,0 +1,318 @@
+use crate::error::EmulatorError;
+use crate::instruction::{Address, Instruction, Register};
+use crate::Emulator;
+
+/// Test on a register value, for conditional breakpoints
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Condition {
+    Equal(Register, u8),
+    NotEqual(Register, u8),
+    Less(Register, u8),
+    Greater(Register, u8),
+}
+
+impl Condition {
+    /// Only the low nibble of the register selects it, as in opcodes
+    pub fn holds(&self, regs: &[u8; 16]) -> bool {
+        match *self {
+            Condition::Equal(reg, val) => regs[reg & 0xF] == val,
+            Condition::NotEqual(reg, val) => regs[reg & 0xF] != val,
+            Condition::Less(reg, val) => regs[reg & 0xF] < val,
+            Condition::Greater(reg, val) => regs[reg & 0xF] > val,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Breakpoint {
+    pub pc: Address,
+    /* only break when the condition holds */
+    pub condition: Option<Condition>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Access {
+    Read,
+    Write,
+    /// Only used by watchpoints, matches both reads and writes
+    ReadWrite,
+}
+
+impl Access {
+    fn matches(self, access: Access) -> bool {
+        self == Access::ReadWrite || self == access
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Watchpoint {
+    pub start: usize,
+    pub len: usize,
+    pub access: Access,
+}
+
+/// Why the debugger gave control back
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StopReason {
+    /// The requested step completed
+    Step,
+    /// About to execute the instruction at a breakpoint
+    Breakpoint(Address),
+    /// The instruction at `pc` accessed watched memory at `addr`
+    Watchpoint { pc: Address, addr: usize, access: Access },
+    /// The subroutine being run returned to its caller
+    Returned,
+    /// Ran the maximum number of instructions without stopping
+    Limit,
+    /// The program executed 00FD
+    Exited,
+    Error(EmulatorError),
+}
+
+/// Runs an emulator under control of breakpoints and watchpoints.
+///
+/// Watchpoints are checked against the memory range the instruction is
+/// about to access, and reported once it has been executed.
+#[derive(Debug, Default)]
+pub struct Debugger {
+    breakpoints: Vec<Breakpoint>,
+    watchpoints: Vec<Watchpoint>,
+}
+
+impl Debugger {
+    pub fn new() -> Self {
+        Debugger::default()
+    }
+    pub fn add_breakpoint(&mut self, pc: Address) {
+        self.breakpoints.push(Breakpoint { pc, condition: None });
+    }
+    pub fn add_conditional_breakpoint(&mut self, pc: Address, condition: Condition) {
+        self.breakpoints.push(Breakpoint { pc, condition: Some(condition) });
+    }
+    /// Remove every breakpoint at `pc`
+    pub fn remove_breakpoint(&mut self, pc: Address) {
+        self.breakpoints.retain(|bp| bp.pc != pc);
+    }
+    pub fn breakpoints(&self) -> &[Breakpoint] {
+        &self.breakpoints
+    }
+    pub fn add_watchpoint(&mut self, start: usize, len: usize, access: Access) {
+        self.watchpoints.push(Watchpoint { start, len, access });
+    }
+    /// Remove every watchpoint starting at `start`
+    pub fn remove_watchpoint(&mut self, start: usize) {
+        self.watchpoints.retain(|wp| wp.start != start);
+    }
+    pub fn watchpoints(&self) -> &[Watchpoint] {
+        &self.watchpoints
+    }
+
+    /// Execute a single instruction, entering subroutines
+    pub fn step(&mut self, emu: &mut Emulator) -> StopReason {
+        self.execute(emu).unwrap_or(StopReason::Step)
+    }
+    /// Execute a single instruction, running called subroutines to completion
+    pub fn step_over(&mut self, emu: &mut Emulator, limit: usize) -> StopReason {
+        if let Instruction::Call(_) = emu.next_instruction() {
+            let depth = emu.sp_reg;
+            let ret = emu.pc_reg.wrapping_add(2);
+
+            self.run_until(emu, limit, StopReason::Step,
+                           |emu| emu.sp_reg == depth && emu.pc_reg == ret)
+        } else {
+            self.step(emu)
+        }
+    }
+    /// Run until the current subroutine returns
+    pub fn run_until_return(&mut self, emu: &mut Emulator, limit: usize) -> StopReason {
+        let depth = emu.sp_reg;
+
+        self.run_until(emu, limit, StopReason::Returned, |emu| emu.sp_reg < depth)
+    }
+    /// Run until a breakpoint, a watchpoint or an error stops execution
+    pub fn run(&mut self, emu: &mut Emulator, limit: usize) -> StopReason {
+        self.run_until(emu, limit, StopReason::Limit, |_| false)
+    }
+
+    fn run_until<F>(&mut self, emu: &mut Emulator, limit: usize, reason: StopReason, done: F) -> StopReason
+        where F: Fn(&Emulator) -> bool
+    {
+        for count in 0..limit {
+            // don't stop again on the breakpoint we are resuming from
+            if count > 0 && self.breakpoint_hit(emu) {
+                return StopReason::Breakpoint(emu.pc_reg);
+            }
+            if let Some(reason) = self.execute(emu) {
+                return reason;
+            }
+            if done(emu) {
+                return reason;
+            }
+        }
+        StopReason::Limit
+    }
+    fn breakpoint_hit(&self, emu: &Emulator) -> bool {
+        self.breakpoints.iter().any(|bp| {
+            bp.pc == emu.pc_reg && bp.condition.is_none_or(|cond| cond.holds(&emu.regs))
+        })
+    }
+    fn execute(&self, emu: &mut Emulator) -> Option<StopReason> {
+        if emu.exited {
+            return Some(StopReason::Exited);
+        }
+        let pc = emu.pc_reg;
+        let access = emu.next_access();
+
+        if let Err(err) = emu.cpu_one_cycle() {
+            return Some(StopReason::Error(err));
+        }
+        if emu.exited {
+            return Some(StopReason::Exited);
+        }
+        // the instruction is waiting for a key or vblank, nothing was accessed
+        if emu.pc_reg == pc {
+            return None;
+        }
+        let (start, len, access) = access?;
+        self.watchpoints.iter()
+            .find(|wp| wp.access.matches(access) && start < wp.start + wp.len && wp.start < start + len)
+            .map(|wp| StopReason::Watchpoint { pc, addr: start.max(wp.start), access })
+    }
+}
+
+impl Emulator {
+    /// Memory range read or written by the next instruction, as (start, len, access)
+    fn next_access(&self) -> Option<(usize, usize, Access)> {
+        let i = self.i_reg as usize;
+
+        match self.next_instruction() {
+            Instruction::Draw(_, _, n) => {
+                let size = if n == 0 { 32 } else { n as usize };
+                Some((i, size * self.planes.count_ones() as usize, Access::Read))
+            },
+            Instruction::Bcd(_) => Some((i, 3, Access::Write)),
+            Instruction::StoreRegs(reg) => Some((i, reg + 1, Access::Write)),
+            Instruction::LoadRegs(reg) => Some((i, reg + 1, Access::Read)),
+            Instruction::SaveRange(first, last) =>
+                Some((i, first.max(last) - first.min(last) + 1, Access::Write)),
+            Instruction::LoadRange(first, last) =>
+                Some((i, first.max(last) - first.min(last) + 1, Access::Read)),
+            Instruction::LoadAudio => Some((i, 16, Access::Read)),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test_debugger {
+    use super::*;
+    use crate::DisplaySize;
+    use crate::quirks::Quirks;
+
+    /// Main loop counting in V0 and calling a subroutine that stores it at 0x300
+    fn counting_program() -> Emulator {
+        let mut emu = Emulator::new(DisplaySize::Basic64x32, Quirks::default(), 0);
+        emu.mem_load_instr(vec![
+            Instruction::LoadAddr(0x300),
+            Instruction::AddVal(0, 1),      // 0x202
+            Instruction::Call(0x20A),
+            Instruction::Jump(0x202),
+            Instruction::Exit,
+            Instruction::StoreRegs(0),      // 0x20A
+            Instruction::LoadRegs(1),
+            Instruction::Ret,
+        ]);
+        emu
+    }
+
+    #[test]
+    fn test_breakpoints() {
+        let mut emu = counting_program();
+        let mut debugger = Debugger::new();
+
+        debugger.add_breakpoint(0x204);
+        assert_eq!(debugger.run(&mut emu, 100), StopReason::Breakpoint(0x204));
+        assert_eq!(emu.regs[0], 1);
+        // resuming doesn't stop on the same breakpoint twice
+        assert_eq!(debugger.run(&mut emu, 100), StopReason::Breakpoint(0x204));
+        assert_eq!(emu.regs[0], 2);
+
+        debugger.remove_breakpoint(0x204);
+        debugger.add_conditional_breakpoint(0x204, Condition::Equal(0, 10));
+        assert_eq!(debugger.run(&mut emu, 1000), StopReason::Breakpoint(0x204));
+        assert_eq!(emu.regs[0], 10);
+
+        debugger.remove_breakpoint(0x204);
+        assert_eq!(debugger.run(&mut emu, 10), StopReason::Limit);
+
+        debugger.add_conditional_breakpoint(0x204, Condition::Greater(0x10, 0));
+        assert_eq!(debugger.run(&mut emu, 100), StopReason::Breakpoint(0x204));
+        assert!(Condition::Less(0x1F, 0x20).holds(&[0x10; 16]));
+    }
+
+    #[test]
+    fn test_watchpoints() {
+        let mut emu = counting_program();
+        let mut debugger = Debugger::new();
+
+        debugger.add_watchpoint(0x300, 1, Access::Write);
+        assert_eq!(debugger.run(&mut emu, 100),
+                   StopReason::Watchpoint { pc: 0x20A, addr: 0x300, access: Access::Write });
+        assert_eq!(emu.memory[0x300], 1);
+
+        debugger.remove_watchpoint(0x300);
+        debugger.add_watchpoint(0x301, 4, Access::ReadWrite);
+        assert_eq!(debugger.run(&mut emu, 100),
+                   StopReason::Watchpoint { pc: 0x20C, addr: 0x301, access: Access::Read });
+        assert_eq!(debugger.watchpoints().len(), 1);
+    }
+
+    #[test]
+    fn test_stepping() {
+        let mut emu = counting_program();
+        let mut debugger = Debugger::new();
+
+        assert_eq!(debugger.step(&mut emu), StopReason::Step);
+        assert_eq!(debugger.step_over(&mut emu, 100), StopReason::Step);
+        assert_eq!(emu.pc_reg, 0x204);
+
+        // step over runs the whole subroutine
+        assert_eq!(debugger.step_over(&mut emu, 100), StopReason::Step);
+        assert_eq!(emu.pc_reg, 0x206);
+        assert_eq!(emu.sp_reg, 0);
+        assert_eq!(emu.memory[0x300], 1);
+
+        // step into then run until it returns
+        debugger.step(&mut emu);
+        debugger.step(&mut emu);
+        assert_eq!(debugger.step(&mut emu), StopReason::Step);
+        assert_eq!(emu.pc_reg, 0x20A);
+        assert_eq!(emu.sp_reg, 1);
+        assert_eq!(debugger.run_until_return(&mut emu, 100), StopReason::Returned);
+        assert_eq!(emu.pc_reg, 0x206);
+        assert_eq!(emu.sp_reg, 0);
+
+        // breakpoints still apply inside stepped over calls
+        debugger.add_breakpoint(0x20C);
+        debugger.step(&mut emu);
+        debugger.step(&mut emu);
+        assert_eq!(debugger.step_over(&mut emu, 100), StopReason::Breakpoint(0x20C));
+    }
+
+    #[test]
+    fn test_errors_and_exit() {
+        let mut emu = counting_program();
+        let mut debugger = Debugger::new();
+
+        // returning from the main loop underflows the stack
+        emu.pc_reg = 0x20E;
+        match debugger.run_until_return(&mut emu, 100) {
+            StopReason::Error(err) => assert_eq!(err.pc(), 0x20E),
+            reason => panic!("unexpected stop: {:?}", reason),
+        }
+
+        emu.pc_reg = 0x208;
+        assert_eq!(debugger.run(&mut emu, 100), StopReason::Exited);
+        assert_eq!(debugger.step(&mut emu), StopReason::Exited);
+    }
+}
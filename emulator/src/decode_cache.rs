@@ -0,0 +1,68 @@
+use crate::instruction::Instruction;
+
+/// Instructions already decoded, by address.
+///
+/// Entries are dropped when the memory under them is written, so self
+/// modifying code is decoded again. An instruction also depends on the byte
+/// before the written range, the one starting there.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct DecodeCache {
+    /* raw opcode and instruction, as long as memory */
+    entries: Vec<Option<(u16, Instruction)>>,
+}
+
+impl DecodeCache {
+    /// Opcode and instruction at `addr`, which must be followed by a byte
+    pub(crate) fn fetch(&mut self, memory: &[u8], addr: usize) -> (u16, Instruction) {
+        if self.entries.len() != memory.len() {
+            self.entries = vec![None; memory.len()];
+        }
+        *self.entries[addr].get_or_insert_with(|| {
+            let opcode = (memory[addr] as u16) << 8 | memory[addr + 1] as u16;
+
+            (opcode, Instruction::from(opcode))
+        })
+    }
+    /// `len` bytes were written at `start`
+    pub(crate) fn invalidate(&mut self, start: usize, len: usize) {
+        let end = (start + len).min(self.entries.len());
+
+        for entry in self.entries[start.saturating_sub(1).min(end)..end].iter_mut() {
+            *entry = None;
+        }
+    }
+    /// Memory changed all over
+    pub(crate) fn clear(&mut self) {
+        self.entries.clear();
+    }
+}
+
+#[cfg(test)]
+mod test_decode_cache {
+    use super::*;
+
+    #[test]
+    fn test_invalidate() {
+        let mut memory = vec![0x00, 0xE0, 0x12, 0x00];
+        let mut cache = DecodeCache::default();
+
+        assert_eq!(cache.fetch(&memory, 0), (0x00E0, Instruction::Cls));
+        assert_eq!(cache.fetch(&memory, 1), (0xE012, Instruction::Invalid));
+        assert_eq!(cache.fetch(&memory, 2), (0x1200, Instruction::Jump(0x200)));
+
+        // stale until invalidated
+        memory[2] = 0x00;
+        memory[3] = 0xEE;
+        assert_eq!(cache.fetch(&memory, 2), (0x1200, Instruction::Jump(0x200)));
+        cache.invalidate(2, 2);
+        assert_eq!(cache.fetch(&memory, 1), (0xE000, Instruction::Invalid));
+        assert_eq!(cache.fetch(&memory, 2), (0x00EE, Instruction::Ret));
+        assert_eq!(cache.fetch(&memory, 0), (0x00E0, Instruction::Cls));
+
+        memory.resize(8, 0);
+        assert_eq!(cache.fetch(&memory, 6), (0, Instruction::Sys(0)));
+        cache.invalidate(7, 4);
+        cache.clear();
+        assert_eq!(cache.fetch(&memory, 2), (0x00EE, Instruction::Ret));
+    }
+}
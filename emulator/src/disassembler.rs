@@ -0,0 +1,255 @@
+use crate::instruction::{Address, Instruction};
+use std::collections::BTreeMap;
+use std::fmt;
+
+/* data bytes per listing line */
+const DATA_LINE: usize = 8;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Item {
+    /// Reachable instruction, F000 NNNN keeps its 4 bytes
+    Code { addr: Address, bytes: Vec<u8>, instr: Instruction },
+    /// Bytes never reached by following the control flow
+    Data { addr: Address, bytes: Vec<u8> },
+}
+
+/// Listing of a whole ROM, separating code from data.
+///
+/// Code is found by following jumps, calls and skips from the entry point,
+/// everything else is data. `JP V0, addr` targets can't be known statically,
+/// so only the base address, taken when V0 is 0, is followed.
+#[derive(Debug, Clone)]
+pub struct Disassembly {
+    items: Vec<Item>,
+    labels: BTreeMap<Address, String>,
+}
+
+fn opcode(rom: &[u8], offset: usize) -> Option<u16> {
+    rom.get(offset..offset + 2).map(|bytes| (bytes[0] as u16) << 8 | bytes[1] as u16)
+}
+
+impl Disassembly {
+    /// Disassemble `rom` loaded at `origin`, starting execution at `origin`
+    pub fn new(rom: &[u8], origin: Address) -> Self {
+        Disassembly::with_entry(rom, origin, origin)
+    }
+    /// Disassemble `rom` loaded at `origin`, starting execution at `entry`
+    pub fn with_entry(rom: &[u8], origin: Address, entry: Address) -> Self {
+        let mut starts = vec![false; rom.len()];
+        let mut labels = BTreeMap::new();
+        let mut pending = vec![entry];
+
+        while let Some(addr) = pending.pop() {
+            let offset = match (addr as usize).checked_sub(origin as usize) {
+                Some(offset) if offset < rom.len() && !starts[offset] => offset,
+                _ => continue,
+            };
+            let instr = match opcode(rom, offset) {
+                Some(op) => Instruction::from(op),
+                None => continue,
+            };
+            let next = addr.wrapping_add(Disassembly::length(instr) as u16);
+
+            match instr {
+                Instruction::Invalid => continue,
+                Instruction::Jump(target) => {
+                    labels.entry(target).or_insert_with(|| format!("label_{:03X}", target));
+                    pending.push(target);
+                },
+                Instruction::Call(target) => {
+                    labels.insert(target, format!("sub_{:03X}", target));
+                    pending.push(target);
+                    pending.push(next);
+                },
+                Instruction::SkipValEq(..) | Instruction::SkipValNotEq(..)
+                | Instruction::SkipEq(..) | Instruction::SkipNotEq(..)
+                | Instruction::SkipKeyPressed(_) | Instruction::SkipKeyNotPressed(_) => {
+                    let skipped = opcode(rom, offset + 2).map_or(Instruction::Invalid, Instruction::from);
+
+                    pending.push(next);
+                    pending.push(next.wrapping_add(Disassembly::length(skipped) as u16));
+                },
+                Instruction::LoadAddr(target) => {
+                    labels.entry(target).or_insert_with(|| format!("data_{:03X}", target));
+                    pending.push(next);
+                },
+                Instruction::JumpRel(target) => {
+                    labels.entry(target).or_insert_with(|| format!("label_{:03X}", target));
+                    pending.push(target);
+                },
+                Instruction::Ret | Instruction::Exit => {},
+                _ => pending.push(next),
+            }
+            starts[offset] = true;
+        }
+
+        Disassembly {
+            items: Disassembly::split(rom, origin, &starts, &labels),
+            labels,
+        }
+    }
+    fn length(instr: Instruction) -> usize {
+        match instr {
+            Instruction::LoadLongAddr => 4,
+            _ => 2,
+        }
+    }
+    /// Cut the ROM into instructions and data lines, a data line never
+    /// crosses a label
+    fn split(rom: &[u8], origin: Address, starts: &[bool], labels: &BTreeMap<Address, String>) -> Vec<Item> {
+        let mut items = Vec::new();
+        let mut offset = 0;
+
+        while offset < rom.len() {
+            let addr = origin.wrapping_add(offset as u16);
+
+            if starts[offset] {
+                let instr = Instruction::from(opcode(rom, offset).unwrap());
+                let end = (offset + Disassembly::length(instr)).min(rom.len());
+
+                items.push(Item::Code { addr, bytes: rom[offset..end].to_vec(), instr });
+                offset = end;
+                continue;
+            }
+            let mut end = offset + 1;
+            while end < rom.len() && end - offset < DATA_LINE && !starts[end]
+                && !labels.contains_key(&origin.wrapping_add(end as u16))
+            {
+                end += 1;
+            }
+            items.push(Item::Data { addr, bytes: rom[offset..end].to_vec() });
+            offset = end;
+        }
+        items
+    }
+    pub fn items(&self) -> &[Item] {
+        &self.items
+    }
+    pub fn label(&self, addr: Address) -> Option<&str> {
+        self.labels.get(&addr).map(|label| label.as_str())
+    }
+    /// Instruction text, with known addresses replaced by their label
+    fn format_instr(&self, instr: Instruction, bytes: &[u8]) -> String {
+        let label = |addr: Address| self.label(addr).map_or_else(|| format!("0x{:03X}", addr), String::from);
+
+        match instr {
+            Instruction::Jump(addr) => format!("JP {}", label(addr)),
+            Instruction::Call(addr) => format!("CALL {}", label(addr)),
+            Instruction::LoadAddr(addr) => format!("LD I, {}", label(addr)),
+            Instruction::JumpRel(addr) => format!("JP V0, {}", label(addr)),
+            Instruction::LoadLongAddr if bytes.len() == 4 =>
+                format!("LD I, LONG 0x{:04X}", (bytes[2] as u16) << 8 | bytes[3] as u16),
+            _ => instr.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for Disassembly {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for item in self.items.iter() {
+            let (addr, bytes) = match item {
+                Item::Code { addr, bytes, .. } | Item::Data { addr, bytes } => (*addr, bytes),
+            };
+            if let Some(label) = self.label(addr) {
+                writeln!(f, "{}:", label)?;
+            }
+            let raw: Vec<String> = bytes.iter().map(|byte| format!("{:02X}", byte)).collect();
+            let text = match item {
+                Item::Code { instr, .. } => self.format_instr(*instr, bytes),
+                Item::Data { .. } => {
+                    let values: Vec<String> = bytes.iter().map(|byte| format!("0x{:02X}", byte)).collect();
+                    format!("DB {}", values.join(", "))
+                },
+            };
+            writeln!(f, "{:03X}: {:<24}{}", addr, raw.join(" "), text)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test_disassembler {
+    use super::*;
+
+    #[test]
+    fn test_code_and_data() {
+        let mut rom = Instruction::assemble(&[
+            Instruction::LoadAddr(0x20C),
+            Instruction::Call(0x208),
+            Instruction::Jump(0x202),
+            Instruction::Invalid,
+            Instruction::Draw(0, 0, 2),     // 0x208
+            Instruction::Ret,
+        ]);
+        rom.extend_from_slice(&[0xF0, 0x90]);
+
+        let disasm = Disassembly::new(&rom, 0x200);
+        assert_eq!(disasm.items(), &[
+            Item::Code { addr: 0x200, bytes: vec![0xA2, 0x0C], instr: Instruction::LoadAddr(0x20C) },
+            Item::Code { addr: 0x202, bytes: vec![0x22, 0x08], instr: Instruction::Call(0x208) },
+            Item::Code { addr: 0x204, bytes: vec![0x12, 0x02], instr: Instruction::Jump(0x202) },
+            // unreachable bytes stay data
+            Item::Data { addr: 0x206, bytes: vec![0x00, 0x00] },
+            Item::Code { addr: 0x208, bytes: vec![0xD0, 0x02], instr: Instruction::Draw(0, 0, 2) },
+            Item::Code { addr: 0x20A, bytes: vec![0x00, 0xEE], instr: Instruction::Ret },
+            Item::Data { addr: 0x20C, bytes: vec![0xF0, 0x90] },
+        ][..]);
+        assert_eq!(disasm.label(0x202), Some("label_202"));
+        assert_eq!(disasm.label(0x208), Some("sub_208"));
+        assert_eq!(disasm.label(0x20C), Some("data_20C"));
+        assert_eq!(disasm.label(0x206), None);
+
+        assert_eq!(disasm.to_string(), "\
+200: A2 0C                   LD I, data_20C
+label_202:
+202: 22 08                   CALL sub_208
+204: 12 02                   JP label_202
+206: 00 00                   DB 0x00, 0x00
+sub_208:
+208: D0 02                   DRW V0, V0, 2
+20A: 00 EE                   RET
+data_20C:
+20C: F0 90                   DB 0xF0, 0x90
+");
+    }
+
+    #[test]
+    fn test_skips_and_long_addr() {
+        let mut rom = Instruction::assemble(&[
+            Instruction::SkipValEq(0, 1),
+            Instruction::LoadLongAddr,
+        ]);
+        rom.extend_from_slice(&[0x03, 0x00]);
+        rom.extend(Instruction::assemble(&[
+            Instruction::Exit,
+            Instruction::Invalid,
+        ]));
+
+        let disasm = Disassembly::new(&rom, 0x200);
+        assert_eq!(disasm.items(), &[
+            Item::Code { addr: 0x200, bytes: vec![0x30, 0x01], instr: Instruction::SkipValEq(0, 1) },
+            Item::Code { addr: 0x202, bytes: vec![0xF0, 0x00, 0x03, 0x00], instr: Instruction::LoadLongAddr },
+            Item::Code { addr: 0x206, bytes: vec![0x00, 0xFD], instr: Instruction::Exit },
+            Item::Data { addr: 0x208, bytes: vec![0x00, 0x00] },
+        ][..]);
+        assert!(disasm.to_string().contains("LD I, LONG 0x0300"));
+    }
+
+    #[test]
+    fn test_jump_rel() {
+        let rom = Instruction::assemble(&[
+            Instruction::JumpRel(0x204),
+            Instruction::Cls,               // never falls through
+            Instruction::Ret,               // 0x204
+        ]);
+
+        let disasm = Disassembly::new(&rom, 0x200);
+        assert_eq!(disasm.items(), &[
+            Item::Code { addr: 0x200, bytes: vec![0xB2, 0x04], instr: Instruction::JumpRel(0x204) },
+            Item::Data { addr: 0x202, bytes: vec![0x00, 0xE0] },
+            Item::Code { addr: 0x204, bytes: vec![0x00, 0xEE], instr: Instruction::Ret },
+        ][..]);
+        assert_eq!(disasm.label(0x204), Some("label_204"));
+        assert!(disasm.to_string().contains("JP V0, label_204"));
+    }
+}
@@ -0,0 +1,302 @@
+use crate::instruction::{Instruction, Register};
+use crate::Emulator;
+use std::rc::Rc;
+
+/// Instruction that only changes registers, timers or flags, with its
+/// operands packed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Op {
+    LoadVal(u8, u8),
+    AddVal(u8, u8),
+    Load(u8, u8),
+    Or(u8, u8),
+    And(u8, u8),
+    Xor(u8, u8),
+    Add(u8, u8),
+    Sub(u8, u8),
+    ShiftRight(u8, u8),
+    SubN(u8, u8),
+    ShiftLeft(u8, u8),
+    LoadAddr(u16),
+    Random(u8, u8),
+    LoadDelayTimer(u8),
+    SetDelayTimer(u8),
+    SetSoundTimer(u8),
+    AddI(u8),
+    LoadSprite(u8),
+    LoadBigSprite(u8),
+    StoreFlags(u8),
+    LoadFlags(u8),
+    SelectPlanes(u8),
+    SetPitch(u8),
+}
+
+impl Op {
+    /// Op for an instruction that can't jump, skip, draw, fault or touch
+    /// memory
+    fn translate(instr: Instruction) -> Option<Op> {
+        let reg = |reg: Register| reg as u8;
+
+        Some(match instr {
+            Instruction::LoadVal(x, val) => Op::LoadVal(reg(x), val),
+            Instruction::AddVal(x, val) => Op::AddVal(reg(x), val),
+            Instruction::Load(x, y) => Op::Load(reg(x), reg(y)),
+            Instruction::Or(x, y) => Op::Or(reg(x), reg(y)),
+            Instruction::And(x, y) => Op::And(reg(x), reg(y)),
+            Instruction::Xor(x, y) => Op::Xor(reg(x), reg(y)),
+            Instruction::Add(x, y) => Op::Add(reg(x), reg(y)),
+            Instruction::Sub(x, y) => Op::Sub(reg(x), reg(y)),
+            Instruction::ShiftRight(x, y) => Op::ShiftRight(reg(x), reg(y)),
+            Instruction::SubN(x, y) => Op::SubN(reg(x), reg(y)),
+            Instruction::ShiftLeft(x, y) => Op::ShiftLeft(reg(x), reg(y)),
+            Instruction::LoadAddr(addr) => Op::LoadAddr(addr),
+            Instruction::Random(x, val) => Op::Random(reg(x), val),
+            Instruction::LoadDelayTimer(x) => Op::LoadDelayTimer(reg(x)),
+            Instruction::SetDelayTimer(x) => Op::SetDelayTimer(reg(x)),
+            Instruction::SetSoundTimer(x) => Op::SetSoundTimer(reg(x)),
+            Instruction::AddI(x) => Op::AddI(reg(x)),
+            Instruction::LoadSprite(x) => Op::LoadSprite(reg(x)),
+            Instruction::LoadBigSprite(x) => Op::LoadBigSprite(reg(x)),
+            Instruction::StoreFlags(x) => Op::StoreFlags(reg(x)),
+            Instruction::LoadFlags(x) => Op::LoadFlags(reg(x)),
+            Instruction::SelectPlanes(planes) => Op::SelectPlanes(planes),
+            Instruction::SetPitch(x) => Op::SetPitch(reg(x)),
+            _ => return None,
+        })
+    }
+}
+
+/// Straight-line code translated to ops, by start address.
+///
+/// A block stops before the first instruction that can't be translated,
+/// which the interpreter runs. Blocks are dropped when the memory they were
+/// translated from is written.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct BlockCache {
+    blocks: Vec<Option<Rc<[Op]>>>,
+    /* number of blocks translated from each byte */
+    covered: Vec<u16>,
+    /* bytes of the longest block */
+    longest: usize,
+}
+
+impl BlockCache {
+    /// Block starting at `addr`, translated the first time it is needed
+    pub(crate) fn block(&mut self, memory: &[u8], addr: usize) -> Rc<[Op]> {
+        if self.blocks.len() != memory.len() {
+            self.blocks = vec![None; memory.len()];
+            self.covered = vec![0; memory.len()];
+            self.longest = 0;
+        }
+        if let Some(block) = &self.blocks[addr] {
+            return block.clone();
+        }
+
+        let mut ops = Vec::new();
+        let mut end = addr;
+        while let Some(bytes) = memory.get(end..end + 2) {
+            match Op::translate(Instruction::from((bytes[0] as u16) << 8 | bytes[1] as u16)) {
+                Some(op) => ops.push(op),
+                None => break,
+            }
+            end += 2;
+        }
+        self.covered[addr..end].iter_mut().for_each(|count| *count += 1);
+        self.longest = self.longest.max(end - addr);
+
+        let block: Rc<[Op]> = ops.into();
+        self.blocks[addr] = Some(block.clone());
+        block
+    }
+    /// `len` bytes were written at `start`
+    pub(crate) fn invalidate(&mut self, start: usize, len: usize) {
+        let end = (start + len).min(self.covered.len());
+        let start = start.min(end);
+
+        if self.covered[start..end].iter().all(|count| *count == 0) {
+            return;
+        }
+        for addr in start.saturating_sub(self.longest)..end {
+            let block_end = match &self.blocks[addr] {
+                Some(block) => addr + block.len() * 2,
+                None => continue,
+            };
+            if block_end > start {
+                self.covered[addr..block_end].iter_mut().for_each(|count| *count -= 1);
+                self.blocks[addr] = None;
+            }
+        }
+    }
+    /// Memory changed all over
+    pub(crate) fn clear(&mut self) {
+        self.blocks.clear();
+        self.covered.clear();
+    }
+}
+
+impl Emulator {
+    /// Run up to `max` instructions from PC as one block, giving how many
+    /// ran. Timers are only updated before the block, the clock doesn't
+    /// move during `run_frame`.
+    pub(crate) fn run_block(&mut self, max: usize) -> usize {
+        let pc = self.pc_reg as usize;
+        if pc + 2 > self.memory.len() {
+            return 0;
+        }
+        let block = self.blocks.block(&self.memory, pc);
+        let count = block.len().min(max);
+
+        if count > 0 {
+            self.tick();
+            for op in block[..count].iter() {
+                self.exec_op(*op);
+            }
+            self.pc_reg = self.pc_reg.wrapping_add(count as u16 * 2);
+        }
+        count
+    }
+    fn exec_op(&mut self, op: Op) {
+        match op {
+            Op::LoadVal(x, val) => self.load_val(x as Register, val),
+            Op::AddVal(x, val) => self.add_val(x as Register, val),
+            Op::Load(x, y) => self.load(x as Register, y as Register),
+            Op::Or(x, y) => self.or(x as Register, y as Register),
+            Op::And(x, y) => self.and(x as Register, y as Register),
+            Op::Xor(x, y) => self.xor(x as Register, y as Register),
+            Op::Add(x, y) => self.add(x as Register, y as Register),
+            Op::Sub(x, y) => self.sub(x as Register, y as Register),
+            Op::ShiftRight(x, y) => self.shr(x as Register, y as Register),
+            Op::SubN(x, y) => self.subn(x as Register, y as Register),
+            Op::ShiftLeft(x, y) => self.shl(x as Register, y as Register),
+            Op::LoadAddr(addr) => self.load_addr(addr),
+            Op::Random(x, val) => self.rand(x as Register, val),
+            Op::LoadDelayTimer(x) => self.dt_to_vx(x as Register),
+            Op::SetDelayTimer(x) => self.vx_to_dt(x as Register),
+            Op::SetSoundTimer(x) => self.load_st(x as Register),
+            Op::AddI(x) => self.addi(x as Register),
+            Op::LoadSprite(x) => self.loadi_sprite(x as Register),
+            Op::LoadBigSprite(x) => self.loadi_big_sprite(x as Register),
+            Op::StoreFlags(x) => self.regs_to_rpl(x as Register),
+            Op::LoadFlags(x) => self.rpl_to_regs(x as Register),
+            Op::SelectPlanes(planes) => self.select_planes(planes),
+            Op::SetPitch(x) => self.set_pitch(x as Register),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test_dynarec {
+    use super::*;
+    use crate::clock::ManualClock;
+    use crate::quirks::Quirks;
+    use crate::DisplaySize;
+
+    #[test]
+    fn test_block_cache() {
+        let mut memory = Instruction::assemble(&[
+            Instruction::LoadVal(0, 1),
+            Instruction::AddVal(0, 2),
+            Instruction::Jump(0x000),
+            Instruction::LoadVal(1, 3),
+        ]);
+        let mut cache = BlockCache::default();
+
+        assert_eq!(&cache.block(&memory, 0)[..], &[Op::LoadVal(0, 1), Op::AddVal(0, 2)]);
+        assert_eq!(&cache.block(&memory, 2)[..], &[Op::AddVal(0, 2)]);
+        assert!(cache.block(&memory, 4).is_empty());
+        assert_eq!(cache.covered, vec![1, 1, 2, 2, 0, 0, 0, 0]);
+
+        // writing the jump leaves both blocks alone
+        memory[4..6].copy_from_slice(&Instruction::Load(1, 0).asm());
+        cache.invalidate(4, 2);
+        assert_eq!(cache.block(&memory, 0).len(), 2);
+
+        memory[3] = 5;
+        cache.invalidate(3, 1);
+        assert_eq!(cache.covered, vec![0; 8]);
+        assert_eq!(&cache.block(&memory, 0)[..], &[Op::LoadVal(0, 1), Op::AddVal(0, 5), Op::Load(1, 0), Op::LoadVal(1, 3)]);
+        assert!(cache.blocks[2].is_none());
+    }
+
+    /// Run the same ROM interpreted and translated, comparing everything
+    /// after each frame
+    fn compare(rom: &[u8], instructions_per_frame: usize, frames: usize) {
+        let clock = ManualClock::new();
+        let mut emus: Vec<Emulator> = [false, true].iter().map(|dynarec| {
+            let mut emu = Emulator::new(DisplaySize::Basic64x32, Quirks::default(), 42);
+            emu.set_clock(Box::new(clock.clone()));
+            emu.instructions_per_frame = instructions_per_frame;
+            emu.dynarec = *dynarec;
+            emu.load_rom(rom);
+            emu
+        }).collect();
+
+        for frame in 0..frames {
+            let results: Vec<_> = emus.iter_mut().map(|emu| emu.run_frame()).collect();
+            assert_eq!(results[0], results[1], "frame {}", frame);
+            assert_eq!(emus[0].cpu_state(), emus[1].cpu_state(), "frame {}", frame);
+            assert_eq!(emus[0].screen, emus[1].screen, "frame {}", frame);
+            assert_eq!(emus[0].memory, emus[1].memory, "frame {}", frame);
+            clock.advance(1);
+        }
+    }
+
+    #[test]
+    fn test_same_as_interpreter() {
+        let rom = Instruction::assemble(&[
+            Instruction::LoadVal(0, 0),
+            Instruction::LoadVal(5, 30),
+            Instruction::SetDelayTimer(5),
+            Instruction::AddVal(0, 3),          // 0x206
+            Instruction::Random(1, 0x3F),
+            Instruction::Load(2, 0),
+            Instruction::ShiftRight(2, 2),
+            Instruction::Add(2, 1),
+            Instruction::Xor(3, 2),
+            Instruction::LoadSprite(2),
+            Instruction::Draw(1, 2, 5),
+            Instruction::LoadDelayTimer(4),
+            Instruction::LoadAddr(0x300),
+            Instruction::Bcd(4),
+            Instruction::LoadRegs(2),
+            Instruction::SkipValEq(4, 0),
+            Instruction::Jump(0x206),
+            Instruction::Jump(0x200),
+        ]);
+
+        // blocks split across frames
+        compare(&rom, 7, 100);
+        compare(&rom, 15, 100);
+    }
+
+    #[test]
+    fn test_self_modifying() {
+        // rewrites the constant of the AddVal at 0x20A through V0, the new
+        // value must be used by the block the next time around
+        let rom = Instruction::assemble(&[
+            Instruction::LoadVal(0, 0x71),
+            Instruction::LoadVal(1, 1),
+            Instruction::LoadVal(2, 0),
+            Instruction::LoadAddr(0x20A),
+            Instruction::LoadVal(3, 0),         // 0x208
+            Instruction::AddVal(1, 1),          // 0x20A, rewritten
+            Instruction::AddVal(2, 1),
+            Instruction::Load(3, 1),
+            Instruction::StoreRegs(1),
+            Instruction::LoadAddr(0x20A),
+            Instruction::SkipValEq(2, 40),
+            Instruction::Jump(0x208),
+            Instruction::Jump(0x218),
+        ]);
+        compare(&rom, 5, 30);
+        compare(&rom, 1000, 2);
+
+        let mut emu = Emulator::new(DisplaySize::Basic64x32, Quirks::default(), 0);
+        emu.dynarec = true;
+        emu.instructions_per_frame = 1000;
+        emu.load_rom(&rom);
+        emu.run_frame().unwrap();
+        assert_eq!(emu.pc_reg, 0x218);
+        assert_eq!(emu.memory[0x20A..0x20C], [0x71, emu.regs[1]]);
+    }
+}
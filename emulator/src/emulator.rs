@@ -1,11 +1,38 @@
+pub mod analyzer;
+pub mod builder;
+pub mod cdp1802;
+pub mod clock;
+pub mod debugger;
+mod decode_cache;
+mod dynarec;
+pub mod disassembler;
+pub mod error;
+pub mod framebuffer;
+pub mod inspect;
 pub mod instruction;
+pub mod options;
+pub mod preset;
+pub mod profile;
+pub mod quirks;
+pub mod random;
+pub mod romdb;
+pub mod rewind;
+pub mod state;
+pub mod timing;
 pub mod ui;
 
-extern crate rand;
-
+use crate::decode_cache::DecodeCache;
+use crate::dynarec::BlockCache;
+use crate::error::{ConfigError, EmulatorError, Fault};
 use crate::instruction::{Instruction, Register, Value, Address};
-use std::time::{Duration, SystemTime};
-
+use crate::clock::{Clock, ManualClock};
+use crate::framebuffer::{Framebuffer, Rect};
+use crate::profile::Profile;
+use crate::quirks::Quirks;
+use crate::random::{RandomSource, XorShift};
+use crate::timing::Timing;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum DisplaySize {
     Basic64x32,
     Eti64x48,
@@ -13,6 +40,37 @@ pub enum DisplaySize {
     Hp128x64,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    /// CHIP-8 and SUPER-CHIP, 4 KiB of memory
+    Chip8,
+    /// two-page hires CHIP-8, 64x64 screen and programs starting at 0x2C0
+    HiresChip8,
+    /// XO-CHIP, 64 KiB of memory
+    XoChip,
+}
+
+impl Mode {
+    /// Guess the mode of a ROM, hires CHIP-8 programs begin by jumping to
+    /// the interpreter patch at 0x260
+    pub fn detect(rom: &[u8]) -> Mode {
+        if rom.starts_with(&[0x12, 0x60]) {
+            Mode::HiresChip8
+        } else {
+            Mode::Chip8
+        }
+    }
+    fn memory_size(self) -> usize {
+        match self {
+            Mode::Chip8 | Mode::HiresChip8 => 0x1000,
+            Mode::XoChip => 0x10000,
+        }
+    }
+}
+
+/* small and big fonts together */
+const FONT_SIZE: usize = SPRITES.len() + BIG_SPRITES.len();
+
 const SPRITES : [u8; 5 * 16] = [
     0xF0, 0x90, 0x90, 0x90, 0xF0,
     0x20, 0x60, 0x20, 0x20, 0x70,
@@ -32,11 +90,48 @@ const SPRITES : [u8; 5 * 16] = [
     0xF0, 0x80, 0xF0, 0x80, 0x80
 ];
 
-// 60Hz timers = 16ms period
-const TICK :Duration = Duration::from_nanos(1000 * 1000 * 1000 / 60);
+/* SUPER-CHIP 8x10 hex digits, stored right after the small ones */
+const BIG_SPRITES : [u8; 10 * 16] = [
+    0xFF, 0xFF, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF,
+    0x18, 0x78, 0x78, 0x18, 0x18, 0x18, 0x18, 0x18, 0xFF, 0xFF,
+    0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF,
+    0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF,
+    0xC3, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, 0x03, 0x03, 0x03, 0x03,
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF,
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF,
+    0xFF, 0xFF, 0x03, 0x03, 0x06, 0x0C, 0x18, 0x18, 0x18, 0x18,
+    0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF,
+    0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF,
+    0x7E, 0xFF, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, 0xC3, 0xC3, 0xC3,
+    0xFC, 0xFC, 0xC3, 0xC3, 0xFC, 0xFC, 0xC3, 0xC3, 0xFC, 0xFC,
+    0x3C, 0xFF, 0xC3, 0xC0, 0xC0, 0xC0, 0xC0, 0xC3, 0xFF, 0x3C,
+    0xFC, 0xFE, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xFE, 0xFC,
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF,
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xC0, 0xC0
+];
+
+// about 900 instructions per second
+pub const INSTRUCTIONS_PER_FRAME: usize = 15;
+
+/// What happened during a `run_frame`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Frame {
+    /// the screen changed since the last frame
+    pub redraw: bool,
+    /// the sound timer was active
+    pub sound: bool,
+    /// the program is blocked on FX0A until a key is pressed
+    pub waiting_for_key: bool,
+}
 
 pub struct Emulator {
-  memory: [u8; 4096],
+  mode: Mode,
+  profile: Profile,
+  memory: Vec<u8>,
+  /* instructions decoded so far, dropped when memory is written */
+  decoded: DecodeCache,
+  /* straight-line code translated so far, also dropped on writes */
+  blocks: BlockCache,
   /* internal registers */
   pc_reg: u16,
   sp_reg: u8,
@@ -53,20 +148,53 @@ pub struct Emulator {
   /* delay timer and sound timer */
   dt_reg: u8,
   st_reg: u8,
-  last_tick: SystemTime,
-  frequency: u64, // Hz
+  /* 60Hz frame of the clock when the timers were last decremented */
+  last_frame: u64,
+  /* a 60Hz tick happened since the last sprite was drawn */
+  vblank: bool,
 
-  /* return addr stack */
+  /* return addr stack, calls deeper than stack_depth overflow */
   stack: [u16; 16],
+  stack_depth: usize,
+
+  /* SUPER-CHIP RPL user flags */
+  rpl: [u8; 16],
+
+  /* XO-CHIP audio pattern buffer and playback pitch */
+  audio_pattern: [u8; 16],
+  pitch: u8,
 
   /* screen, size may vary depending on configuration */
   pub resolution: (usize, usize),
-  pub screen: Vec<Vec<bool>>,
+  /* each pixel is lit on some of the planes */
+  pub screen: Framebuffer,
+  /* bitmask of the planes affected by drawing instructions */
+  planes: u8,
   pub redraw: bool,
+  /* the program executed 00FD */
+  pub exited: bool,
+
+  /* behaviour of ambiguous opcodes, may be changed while running */
+  pub quirks: Quirks,
+  /* instructions executed by each run_frame */
+  pub instructions_per_frame: usize,
+  pub timing: Timing,
+  /* run_frame runs straight-line code as translated blocks */
+  pub dynarec: bool,
+  /* VIP machine cycles left in the current frame, negative when the last
+   * instruction ran over */
+  vip_cycles: i32,
+
+  /* random numbers for CXNN */
+  rng: Box<dyn RandomSource>,
+  /* drives the 60Hz timers */
+  clock: Box<dyn Clock>,
 }
 
 impl Emulator {
-    pub fn new_with_time(display: DisplaySize, now: SystemTime) -> Self where Self: Sized {
+    /// Identical seeds and inputs give identical runs. The clock does not
+    /// move until one is given with `set_clock`.
+    pub fn new(display: DisplaySize, quirks: Quirks, seed: u64) -> Self where Self: Sized {
         let resolution = Emulator::get_resolution(display);
 
         let mut emu = Emulator {
@@ -76,22 +204,45 @@ impl Emulator {
 
             dt_reg: 0,
             st_reg: 0,
-            last_tick: now,
-            frequency: 6000,
-
-            memory: [0; 4096],
+            last_frame: 0,
+            vblank: false,
+
+            mode: Mode::Chip8,
+            profile: Profile { display, ..Profile::chip8() },
+            memory: vec![0; Mode::Chip8.memory_size()],
+            decoded: DecodeCache::default(),
+            blocks: BlockCache::default(),
             regs: [0; 16],
             stack: [0; 16],
+            stack_depth: 16,
+            rpl: [0; 16],
+            audio_pattern: [0; 16],
+            pitch: 64,
             resolution,
-            screen: vec![vec![false; resolution.1]; resolution.0],
+            screen: Framebuffer::new(resolution.0, resolution.1),
+            planes: 0x1,
             redraw: false,
+            exited: false,
             keys: [false; 16],
+            quirks,
+            instructions_per_frame: INSTRUCTIONS_PER_FRAME,
+            timing: Timing::default(),
+            dynarec: false,
+            vip_cycles: 0,
+            rng: Box::new(XorShift::new(seed)),
+            clock: Box::new(ManualClock::new()),
         };
         emu.init_sprites();
         emu
     }
-    pub fn new(display: DisplaySize) -> Self where Self: Sized {
-        Emulator::new_with_time(display, SystemTime::now())
+    /// Replace the random number source
+    pub fn set_random_source(&mut self, rng: Box<dyn RandomSource>) {
+        self.rng = rng;
+    }
+    /// Replace the clock driving the timers
+    pub fn set_clock(&mut self, clock: Box<dyn Clock>) {
+        self.last_frame = clock.frame();
+        self.clock = clock;
     }
     fn get_resolution(display: DisplaySize) -> (usize, usize) {
         match display {
@@ -104,33 +255,104 @@ impl Emulator {
     fn set_screen_mode(&mut self, resolution: DisplaySize) {
         let resolution = Emulator::get_resolution(resolution);
         self.resolution = resolution;
-        self.screen = vec![vec![false; resolution.1]; resolution.0]
+        self.screen = Framebuffer::new(resolution.0, resolution.1);
     }
-    fn init_sprites(&mut self) {
-        for (idx, x) in SPRITES.iter().enumerate() {
-            self.memory[idx] = *x;
+    /// Switch between CHIP-8 variants, resizing memory and the screen accordingly
+    pub fn set_mode(&mut self, mode: Mode) {
+        if mode == Mode::HiresChip8 {
+            self.replace_profile(Profile::hires_chip8());
+        } else if self.mode == Mode::HiresChip8 {
+            self.replace_profile(Profile::chip8());
         }
+        self.mode = mode;
+        self.memory.resize(mode.memory_size(), 0);
+        self.memory_replaced();
+    }
+    pub fn mode(&self) -> Mode {
+        self.mode
+    }
+    /// Change the memory layout and screen, the font is moved right away and
+    /// the new entry point is used by the next `load_rom` or `reset`
+    pub fn set_profile(&mut self, profile: Profile) -> Result<(), ConfigError> {
+        profile.check(self.memory.len())?;
+        self.replace_profile(profile);
+        Ok(())
+    }
+    /* profile already known to fit in memory */
+    pub(crate) fn replace_profile(&mut self, profile: Profile) {
+        self.clear_sprites();
+        self.profile = profile;
+        self.init_sprites();
+        self.memory_replaced();
+        self.set_screen_mode(profile.display);
+    }
+    pub fn profile(&self) -> Profile {
+        self.profile
+    }
+    /// XO-CHIP 1-bit audio pattern, played while the sound timer is active
+    pub fn audio_pattern(&self) -> &[u8; 16] {
+        &self.audio_pattern
+    }
+    /// Playback rate of the audio pattern in bits per second
+    pub fn audio_rate(&self) -> f64 {
+        4000.0 * 2f64.powf((self.pitch as f64 - 64.0) / 48.0)
+    }
+    fn big_sprites_addr(&self) -> usize {
+        self.profile.font_address as usize + SPRITES.len()
+    }
+    fn init_sprites(&mut self) {
+        let start = self.profile.font_address as usize;
+
+        self.memory[start..start + SPRITES.len()].copy_from_slice(&SPRITES);
+        let start = self.big_sprites_addr();
+        let len = self.profile.font.big_glyphs() * 10;
+        self.memory[start..start + len].copy_from_slice(&BIG_SPRITES[..len]);
+    }
+    fn clear_sprites(&mut self) {
+        let start = self.profile.font_address as usize;
+
+        self.memory[start..start + FONT_SIZE].iter_mut().for_each(|x| *x = 0);
     }
     pub fn reset(&mut self) {
-        self.pc_reg = 0x200;
+        self.pc_reg = self.profile.entry_point;
         self.sp_reg = 0;
         self.regs[0xF] = 0;
-        self.screen.iter_mut().for_each(|x| {
-            x.iter_mut().for_each(|y| *y = false);
-        });
+        self.screen.clear(0x3);
+        self.planes = 0x1;
         self.memory.iter_mut().for_each(|x| *x = 0);
         self.init_sprites();
+        self.memory_replaced();
         self.regs.iter_mut().for_each(|x| *x = 0);
         self.i_reg = 0;
         self.dt_reg = 0;
         self.st_reg = 0;
+        self.vblank = false;
+        self.vip_cycles = 0;
         self.stack.iter_mut().for_each(|x| *x = 0);
+        self.rpl.iter_mut().for_each(|x| *x = 0);
+        self.audio_pattern.iter_mut().for_each(|x| *x = 0);
+        self.pitch = 64;
+        self.exited = false;
         self.keys.iter_mut().for_each(|x| *x = false);
     }
+    /// Load a ROM and start it, as the profile says. Whatever doesn't fit in
+    /// memory is dropped.
+    pub fn load_rom(&mut self, rom: &[u8]) {
+        let start = (self.profile.load_address as usize).min(self.memory.len());
+        let len = rom.len().min(self.memory.len() - start);
+
+        self.memory[start..start + len].copy_from_slice(&rom[..len]);
+        self.memory_written(start, len);
+        self.pc_reg = self.profile.entry_point;
+    }
+    /// Copy data at the profile's load address
     pub fn mem_load_bin(&mut self, data: Vec<u8>) {
+        let start = self.profile.load_address as usize;
+
         for (idx, x) in data.iter().enumerate() {
-            self.memory[0x200 + idx ] = *x;
+            self.memory[start + idx ] = *x;
         }
+        self.memory_written(start, data.len());
     }
     #[cfg(test)]
     fn mem_load_instr(&mut self, data: Vec<Instruction>) {
@@ -141,66 +363,125 @@ impl Emulator {
     }
 
     fn tick(&mut self) {
-        let mut elapsed = self.last_tick.elapsed().unwrap();
+        let frame = self.clock.frame();
 
-        if elapsed > TICK {
-            while elapsed > TICK {
-                if self.st_reg > 0 {
-                    self.st_reg -= 1;
-                }
-                if self.dt_reg > 0 {
-                    self.dt_reg -= 1;
+        // a clock going backwards is ignored until it catches up
+        if frame > self.last_frame {
+            self.decrement_timers(frame - self.last_frame);
+            self.last_frame = frame;
+        }
+    }
+    fn decrement_timers(&mut self, frames: u64) {
+        let frames = frames.min(255) as u8;
+
+        self.st_reg = self.st_reg.saturating_sub(frames);
+        self.dt_reg = self.dt_reg.saturating_sub(frames);
+        self.vblank = true;
+    }
+    /// Instruction at PC, the next one to be executed
+    pub fn next_instruction(&self) -> Instruction {
+        let pc = self.pc_reg as usize;
+
+        match self.memory.get(pc..pc + 2) {
+            Some(bytes) => Instruction::from((bytes[0] as u16) << 8 | bytes[1] as u16),
+            None => Instruction::Invalid,
+        }
+    }
+    fn run_vip_cycles(&mut self) -> Result<(), EmulatorError> {
+        self.vip_cycles += timing::VIP_CYCLES_PER_FRAME;
+
+        while self.vip_cycles > 0 && !self.exited {
+            let instr = self.next_instruction();
+
+            // the rest of the frame is spent waiting for the display interrupt
+            if let Instruction::Draw(_, _, _) = instr {
+                if !self.vblank {
+                    self.vip_cycles = 0;
+                    break;
                 }
-                elapsed -= TICK;
             }
-            self.last_tick = SystemTime::now();
+            self.cpu_one_cycle()?;
+            self.vip_cycles -= timing::vip_cycles(instr);
         }
+        Ok(())
     }
-    fn tick_with_time(&mut self, now: SystemTime) {
-        let mut elapsed = now.duration_since(self.last_tick).unwrap();
-        if elapsed > TICK {
-            while elapsed > TICK {
-                if self.st_reg > 0 {
-                    self.st_reg -= 1;
+    /// Run a frame worth of instructions, depending on `timing`, then decrement the timers
+    /// once, meant to be called 60 times per second. The clock given to
+    /// `set_clock` should be left alone when using it.
+    pub fn run_frame(&mut self) -> Result<Frame, EmulatorError> {
+        match self.timing {
+            Timing::Instructions => {
+                let mut left = self.instructions_per_frame;
+
+                while left > 0 && !self.exited {
+                    if self.dynarec {
+                        left -= self.run_block(left);
+                        if left == 0 {
+                            break;
+                        }
+                    }
+                    self.cpu_one_cycle()?;
+                    left -= 1;
                 }
-                if self.dt_reg > 0 {
-                    self.dt_reg -= 1;
-                }
-                elapsed -= TICK;
-            }
-            self.last_tick = now;
+            },
+            Timing::Vip => self.run_vip_cycles()?,
         }
-    } 
-    pub fn cpu_one_cycle_with_time(&mut self, now: SystemTime) {
-        let instr = self.cpu_load();
+        let sound = self.st_reg > 0;
+        self.decrement_timers(1);
 
-        self.tick_with_time(now);
-        self.cpu_exec(instr);
+        let frame = Frame {
+            redraw: self.redraw,
+            sound,
+            waiting_for_key: !self.exited && matches!(self.next_instruction(), Instruction::LoadKey(_)),
+        };
+        self.redraw = false;
+        Ok(frame)
     }
-    pub fn cpu_one_cycle(&mut self) {
-        let instr = self.cpu_load();
+    pub fn cpu_one_cycle(&mut self) -> Result<(), EmulatorError> {
+        if self.exited {
+            return Ok(());
+        }
+        let instr = self.cpu_load()?;
 
         self.tick();
-        self.cpu_exec(instr);
+        self.cpu_exec(instr)
+    }
+    /// `len` bytes of memory were written at `start`, code there must be
+    /// decoded again
+    fn memory_written(&mut self, start: usize, len: usize) {
+        self.decoded.invalidate(start, len);
+        self.blocks.invalidate(start, len);
     }
-    fn cpu_load(&mut self) -> u16 {
-        let instr : u16 = ( (self.memory[self.pc_reg as usize] as u16) << 8
-                          | (self.memory[self.pc_reg as usize + 1] as u16)).into();
+    /// Memory was written all over
+    fn memory_replaced(&mut self) {
+        self.decoded.clear();
+        self.blocks.clear();
+    }
+    fn cpu_load(&mut self) -> Result<(u16, Instruction), EmulatorError> {
+        let pc = self.pc_reg;
+
+        self.check_mem(pc as usize, 2).map_err(|fault| fault.at(pc, 0))?;
+        let instr = self.decoded.fetch(&self.memory, pc as usize);
 
         self.inc_pc();
-        instr
+        Ok(instr)
     }
-    fn cpu_exec(&mut self, instr: u16) {
-        match Instruction::from(instr) {
-            /* 2 special cases */
-            Instruction::Sys(0x230) => self.cls(), // TODO: test
-            Instruction::Jump(0x1260) if self.pc_reg == 0x202 => self.hires(), // TODO: test
+    fn cpu_exec(&mut self, (opcode, instr): (u16, Instruction)) -> Result<(), EmulatorError> {
+        let pc = self.pc_reg.wrapping_sub(2);
+
+        self.exec(instr).map_err(|fault| fault.at(pc, opcode))
+    }
+    fn exec(&mut self, instr: Instruction) -> Result<(), Fault> {
+        match instr {
+            /* hires CHIP-8 interpreter patch */
+            Instruction::Sys(0x230) if self.mode == Mode::HiresChip8 => self.cls(),
 
             Instruction::Cls => self.cls(),
-            Instruction::Ret => self.ret(),
+            Instruction::Ret => self.ret()?,
+            Instruction::Sys(addr) if self.quirks.machine_code => self.machine_code(addr)?,
             Instruction::Sys(_) => {},
             Instruction::Jump(addr) => self.jump(addr),
-            Instruction::Call(addr) => self.call(addr),
+            Instruction::Call(addr) => self.call(addr)?,
             Instruction::SkipValEq(reg, val) => self.skip_val_equal(reg, val),
             Instruction::SkipValNotEq(reg, val) => self.skip_val_notequal(reg, val),
             Instruction::SkipEq(reg1, reg2) => self.skip_reg_equal(reg1, reg2),
@@ -212,14 +493,14 @@ impl Emulator {
             Instruction::Xor(dst, src) => self.xor(dst, src),
             Instruction::Add(dst, src) => self.add(dst, src),
             Instruction::Sub(dst, src) => self.sub(dst, src),
-            Instruction::ShiftRight(reg) => self.shr(reg),
+            Instruction::ShiftRight(dst, src) => self.shr(dst, src),
             Instruction::SubN(dst, src) => self.subn(dst, src),
-            Instruction::ShiftLeft(reg) => self.shl(reg),
+            Instruction::ShiftLeft(dst, src) => self.shl(dst, src),
             Instruction::SkipNotEq(reg1, reg2) => self.skip_reg_not_equal(reg1, reg2),
             Instruction::LoadAddr(addr) => self.load_addr(addr),
             Instruction::JumpRel(addr) => self.jump_v0(addr),
             Instruction::Random(reg, val) => self.rand(reg, val),
-            Instruction::Draw(xreg, yreg, nibble) => self.draw(xreg, yreg, nibble),
+            Instruction::Draw(xreg, yreg, nibble) => self.draw(xreg, yreg, nibble)?,
             Instruction::SkipKeyPressed(reg) => self.skp(reg),
             Instruction::SkipKeyNotPressed(reg) => self.sknp(reg),
             Instruction::LoadDelayTimer(reg) => self.dt_to_vx(reg),
@@ -228,60 +509,94 @@ impl Emulator {
             Instruction::SetSoundTimer(reg) => self.load_st(reg),
             Instruction::AddI(reg) => self.addi(reg),
             Instruction::LoadSprite(reg) => self.loadi_sprite(reg),
-            Instruction::Bcd(reg) => self.bcd(reg),
-            Instruction::StoreRegs(reg) => self.regs_to_mem(reg),
-            Instruction::LoadRegs(reg) => self.mem_to_regs(reg),
-            _ => panic!("Invalid instruction {:x}", instr)
+            Instruction::Bcd(reg) => self.bcd(reg)?,
+            Instruction::StoreRegs(reg) => self.regs_to_mem(reg)?,
+            Instruction::LoadRegs(reg) => self.mem_to_regs(reg)?,
+            Instruction::ScrollDown(n) => self.scroll_down(n),
+            Instruction::ScrollUp(n) => self.scroll_up(n),
+            Instruction::ScrollRight => self.scroll_right(),
+            Instruction::ScrollLeft => self.scroll_left(),
+            Instruction::Exit => self.exit(),
+            Instruction::LowRes => self.lores(),
+            Instruction::HighRes => self.hires_schip(),
+            Instruction::LoadBigSprite(reg) => self.loadi_big_sprite(reg),
+            Instruction::StoreFlags(reg) => self.regs_to_rpl(reg),
+            Instruction::LoadFlags(reg) => self.rpl_to_regs(reg),
+            Instruction::SaveRange(first, last) => self.save_range(first, last)?,
+            Instruction::LoadRange(first, last) => self.load_range(first, last)?,
+            Instruction::LoadLongAddr => self.load_long_addr()?,
+            Instruction::SelectPlanes(planes) => self.select_planes(planes),
+            Instruction::LoadAudio => self.load_audio()?,
+            Instruction::SetPitch(reg) => self.set_pitch(reg),
+            Instruction::Invalid => return Err(Fault::InvalidOpcode),
+        }
+        Ok(())
+    }
+    /// Fail if any byte of [start, start + len) is outside of memory
+    fn check_mem(&self, start: usize, len: usize) -> Result<(), Fault> {
+        if start + len > self.memory.len() {
+            Err(Fault::MemoryOutOfRange(start.max(self.memory.len())))
+        } else {
+            Ok(())
         }
     }
     fn inc_pc(&mut self) {
-        self.pc_reg += 2;
+        self.pc_reg = self.pc_reg.wrapping_add(2);
+    }
+    /// Skip the next instruction, F000 NNNN being 4 bytes long
+    fn skip(&mut self) {
+        let pc = self.pc_reg as usize;
+
+        if self.memory.get(pc..pc + 2) == Some(&[0xF0, 0x00]) {
+            self.inc_pc();
+        }
+        self.inc_pc();
     }
     fn screen_draw(&mut self) {
         self.redraw = true;
     }
 
-    fn hires(&mut self) {
-        self.set_screen_mode(DisplaySize::Eti64x64);
-        self.pc_reg = 0x2c0;
-    }
     /// Clear display
     fn cls(&mut self) {
-        for y in 0..self.resolution.1 {
-            for x in 0..self.resolution.0 {
-                self.screen[x][y] = false;
-            }
-        }
-       self.screen_draw();
+        self.screen.clear(self.planes);
+        self.screen_draw();
     }
     /// Return from subroutine
-    fn ret(&mut self) {
+    fn ret(&mut self) -> Result<(), Fault> {
+        if self.sp_reg == 0 {
+            return Err(Fault::StackUnderflow);
+        }
         self.sp_reg -= 1;
         self.pc_reg = self.stack[self.sp_reg as usize];
+        Ok(())
     }
     /// Jump to address
     fn jump(&mut self, addr: Address) {
         self.pc_reg = addr;
     }
     /// Call function at address
-    fn call(&mut self, addr: Address) {
+    fn call(&mut self, addr: Address) -> Result<(), Fault> {
+        if self.sp_reg as usize == self.stack_depth {
+            return Err(Fault::StackOverflow);
+        }
         self.stack[self.sp_reg as usize] = self.pc_reg;
         self.sp_reg += 1;
         self.pc_reg = addr;
+        Ok(())
     }
     fn skip_val_equal(&mut self, reg: Register, val: Value) {
         if self.regs[reg] == val {
-            self.inc_pc();
+            self.skip();
         }
     }
     fn skip_val_notequal(&mut self, reg: Register, val: Value) {
         if self.regs[reg] != val {
-            self.inc_pc();
+            self.skip();
         }
     }
     fn skip_reg_equal(&mut self, reg1: Register, reg2: Register) {
         if self.regs[reg1] == self.regs[reg2] {
-            self.inc_pc();
+            self.skip();
         }
     }
     fn load_val(&mut self, dst: Register, val: Value) {
@@ -295,12 +610,20 @@ impl Emulator {
     }
     fn or(&mut self, dst: Register, src: Register) {
         self.regs[dst] |= self.regs[src];
+        self.logic_reset_vf();
     }
     fn and(&mut self, dst: Register, src: Register) {
         self.regs[dst] &= self.regs[src];
+        self.logic_reset_vf();
     }
     fn xor(&mut self, dst: Register, src: Register) {
         self.regs[dst] ^= self.regs[src];
+        self.logic_reset_vf();
+    }
+    fn logic_reset_vf(&mut self) {
+        if self.quirks.logic_resets_vf {
+            self.regs[0xF] = 0;
+        }
     }
     fn add(&mut self, dst: Register, src: Register) {
         self.regs[0xF] = if self.regs[dst] as u16 + self.regs[src] as u16 > 255 { 1 } else { 0 };
@@ -310,65 +633,100 @@ impl Emulator {
         self.regs[0xF] = if self.regs[dst] >= self.regs[src] { 1 } else { 0 };
         self.regs[dst] = self.regs[dst].wrapping_sub(self.regs[src]);
     }
-    fn shr(&mut self, reg: Register) {
-        self.regs[0xF] = if self.regs[reg] & 0x01 != 0 { 1 } else { 0 };
-        self.regs[reg] >>= 1;
+    fn shr(&mut self, dst: Register, src: Register) {
+        let val = if self.quirks.shift_uses_vy { self.regs[src] } else { self.regs[dst] };
+
+        self.regs[dst] = val >> 1;
+        self.regs[0xF] = val & 0x01;
     }
     fn subn(&mut self, dst: Register, src: Register) {
         self.regs[0xF] = if self.regs[src] >= self.regs[dst] { 1 } else { 0 };
         self.regs[dst] = self.regs[src].wrapping_sub(self.regs[dst]);
     }
-    fn shl(&mut self, reg: Register) {
-        self.regs[0xF] = if self.regs[reg] & 128 != 0 { 1 } else { 0 };
-        self.regs[reg] <<= 1;
+    fn shl(&mut self, dst: Register, src: Register) {
+        let val = if self.quirks.shift_uses_vy { self.regs[src] } else { self.regs[dst] };
+
+        self.regs[dst] = val << 1;
+        self.regs[0xF] = val >> 7;
     }
     fn skip_reg_not_equal(&mut self, dst: Register, src: Register) {
         if self.regs[dst] != self.regs[src] {
-            self.inc_pc();
+            self.skip();
         }
     }
     fn load_addr(&mut self, addr: Address) {
         self.i_reg = addr;
     }
     fn jump_v0(&mut self, addr: Address) {
-        self.pc_reg = self.regs[0] as u16 + addr;
+        let reg = if self.quirks.jump_uses_vx { (addr >> 8) as Register } else { 0 };
+
+        self.pc_reg = self.regs[reg] as u16 + addr;
     }
     fn rand(&mut self, reg: Register, val: Value) {
-        self.regs[reg] = rand::random::<u8>() & val;
+        self.regs[reg] = self.rng.next_u8() & val;
     }
-    fn draw(&mut self, xreg: Register, yreg: Register, n: Value) {
-        let x_start = self.regs[xreg] as usize;
-        let y_start = self.regs[yreg] as usize;
+    fn draw(&mut self, xreg: Register, yreg: Register, n: Value) -> Result<(), Fault> {
+        if self.quirks.display_wait || self.timing == Timing::Vip {
+            // retry the same instruction until the next 60Hz tick
+            if !self.vblank {
+                self.pc_reg = self.pc_reg.wrapping_sub(2);
+                return Ok(());
+            }
+            self.vblank = false;
+        }
+        let x_start = self.regs[xreg] as usize % self.resolution.0;
+        let y_start = self.regs[yreg] as usize % self.resolution.1;
+        // DXY0 draws a 16x16 SUPER-CHIP sprite, 2 bytes per line
+        let (width, height) = if n == 0 { (16, 16) } else { (8, n as usize) };
+        // with both XO-CHIP planes selected, the second sprite follows the first
+        let mut addr = self.i_reg as usize;
+        let planes = self.planes;
+
+        self.check_mem(addr, width / 8 * height * planes.count_ones() as usize)?;
 
         self.regs[0xF] = 0;
-        for y in 0..n as usize {
-            let line = self.memory[self.i_reg as usize + y];
-            let screen_y = (y_start + y) % self.resolution.1;
-
-            for x in 0..8 {
-                let sprite_pixel : bool = line & (0x1 << 7 - x) != 0;
-                let screen_x = (x_start + x) % self.resolution.0;
-                let current_pixel = self.screen[screen_x][screen_y];
-
-                // pixel will be erased, flag overflow
-                if sprite_pixel && current_pixel {
-                    self.regs[0xF] = 1;
+        for plane in [0x1, 0x2].iter().filter(|plane| planes & *plane != 0) {
+            for y in 0..height {
+                if self.quirks.clip_sprites && y_start + y >= self.resolution.1 {
+                    break;
+                }
+                let line = if width == 16 {
+                    (self.memory[addr + 2 * y] as u16) << 8
+                        | self.memory[addr + 2 * y + 1] as u16
+                } else {
+                    (self.memory[addr + y] as u16) << 8
+                };
+                let screen_y = (y_start + y) % self.resolution.1;
+
+                for x in 0..width {
+                    if self.quirks.clip_sprites && x_start + x >= self.resolution.0 {
+                        break;
+                    }
+                    let sprite_pixel : bool = line & (0x1 << (15 - x)) != 0;
+                    let screen_x = (x_start + x) % self.resolution.0;
+
+                    // pixel erased, flag overflow
+                    if sprite_pixel && self.screen.toggle(screen_x, screen_y, *plane) {
+                        self.regs[0xF] = 1;
+                    }
                 }
-                self.screen[screen_x][screen_y] ^= sprite_pixel;
             }
+            addr += width / 8 * height;
         }
+        self.screen.mark_dirty(Rect { x: x_start, y: y_start, width, height });
         self.screen_draw();
+        Ok(())
     }
     // skip if key pressed
     fn skp(&mut self, reg: Register) {
-        if self.keys[self.regs[reg] as usize] {
-            self.inc_pc();
+        if self.keys[(self.regs[reg] & 0xF) as usize] {
+            self.skip();
         }
     }
     // skip if key not pressed
     fn sknp(&mut self, reg: Register) {
-        if !self.keys[self.regs[reg] as usize] {
-            self.inc_pc();
+        if !self.keys[(self.regs[reg] & 0xF) as usize] {
+            self.skip();
         }
     }
     fn dt_to_vx(&mut self, reg: Register) {
@@ -386,7 +744,7 @@ impl Emulator {
         }
         // loop until a key is pressed
         if !pressed {
-            self.pc_reg -= 2;
+            self.pc_reg = self.pc_reg.wrapping_sub(2);
         }
     }
     fn vx_to_dt(&mut self, src: Register) {
@@ -396,29 +754,137 @@ impl Emulator {
         self.st_reg = self.regs[src];
     }
     fn addi(&mut self, src: Register) {
-        self.i_reg += self.regs[src] as u16;
+        self.i_reg = self.i_reg.wrapping_add(self.regs[src] as u16);
     }
     fn loadi_sprite(&mut self, src: Register) {
-        self.i_reg = self.regs[src] as u16 * 5;
+        self.i_reg = self.profile.font_address + self.regs[src] as u16 * 5;
     }
-    fn bcd(&mut self, src: Register) {
+    fn bcd(&mut self, src: Register) -> Result<(), Fault> {
         let mut val = self.regs[src];
 
+        self.check_mem(self.i_reg as usize, 3)?;
+
         self.memory[self.i_reg as usize + 2] = val % 10;
         val /= 10;
         self.memory[self.i_reg as usize + 1] = val % 10;
         val /= 10;
         self.memory[self.i_reg as usize] = val;
+        self.memory_written(self.i_reg as usize, 3);
+        Ok(())
     }
-    fn regs_to_mem(&mut self, reg: Register) {
+    fn regs_to_mem(&mut self, reg: Register) -> Result<(), Fault> {
+        self.check_mem(self.i_reg as usize, reg + 1)?;
         for i in 0..=reg {
             self.memory[self.i_reg as usize + i] = self.regs[i];
         }
+        self.memory_written(self.i_reg as usize, reg + 1);
+        if self.quirks.load_store_increments_i {
+            self.i_reg = self.i_reg.wrapping_add(reg as u16 + 1);
+        } else if self.quirks.load_store_adds_x {
+            self.i_reg = self.i_reg.wrapping_add(reg as u16);
+        }
+        Ok(())
     }
-    fn mem_to_regs(&mut self, reg: Register) {
+    fn mem_to_regs(&mut self, reg: Register) -> Result<(), Fault> {
+        self.check_mem(self.i_reg as usize, reg + 1)?;
         for i in 0..=reg {
             self.regs[i] = self.memory[self.i_reg as usize + i];
         }
+        if self.quirks.load_store_increments_i {
+            self.i_reg = self.i_reg.wrapping_add(reg as u16 + 1);
+        } else if self.quirks.load_store_adds_x {
+            self.i_reg = self.i_reg.wrapping_add(reg as u16);
+        }
+        Ok(())
+    }
+    /// Move the selected planes by (dx, dy), pixels scrolled out are lost
+    fn scroll(&mut self, dx: isize, dy: isize) {
+        self.screen.scroll(dx, dy, self.planes);
+        self.screen_draw();
+    }
+    /// Scroll display n lines down
+    fn scroll_down(&mut self, n: Value) {
+        self.scroll(0, n as isize);
+    }
+    /// Scroll display n lines up
+    fn scroll_up(&mut self, n: Value) {
+        self.scroll(0, -(n as isize));
+    }
+    /// Scroll display 4 pixels right
+    fn scroll_right(&mut self) {
+        self.scroll(4, 0);
+    }
+    /// Scroll display 4 pixels left
+    fn scroll_left(&mut self) {
+        self.scroll(-4, 0);
+    }
+    /// Exit interpreter
+    fn exit(&mut self) {
+        self.exited = true;
+    }
+    /// Disable extended screen mode
+    fn lores(&mut self) {
+        self.set_screen_mode(DisplaySize::Basic64x32);
+        self.screen_draw();
+    }
+    /// Enable extended screen mode for full-screen graphics
+    fn hires_schip(&mut self) {
+        self.set_screen_mode(DisplaySize::Hp128x64);
+        self.screen_draw();
+    }
+    fn loadi_big_sprite(&mut self, src: Register) {
+        self.i_reg = (self.big_sprites_addr() + self.regs[src] as usize * 10) as u16;
+    }
+    fn regs_to_rpl(&mut self, reg: Register) {
+        self.rpl[..=reg].copy_from_slice(&self.regs[..=reg]);
+    }
+    fn rpl_to_regs(&mut self, reg: Register) {
+        self.regs[..=reg].copy_from_slice(&self.rpl[..=reg]);
+    }
+    /// Store VX..VY in memory starting at I, in either order
+    fn save_range(&mut self, first: Register, last: Register) -> Result<(), Fault> {
+        self.check_mem(self.i_reg as usize, first.max(last) - first.min(last) + 1)?;
+        for (offset, reg) in Emulator::reg_range(first, last).enumerate() {
+            self.memory[self.i_reg as usize + offset] = self.regs[reg];
+        }
+        self.memory_written(self.i_reg as usize, first.max(last) - first.min(last) + 1);
+        Ok(())
+    }
+    /// Read VX..VY from memory starting at I, in either order
+    fn load_range(&mut self, first: Register, last: Register) -> Result<(), Fault> {
+        self.check_mem(self.i_reg as usize, first.max(last) - first.min(last) + 1)?;
+        for (offset, reg) in Emulator::reg_range(first, last).enumerate() {
+            self.regs[reg] = self.memory[self.i_reg as usize + offset];
+        }
+        Ok(())
+    }
+    fn reg_range(first: Register, last: Register) -> Box<dyn Iterator<Item = Register>> {
+        if first <= last {
+            Box::new(first..=last)
+        } else {
+            Box::new((last..=first).rev())
+        }
+    }
+    /// Load I with the 16-bit address following the instruction
+    fn load_long_addr(&mut self) -> Result<(), Fault> {
+        self.check_mem(self.pc_reg as usize, 2)?;
+        self.i_reg = (self.memory[self.pc_reg as usize] as u16) << 8
+                   | self.memory[self.pc_reg as usize + 1] as u16;
+        self.inc_pc();
+        Ok(())
+    }
+    fn select_planes(&mut self, planes: Value) {
+        self.planes = planes & 0x3;
+    }
+    fn load_audio(&mut self) -> Result<(), Fault> {
+        let start = self.i_reg as usize;
+
+        self.check_mem(start, 16)?;
+        self.audio_pattern.copy_from_slice(&self.memory[start..start + 16]);
+        Ok(())
+    }
+    fn set_pitch(&mut self, src: Register) {
+        self.pitch = self.regs[src];
     }
 }
 
@@ -428,7 +894,7 @@ mod test_emu {
 
     #[test]
     fn test_001_load_software() {
-        let mut emu = Emulator::new(DisplaySize::Basic64x32);
+        let mut emu = Emulator::new(DisplaySize::Basic64x32, Quirks::default(), 0);
         emu.mem_load_bin(vec![0x01, 0x02, 0x03, 0x04]);
         assert_eq!(emu.memory[0x200], 0x01);
         assert_eq!(emu.memory[0x201], 0x02);
@@ -438,7 +904,7 @@ mod test_emu {
 
     #[test]
     fn test_010_loadval_addval() {
-        let mut emu = Emulator::new(DisplaySize::Basic64x32);
+        let mut emu = Emulator::new(DisplaySize::Basic64x32, Quirks::default(), 0);
         emu.mem_load_instr(vec![
             Instruction::LoadVal(1, 0x55),
             Instruction::AddVal(1, 0xAA),
@@ -447,22 +913,22 @@ mod test_emu {
         assert_eq!(emu.regs[1], 0x00);
         assert_eq!(emu.pc_reg, 0x200);
 
-        emu.cpu_one_cycle();
+        emu.cpu_one_cycle().unwrap();
         assert_eq!(emu.pc_reg, 0x202);
         assert_eq!(emu.regs[1], 0x55);
 
-        emu.cpu_one_cycle();
+        emu.cpu_one_cycle().unwrap();
         assert_eq!(emu.pc_reg, 0x204);
         assert_eq!(emu.regs[1], 0xFF);
 
-        emu.cpu_one_cycle();
+        emu.cpu_one_cycle().unwrap();
         assert_eq!(emu.pc_reg, 0x206);
         assert_eq!(emu.regs[1], 0x00);
     }
 
     #[test]
     fn test_011_load_add_sub() {
-        let mut emu = Emulator::new(DisplaySize::Basic64x32);
+        let mut emu = Emulator::new(DisplaySize::Basic64x32, Quirks::default(), 0);
         emu.mem_load_instr(vec![
             Instruction::LoadVal(1, 0x56), // $1 = 0x56
             Instruction::Load(2, 1),       // $2 = $1
@@ -475,35 +941,35 @@ mod test_emu {
         assert_eq!(emu.regs[2], 0x00);
         assert_eq!(emu.pc_reg, 0x200);
 
-        emu.cpu_one_cycle();
+        emu.cpu_one_cycle().unwrap();
         assert_eq!(emu.regs[1], 0x56);
         assert_eq!(emu.regs[2], 0x00);
         assert_eq!(emu.pc_reg, 0x202);
 
-        emu.cpu_one_cycle();
+        emu.cpu_one_cycle().unwrap();
         assert_eq!(emu.regs[1], 0x56);
         assert_eq!(emu.regs[2], 0x56);
         assert_eq!(emu.pc_reg, 0x204);
 
-        emu.cpu_one_cycle();
+        emu.cpu_one_cycle().unwrap();
         assert_eq!(emu.regs[1], 0x56);
         assert_eq!(emu.regs[2], 0xAC);
         assert_eq!(emu.regs[0xF], 0);
         assert_eq!(emu.pc_reg, 0x206);
 
-        emu.cpu_one_cycle();
+        emu.cpu_one_cycle().unwrap();
         assert_eq!(emu.regs[1], 0x02);
         assert_eq!(emu.regs[2], 0xAC);
         assert_eq!(emu.regs[0xF], 1);
         assert_eq!(emu.pc_reg, 0x208);
 
-        emu.cpu_one_cycle();
+        emu.cpu_one_cycle().unwrap();
         assert_eq!(emu.regs[1], 0x56);
         assert_eq!(emu.regs[2], 0xAC);
         assert_eq!(emu.regs[0xF], 0);
         assert_eq!(emu.pc_reg, 0x20A);
 
-        emu.cpu_one_cycle();
+        emu.cpu_one_cycle().unwrap();
         assert_eq!(emu.regs[1], 0x00);
         assert_eq!(emu.regs[0xF], 1);
         assert_eq!(emu.pc_reg, 0x20C);
@@ -511,7 +977,7 @@ mod test_emu {
 
     #[test]
     fn test_012_or_and_xor_shr_shl() {
-        let mut emu = Emulator::new(DisplaySize::Basic64x32);
+        let mut emu = Emulator::new(DisplaySize::Basic64x32, Quirks::default(), 0);
         emu.mem_load_instr(vec![
             Instruction::LoadVal(1, 0xAA), // $1 = 0x55
             Instruction::LoadVal(2, 0x55), // $2 = 0x55
@@ -525,48 +991,48 @@ mod test_emu {
             Instruction::Xor(1, 1),        // $1 = $1 ^ $1 (0x00)
 
             Instruction::LoadVal(1, 0x55), // $1 = 0x55
-            Instruction::ShiftLeft(1),     // $1 <<= 1 (0xAA)
-            Instruction::ShiftLeft(1),     // $1 <<= 1 (0x54 + overflow)
+            Instruction::ShiftLeft(1, 0),  // $1 <<= 1 (0xAA)
+            Instruction::ShiftLeft(1, 0),  // $1 <<= 1 (0x54 + overflow)
 
             Instruction::LoadVal(1, 0x55), // $1 = 0x55
-            Instruction::ShiftRight(1),    // $1 <<= 1 (0x2A)
-            Instruction::ShiftRight(1),    // $1 <<= 1 (0x15 + overflow)
+            Instruction::ShiftRight(1, 0), // $1 <<= 1 (0x2A)
+            Instruction::ShiftRight(1, 0), // $1 <<= 1 (0x15 + overflow)
         ]);
-        emu.cpu_one_cycle();
-        emu.cpu_one_cycle();
-        emu.cpu_one_cycle();
+        emu.cpu_one_cycle().unwrap();
+        emu.cpu_one_cycle().unwrap();
+        emu.cpu_one_cycle().unwrap();
         assert_eq!(emu.pc_reg, 0x206);
         assert_eq!(emu.regs[1], 0xFF);
 
-        emu.cpu_one_cycle();
-        emu.cpu_one_cycle();
+        emu.cpu_one_cycle().unwrap();
+        emu.cpu_one_cycle().unwrap();
         assert_eq!(emu.pc_reg, 0x20A);
         assert_eq!(emu.regs[1], 0x00);
 
-        emu.cpu_one_cycle();
-        emu.cpu_one_cycle();
+        emu.cpu_one_cycle().unwrap();
+        emu.cpu_one_cycle().unwrap();
         assert_eq!(emu.pc_reg, 0x20E);
         assert_eq!(emu.regs[1], 0xFF);
-        emu.cpu_one_cycle();
+        emu.cpu_one_cycle().unwrap();
         assert_eq!(emu.pc_reg, 0x210);
         assert_eq!(emu.regs[1], 0x00);
 
-        emu.cpu_one_cycle();
-        emu.cpu_one_cycle();
+        emu.cpu_one_cycle().unwrap();
+        emu.cpu_one_cycle().unwrap();
         assert_eq!(emu.pc_reg, 0x214);
         assert_eq!(emu.regs[1], 0xAA);
         assert_eq!(emu.regs[0xF], 0);
-        emu.cpu_one_cycle();
+        emu.cpu_one_cycle().unwrap();
         assert_eq!(emu.pc_reg, 0x216);
         assert_eq!(emu.regs[1], 0x54);
         assert_eq!(emu.regs[0xF], 1);
 
-        emu.cpu_one_cycle();
-        emu.cpu_one_cycle();
+        emu.cpu_one_cycle().unwrap();
+        emu.cpu_one_cycle().unwrap();
         assert_eq!(emu.pc_reg, 0x21A);
         assert_eq!(emu.regs[1], 0x2A);
         assert_eq!(emu.regs[0xF], 1);
-        emu.cpu_one_cycle();
+        emu.cpu_one_cycle().unwrap();
         assert_eq!(emu.pc_reg, 0x21C);
         assert_eq!(emu.regs[1], 0x15);
         assert_eq!(emu.regs[0xF], 0);
@@ -574,7 +1040,7 @@ mod test_emu {
 
     #[test]
     fn test_013_skip() {
-        let mut emu = Emulator::new(DisplaySize::Basic64x32);
+        let mut emu = Emulator::new(DisplaySize::Basic64x32, Quirks::default(), 0);
         emu.mem_load_instr(vec![
             Instruction::LoadVal(1, 0xAA),
             Instruction::SkipValEq(1, 0xAB),
@@ -593,31 +1059,31 @@ mod test_emu {
         ]);
 
         assert_eq!(emu.pc_reg, 0x200);
-        emu.cpu_one_cycle();
+        emu.cpu_one_cycle().unwrap();
         assert_eq!(emu.pc_reg, 0x202);
-        emu.cpu_one_cycle();
+        emu.cpu_one_cycle().unwrap();
         assert_eq!(emu.pc_reg, 0x204);
-        emu.cpu_one_cycle();
+        emu.cpu_one_cycle().unwrap();
         assert_eq!(emu.pc_reg, 0x208);
 
-        emu.cpu_one_cycle();
+        emu.cpu_one_cycle().unwrap();
         assert_eq!(emu.pc_reg, 0x20A);
-        emu.cpu_one_cycle();
+        emu.cpu_one_cycle().unwrap();
         assert_eq!(emu.pc_reg, 0x20E);
 
-        emu.cpu_one_cycle();
+        emu.cpu_one_cycle().unwrap();
         assert_eq!(emu.pc_reg, 0x210);
-        emu.cpu_one_cycle();
+        emu.cpu_one_cycle().unwrap();
         assert_eq!(emu.pc_reg, 0x212);
-        emu.cpu_one_cycle();
+        emu.cpu_one_cycle().unwrap();
         assert_eq!(emu.pc_reg, 0x214);
-        emu.cpu_one_cycle();
+        emu.cpu_one_cycle().unwrap();
         assert_eq!(emu.pc_reg, 0x218);
     }
 
     #[test]
     fn test_014_jump_call_ret() {
-        let mut emu = Emulator::new(DisplaySize::Basic64x32);
+        let mut emu = Emulator::new(DisplaySize::Basic64x32, Quirks::default(), 0);
         emu.mem_load_instr(vec![
             Instruction::Jump(0x204),
             Instruction::Invalid,
@@ -626,23 +1092,23 @@ mod test_emu {
             Instruction::Ret,
         ]);
         assert_eq!(emu.pc_reg, 0x200);
-        emu.cpu_one_cycle();
+        emu.cpu_one_cycle().unwrap();
         assert_eq!(emu.pc_reg, 0x204);
         assert_eq!(emu.sp_reg, 0);
 
-        emu.cpu_one_cycle();
+        emu.cpu_one_cycle().unwrap();
         assert_eq!(emu.pc_reg, 0x208);
         assert_eq!(emu.sp_reg, 1);
         assert_eq!(emu.stack[0], 0x206);
 
-        emu.cpu_one_cycle();
+        emu.cpu_one_cycle().unwrap();
         assert_eq!(emu.pc_reg, 0x206);
         assert_eq!(emu.sp_reg, 0);
     }
 
     #[test]
     fn test_015_sprite_draw_cls() {
-        let mut emu = Emulator::new(DisplaySize::Basic64x32);
+        let mut emu = Emulator::new(DisplaySize::Basic64x32, Quirks::default(), 0);
         emu.mem_load_instr(vec![
             Instruction::LoadVal(1, 0x00),
             Instruction::LoadVal(2, 0x00),
@@ -662,102 +1128,102 @@ mod test_emu {
             Instruction::Cls,
 
         ]);
-        emu.cpu_one_cycle();
-        emu.cpu_one_cycle();
-        emu.cpu_one_cycle();
+        emu.cpu_one_cycle().unwrap();
+        emu.cpu_one_cycle().unwrap();
+        emu.cpu_one_cycle().unwrap();
         assert_eq!(emu.pc_reg, 0x206);
 
-        emu.cpu_one_cycle();
+        emu.cpu_one_cycle().unwrap();
         assert_eq!(emu.i_reg, 0xF * 5);
         assert_eq!(emu.pc_reg, 0x208);
 
         // draw letter "F"
-        emu.cpu_one_cycle();
+        emu.cpu_one_cycle().unwrap();
         assert_eq!(emu.pc_reg, 0x20A);
         assert_eq!(emu.regs[0xF], 0);
-        assert_eq!(emu.screen[0][0], true);
-        assert_eq!(emu.screen[1][0], true);
-        assert_eq!(emu.screen[2][0], true);
-        assert_eq!(emu.screen[3][0], true);
-        assert_eq!(emu.screen[4][0], false);
-        assert_eq!(emu.screen[0][1], true);
-        assert_eq!(emu.screen[1][1], false);
-        assert_eq!(emu.screen[0][2], true);
-        assert_eq!(emu.screen[1][2], true);
-        assert_eq!(emu.screen[2][2], true);
-        assert_eq!(emu.screen[3][2], true);
-        assert_eq!(emu.screen[4][2], false);
-        assert_eq!(emu.screen[0][3], true);
-        assert_eq!(emu.screen[1][3], false);
-        assert_eq!(emu.screen[0][4], true);
-        assert_eq!(emu.screen[1][4], false);
+        assert_eq!(emu.screen.get(0, 0), 1);
+        assert_eq!(emu.screen.get(1, 0), 1);
+        assert_eq!(emu.screen.get(2, 0), 1);
+        assert_eq!(emu.screen.get(3, 0), 1);
+        assert_eq!(emu.screen.get(4, 0), 0);
+        assert_eq!(emu.screen.get(0, 1), 1);
+        assert_eq!(emu.screen.get(1, 1), 0);
+        assert_eq!(emu.screen.get(0, 2), 1);
+        assert_eq!(emu.screen.get(1, 2), 1);
+        assert_eq!(emu.screen.get(2, 2), 1);
+        assert_eq!(emu.screen.get(3, 2), 1);
+        assert_eq!(emu.screen.get(4, 2), 0);
+        assert_eq!(emu.screen.get(0, 3), 1);
+        assert_eq!(emu.screen.get(1, 3), 0);
+        assert_eq!(emu.screen.get(0, 4), 1);
+        assert_eq!(emu.screen.get(1, 4), 0);
 
         // clear letter "F"
-        emu.cpu_one_cycle();
+        emu.cpu_one_cycle().unwrap();
         assert_eq!(emu.pc_reg, 0x20C);
         assert_eq!(emu.regs[0xF], 1);
-        assert_eq!(emu.screen[0][0], false);
-        assert_eq!(emu.screen[1][0], false);
-        assert_eq!(emu.screen[2][0], false);
-        assert_eq!(emu.screen[3][0], false);
-        assert_eq!(emu.screen[4][0], false);
-        assert_eq!(emu.screen[0][1], false);
-        assert_eq!(emu.screen[1][1], false);
-        assert_eq!(emu.screen[0][2], false);
-        assert_eq!(emu.screen[1][2], false);
-        assert_eq!(emu.screen[2][2], false);
-        assert_eq!(emu.screen[0][3], false);
-        assert_eq!(emu.screen[1][3], false);
-        assert_eq!(emu.screen[0][4], false);
-        assert_eq!(emu.screen[1][4], false);
+        assert_eq!(emu.screen.get(0, 0), 0);
+        assert_eq!(emu.screen.get(1, 0), 0);
+        assert_eq!(emu.screen.get(2, 0), 0);
+        assert_eq!(emu.screen.get(3, 0), 0);
+        assert_eq!(emu.screen.get(4, 0), 0);
+        assert_eq!(emu.screen.get(0, 1), 0);
+        assert_eq!(emu.screen.get(1, 1), 0);
+        assert_eq!(emu.screen.get(0, 2), 0);
+        assert_eq!(emu.screen.get(1, 2), 0);
+        assert_eq!(emu.screen.get(2, 2), 0);
+        assert_eq!(emu.screen.get(0, 3), 0);
+        assert_eq!(emu.screen.get(1, 3), 0);
+        assert_eq!(emu.screen.get(0, 4), 0);
+        assert_eq!(emu.screen.get(1, 4), 0);
 
         // draw letter F on border
-        emu.cpu_one_cycle();
-        emu.cpu_one_cycle();
-        emu.cpu_one_cycle();
+        emu.cpu_one_cycle().unwrap();
+        emu.cpu_one_cycle().unwrap();
+        emu.cpu_one_cycle().unwrap();
         assert_eq!(emu.pc_reg, 0x212);
         assert_eq!(emu.regs[0xF], 0);
-        assert_eq!(emu.screen[63][31], true);
-        assert_eq!(emu.screen[0][31], true);
-        assert_eq!(emu.screen[1][31], true);
-        assert_eq!(emu.screen[2][31], true);
-        assert_eq!(emu.screen[3][31], false);
-        assert_eq!(emu.screen[63][0], true);
-        assert_eq!(emu.screen[0][0], false);
-        assert_eq!(emu.screen[63][1], true);
-        assert_eq!(emu.screen[0][1], true);
-        assert_eq!(emu.screen[1][1], true);
-        assert_eq!(emu.screen[2][1], true);
-        assert_eq!(emu.screen[3][1], false);
-        assert_eq!(emu.screen[63][2], true);
-        assert_eq!(emu.screen[0][2], false);
-        assert_eq!(emu.screen[63][3], true);
-        assert_eq!(emu.screen[0][3], false);
-
-        emu.cpu_one_cycle();
+        assert_eq!(emu.screen.get(63, 31), 1);
+        assert_eq!(emu.screen.get(0, 31), 1);
+        assert_eq!(emu.screen.get(1, 31), 1);
+        assert_eq!(emu.screen.get(2, 31), 1);
+        assert_eq!(emu.screen.get(3, 31), 0);
+        assert_eq!(emu.screen.get(63, 0), 1);
+        assert_eq!(emu.screen.get(0, 0), 0);
+        assert_eq!(emu.screen.get(63, 1), 1);
+        assert_eq!(emu.screen.get(0, 1), 1);
+        assert_eq!(emu.screen.get(1, 1), 1);
+        assert_eq!(emu.screen.get(2, 1), 1);
+        assert_eq!(emu.screen.get(3, 1), 0);
+        assert_eq!(emu.screen.get(63, 2), 1);
+        assert_eq!(emu.screen.get(0, 2), 0);
+        assert_eq!(emu.screen.get(63, 3), 1);
+        assert_eq!(emu.screen.get(0, 3), 0);
+
+        emu.cpu_one_cycle().unwrap();
         assert_eq!(emu.pc_reg, 0x214);
         assert_eq!(emu.regs[0xF], 0);
-        assert_eq!(emu.screen[63][31], false);
-        assert_eq!(emu.screen[0][31], false);
-        assert_eq!(emu.screen[1][31], false);
-        assert_eq!(emu.screen[2][31], false);
-        assert_eq!(emu.screen[3][31], false);
-        assert_eq!(emu.screen[63][0], false);
-        assert_eq!(emu.screen[0][0], false);
-        assert_eq!(emu.screen[63][1], false);
-        assert_eq!(emu.screen[0][1], false);
-        assert_eq!(emu.screen[1][1], false);
-        assert_eq!(emu.screen[2][1], false);
-        assert_eq!(emu.screen[3][1], false);
-        assert_eq!(emu.screen[63][2], false);
-        assert_eq!(emu.screen[0][2], false);
-        assert_eq!(emu.screen[63][3], false);
-        assert_eq!(emu.screen[0][3], false);
+        assert_eq!(emu.screen.get(63, 31), 0);
+        assert_eq!(emu.screen.get(0, 31), 0);
+        assert_eq!(emu.screen.get(1, 31), 0);
+        assert_eq!(emu.screen.get(2, 31), 0);
+        assert_eq!(emu.screen.get(3, 31), 0);
+        assert_eq!(emu.screen.get(63, 0), 0);
+        assert_eq!(emu.screen.get(0, 0), 0);
+        assert_eq!(emu.screen.get(63, 1), 0);
+        assert_eq!(emu.screen.get(0, 1), 0);
+        assert_eq!(emu.screen.get(1, 1), 0);
+        assert_eq!(emu.screen.get(2, 1), 0);
+        assert_eq!(emu.screen.get(3, 1), 0);
+        assert_eq!(emu.screen.get(63, 2), 0);
+        assert_eq!(emu.screen.get(0, 2), 0);
+        assert_eq!(emu.screen.get(63, 3), 0);
+        assert_eq!(emu.screen.get(0, 3), 0);
     }
 
     #[test]
     fn test_016_loadaddr_addi_regs_store_load() {
-        let mut emu = Emulator::new(DisplaySize::Basic64x32);
+        let mut emu = Emulator::new(DisplaySize::Basic64x32, Quirks::default(), 0);
         emu.mem_load_instr(vec![
             Instruction::LoadAddr(0x600),
             Instruction::LoadVal(0, 0xDE),
@@ -772,59 +1238,907 @@ mod test_emu {
             Instruction::LoadRegs(2),
             Instruction::AddI(1),
         ]);
-        emu.cpu_one_cycle();
+        emu.cpu_one_cycle().unwrap();
         assert_eq!(emu.pc_reg, 0x202);
         assert_eq!(emu.i_reg, 0x600);
 
-        emu.cpu_one_cycle();
-        emu.cpu_one_cycle();
-        emu.cpu_one_cycle();
-        emu.cpu_one_cycle();
+        emu.cpu_one_cycle().unwrap();
+        emu.cpu_one_cycle().unwrap();
+        emu.cpu_one_cycle().unwrap();
+        emu.cpu_one_cycle().unwrap();
         assert_eq!(emu.pc_reg, 0x20A);
 
-        emu.cpu_one_cycle();
+        emu.cpu_one_cycle().unwrap();
         assert_eq!(emu.memory[0x600], 0xDE);
         assert_eq!(emu.memory[0x601], 0xAD);
         assert_eq!(emu.memory[0x602], 0xBE);
         assert_eq!(emu.memory[0x603], 0x00);
 
-        emu.cpu_one_cycle();
-        emu.cpu_one_cycle();
-        emu.cpu_one_cycle();
+        emu.cpu_one_cycle().unwrap();
+        emu.cpu_one_cycle().unwrap();
+        emu.cpu_one_cycle().unwrap();
         assert_eq!(emu.pc_reg, 0x212);
 
-        emu.cpu_one_cycle();
+        emu.cpu_one_cycle().unwrap();
         assert_eq!(emu.regs[0], 0xDE);
         assert_eq!(emu.regs[1], 0xAD);
         assert_eq!(emu.regs[2], 0xBE);
         assert_eq!(emu.regs[3], 0xEF);
         assert_eq!(emu.pc_reg, 0x214);
 
-        emu.cpu_one_cycle();
+        emu.cpu_one_cycle().unwrap();
         assert_eq!(emu.i_reg, 0x6AD);
     }
 
     #[test]
     fn test_017_bcd() {
-        let mut emu = Emulator::new(DisplaySize::Basic64x32);
+        let mut emu = Emulator::new(DisplaySize::Basic64x32, Quirks::default(), 0);
         emu.mem_load_instr(vec![
             Instruction::LoadVal(1, 234),
             Instruction::LoadAddr(0x600),
             Instruction::Bcd(1),
         ]);
 
-        emu.cpu_one_cycle();
+        emu.cpu_one_cycle().unwrap();
         assert_eq!(emu.pc_reg, 0x202);
         assert_eq!(emu.regs[1], 234);
 
-        emu.cpu_one_cycle();
+        emu.cpu_one_cycle().unwrap();
         assert_eq!(emu.pc_reg, 0x204);
         assert_eq!(emu.i_reg, 0x600);
 
-        emu.cpu_one_cycle();
+        emu.cpu_one_cycle().unwrap();
         assert_eq!(emu.pc_reg, 0x206);
         assert_eq!(emu.memory[0x600], 2);
         assert_eq!(emu.memory[0x601], 3);
         assert_eq!(emu.memory[0x602], 4);
     }
+
+    #[test]
+    fn test_018_quirk_shift_uses_vy() {
+        let program = vec![
+            Instruction::LoadVal(1, 0x0F),
+            Instruction::LoadVal(2, 0x81),
+            Instruction::ShiftRight(1, 2),
+            Instruction::LoadVal(1, 0x0F),
+            Instruction::ShiftLeft(1, 2),
+        ];
+
+        let mut emu = Emulator::new(DisplaySize::Basic64x32, Quirks::default(), 0);
+        emu.mem_load_instr(program.clone());
+        emu.cpu_one_cycle().unwrap();
+        emu.cpu_one_cycle().unwrap();
+        emu.cpu_one_cycle().unwrap();
+        assert_eq!(emu.regs[1], 0x07);
+        assert_eq!(emu.regs[0xF], 1);
+        emu.cpu_one_cycle().unwrap();
+        emu.cpu_one_cycle().unwrap();
+        assert_eq!(emu.regs[1], 0x1E);
+        assert_eq!(emu.regs[0xF], 0);
+
+        let quirks = Quirks { shift_uses_vy: true, ..Quirks::default() };
+        let mut emu = Emulator::new(DisplaySize::Basic64x32, quirks, 0);
+        emu.mem_load_instr(program);
+        emu.cpu_one_cycle().unwrap();
+        emu.cpu_one_cycle().unwrap();
+        emu.cpu_one_cycle().unwrap();
+        assert_eq!(emu.regs[1], 0x40);
+        assert_eq!(emu.regs[2], 0x81);
+        assert_eq!(emu.regs[0xF], 1);
+        emu.cpu_one_cycle().unwrap();
+        emu.cpu_one_cycle().unwrap();
+        assert_eq!(emu.regs[1], 0x02);
+        assert_eq!(emu.regs[0xF], 1);
+    }
+
+    #[test]
+    fn test_019_quirk_load_store_increments_i() {
+        let program = vec![
+            Instruction::LoadAddr(0x600),
+            Instruction::StoreRegs(2),
+            Instruction::LoadRegs(3),
+        ];
+
+        let mut emu = Emulator::new(DisplaySize::Basic64x32, Quirks::default(), 0);
+        emu.mem_load_instr(program.clone());
+        emu.cpu_one_cycle().unwrap();
+        emu.cpu_one_cycle().unwrap();
+        assert_eq!(emu.i_reg, 0x600);
+        emu.cpu_one_cycle().unwrap();
+        assert_eq!(emu.i_reg, 0x600);
+
+        let quirks = Quirks { load_store_increments_i: true, ..Quirks::default() };
+        let mut emu = Emulator::new(DisplaySize::Basic64x32, quirks, 0);
+        emu.mem_load_instr(program.clone());
+        emu.cpu_one_cycle().unwrap();
+        emu.cpu_one_cycle().unwrap();
+        assert_eq!(emu.i_reg, 0x603);
+        emu.cpu_one_cycle().unwrap();
+        assert_eq!(emu.i_reg, 0x607);
+
+        let quirks = Quirks { load_store_adds_x: true, ..Quirks::default() };
+        let mut emu = Emulator::new(DisplaySize::Basic64x32, quirks, 0);
+        emu.mem_load_instr(program);
+        emu.cpu_one_cycle().unwrap();
+        emu.cpu_one_cycle().unwrap();
+        assert_eq!(emu.i_reg, 0x602);
+        emu.cpu_one_cycle().unwrap();
+        assert_eq!(emu.i_reg, 0x605);
+    }
+
+    #[test]
+    fn test_020_quirk_jump_uses_vx() {
+        let program = vec![
+            Instruction::LoadVal(0, 0x10),
+            Instruction::LoadVal(3, 0x20),
+            Instruction::JumpRel(0x300),
+        ];
+
+        let mut emu = Emulator::new(DisplaySize::Basic64x32, Quirks::default(), 0);
+        emu.mem_load_instr(program.clone());
+        emu.cpu_one_cycle().unwrap();
+        emu.cpu_one_cycle().unwrap();
+        emu.cpu_one_cycle().unwrap();
+        assert_eq!(emu.pc_reg, 0x310);
+
+        let quirks = Quirks { jump_uses_vx: true, ..Quirks::default() };
+        let mut emu = Emulator::new(DisplaySize::Basic64x32, quirks, 0);
+        emu.mem_load_instr(program);
+        emu.cpu_one_cycle().unwrap();
+        emu.cpu_one_cycle().unwrap();
+        emu.cpu_one_cycle().unwrap();
+        assert_eq!(emu.pc_reg, 0x320);
+    }
+
+    #[test]
+    fn test_021_quirk_logic_resets_vf() {
+        let program = vec![
+            Instruction::LoadVal(0xF, 0x01),
+            Instruction::Or(1, 2),
+            Instruction::LoadVal(0xF, 0x01),
+            Instruction::And(1, 2),
+            Instruction::LoadVal(0xF, 0x01),
+            Instruction::Xor(1, 2),
+        ];
+
+        let mut emu = Emulator::new(DisplaySize::Basic64x32, Quirks::default(), 0);
+        emu.mem_load_instr(program.clone());
+        for _ in 0..3 {
+            emu.cpu_one_cycle().unwrap();
+            emu.cpu_one_cycle().unwrap();
+            assert_eq!(emu.regs[0xF], 1);
+        }
+
+        let quirks = Quirks { logic_resets_vf: true, ..Quirks::default() };
+        let mut emu = Emulator::new(DisplaySize::Basic64x32, quirks, 0);
+        emu.mem_load_instr(program);
+        for _ in 0..3 {
+            emu.cpu_one_cycle().unwrap();
+            emu.cpu_one_cycle().unwrap();
+            assert_eq!(emu.regs[0xF], 0);
+        }
+    }
+
+    #[test]
+    fn test_022_quirk_clip_sprites() {
+        let program = vec![
+            Instruction::LoadVal(1, 62),
+            Instruction::LoadVal(2, 30),
+            Instruction::LoadVal(3, 0x0),
+            Instruction::LoadSprite(3),
+            Instruction::Draw(1, 2, 5),
+        ];
+
+        let mut emu = Emulator::new(DisplaySize::Basic64x32, Quirks::default(), 0);
+        emu.mem_load_instr(program.clone());
+        for _ in 0..5 {
+            emu.cpu_one_cycle().unwrap();
+        }
+        assert_eq!(emu.screen.get(62, 30), 1);
+        assert_eq!(emu.screen.get(0, 30), 1);
+        assert_eq!(emu.screen.get(62, 0), 1);
+        assert_eq!(emu.screen.get(0, 2), 1);
+
+        let quirks = Quirks { clip_sprites: true, ..Quirks::default() };
+        let mut emu = Emulator::new(DisplaySize::Basic64x32, quirks, 0);
+        emu.mem_load_instr(program);
+        for _ in 0..5 {
+            emu.cpu_one_cycle().unwrap();
+        }
+        assert_eq!(emu.screen.get(62, 30), 1);
+        assert_eq!(emu.screen.get(63, 30), 1);
+        assert_eq!(emu.screen.get(62, 31), 1);
+        assert_eq!(emu.screen.get(0, 30), 0);
+        assert_eq!(emu.screen.get(62, 0), 0);
+        assert_eq!(emu.screen.get(0, 2), 0);
+    }
+
+    #[test]
+    fn test_023_quirk_display_wait() {
+        let program = vec![
+            Instruction::LoadSprite(0),
+            Instruction::Draw(0, 0, 5),
+            Instruction::Draw(0, 0, 5),
+        ];
+        let emu_with_clock = |quirks| {
+            let clock = ManualClock::new();
+            let mut emu = Emulator::new(DisplaySize::Basic64x32, quirks, 0);
+            emu.set_clock(Box::new(clock.clone()));
+            emu.mem_load_instr(program.clone());
+            (emu, clock)
+        };
+
+        let (mut emu, _) = emu_with_clock(Quirks::default());
+        emu.cpu_one_cycle().unwrap();
+        emu.cpu_one_cycle().unwrap();
+        assert_eq!(emu.pc_reg, 0x204);
+        assert_eq!(emu.screen.get(0, 0), 1);
+        emu.cpu_one_cycle().unwrap();
+        assert_eq!(emu.pc_reg, 0x206);
+        assert_eq!(emu.screen.get(0, 0), 0);
+
+        let (mut emu, clock) = emu_with_clock(Quirks { display_wait: true, ..Quirks::default() });
+        emu.cpu_one_cycle().unwrap();
+        emu.cpu_one_cycle().unwrap();
+        assert_eq!(emu.pc_reg, 0x202);
+        assert_eq!(emu.screen.get(0, 0), 0);
+        clock.advance(2);
+        emu.cpu_one_cycle().unwrap();
+        assert_eq!(emu.pc_reg, 0x204);
+        assert_eq!(emu.screen.get(0, 0), 1);
+        emu.cpu_one_cycle().unwrap();
+        assert_eq!(emu.pc_reg, 0x204);
+        assert_eq!(emu.screen.get(0, 0), 1);
+
+        // waiting on the last instruction of memory, PC has wrapped to 0
+        let mut emu = Emulator::new(DisplaySize::Basic64x32, Quirks { display_wait: true, ..Quirks::default() }, 0);
+        emu.set_mode(Mode::XoChip);
+        emu.memory[0xFFFE..].copy_from_slice(&Instruction::Draw(0, 0, 1).asm());
+        emu.pc_reg = 0xFFFE;
+        emu.cpu_one_cycle().unwrap();
+        assert_eq!(emu.pc_reg, 0xFFFE);
+        emu.memory[0xFFFE..].copy_from_slice(&Instruction::LoadKey(0).asm());
+        emu.memory_replaced();
+        emu.cpu_one_cycle().unwrap();
+        assert_eq!(emu.pc_reg, 0xFFFE);
+    }
+
+    #[test]
+    fn test_024_quirks_switch_at_runtime() {
+        let mut emu = Emulator::new(DisplaySize::Basic64x32, Quirks::default(), 0);
+        emu.mem_load_instr(vec![
+            Instruction::LoadVal(2, 0x04),
+            Instruction::ShiftRight(1, 2),
+            Instruction::ShiftRight(1, 2),
+        ]);
+        emu.cpu_one_cycle().unwrap();
+        emu.cpu_one_cycle().unwrap();
+        assert_eq!(emu.regs[1], 0x00);
+
+        emu.quirks = Quirks::vip();
+        emu.cpu_one_cycle().unwrap();
+        assert_eq!(emu.regs[1], 0x02);
+    }
+
+    #[test]
+    fn test_025_schip_hires_lores() {
+        let mut emu = Emulator::new(DisplaySize::Basic64x32, Quirks::default(), 0);
+        emu.mem_load_instr(vec![
+            Instruction::HighRes,
+            Instruction::LoadVal(1, 120),
+            Instruction::LoadVal(2, 60),
+            Instruction::LoadSprite(0),
+            Instruction::Draw(1, 2, 1),
+            Instruction::LowRes,
+        ]);
+        emu.cpu_one_cycle().unwrap();
+        assert_eq!(emu.resolution, (128, 64));
+        assert_eq!((emu.screen.width(), emu.screen.height()), (128, 64));
+
+        for _ in 0..4 {
+            emu.cpu_one_cycle().unwrap();
+        }
+        assert_eq!(emu.screen.get(120, 60), 1);
+        assert_eq!(emu.screen.get(123, 60), 1);
+        assert_eq!(emu.screen.get(124, 60), 0);
+
+        emu.cpu_one_cycle().unwrap();
+        assert_eq!(emu.resolution, (64, 32));
+        assert_eq!((emu.screen.width(), emu.screen.height()), (64, 32));
+    }
+
+    #[test]
+    fn test_026_schip_scroll() {
+        let mut emu = Emulator::new(DisplaySize::Basic64x32, Quirks::default(), 0);
+        emu.mem_load_instr(vec![
+            Instruction::LoadVal(1, 1),
+            Instruction::LoadSprite(1),
+            Instruction::Draw(0, 0, 1),
+            Instruction::ScrollDown(3),
+            Instruction::ScrollRight,
+            Instruction::ScrollLeft,
+            Instruction::ScrollLeft,
+        ]);
+        for _ in 0..3 {
+            emu.cpu_one_cycle().unwrap();
+        }
+        // top line of "1" is 0x20
+        assert_eq!(emu.screen.get(2, 0), 1);
+
+        emu.cpu_one_cycle().unwrap();
+        assert_eq!(emu.screen.get(2, 0), 0);
+        assert_eq!(emu.screen.get(2, 3), 1);
+
+        emu.cpu_one_cycle().unwrap();
+        assert_eq!(emu.screen.get(2, 3), 0);
+        assert_eq!(emu.screen.get(6, 3), 1);
+
+        emu.cpu_one_cycle().unwrap();
+        assert_eq!(emu.screen.get(6, 3), 0);
+        assert_eq!(emu.screen.get(2, 3), 1);
+
+        // pixels scrolled out of the screen are lost
+        emu.cpu_one_cycle().unwrap();
+        assert_eq!(emu.screen, Framebuffer::new(64, 32));
+    }
+
+    #[test]
+    fn test_027_schip_big_sprite() {
+        let mut emu = Emulator::new(DisplaySize::Basic64x32, Quirks::default(), 0);
+        emu.mem_load_instr(vec![
+            Instruction::LoadVal(1, 8),
+            Instruction::LoadBigSprite(1),
+            Instruction::LoadAddr(0x300),
+            Instruction::Draw(0, 0, 0),
+            Instruction::Draw(0, 0, 0),
+        ]);
+        emu.memory[0x300] = 0x80;
+        emu.memory[0x301] = 0x01;
+        emu.memory[0x31E] = 0x80;
+        emu.memory[0x31F] = 0x01;
+
+        emu.cpu_one_cycle().unwrap();
+        emu.cpu_one_cycle().unwrap();
+        assert_eq!(emu.i_reg, 0x50 + 8 * 10);
+        assert_eq!(emu.memory[emu.i_reg as usize], 0xFF);
+
+        emu.cpu_one_cycle().unwrap();
+        emu.cpu_one_cycle().unwrap();
+        assert_eq!(emu.regs[0xF], 0);
+        assert_eq!(emu.screen.get(0, 0), 1);
+        assert_eq!(emu.screen.get(15, 0), 1);
+        assert_eq!(emu.screen.get(1, 0), 0);
+        assert_eq!(emu.screen.get(0, 15), 1);
+        assert_eq!(emu.screen.get(15, 15), 1);
+        assert_eq!(emu.screen.get(0, 16), 0);
+
+        emu.cpu_one_cycle().unwrap();
+        assert_eq!(emu.regs[0xF], 1);
+        assert_eq!(emu.screen.get(0, 0), 0);
+        assert_eq!(emu.screen.get(15, 15), 0);
+    }
+
+    #[test]
+    fn test_028_schip_rpl_flags_exit() {
+        let mut emu = Emulator::new(DisplaySize::Basic64x32, Quirks::default(), 0);
+        emu.mem_load_instr(vec![
+            Instruction::LoadVal(0, 0x12),
+            Instruction::LoadVal(1, 0x34),
+            Instruction::StoreFlags(1),
+            Instruction::LoadVal(0, 0x00),
+            Instruction::LoadVal(1, 0x00),
+            Instruction::LoadFlags(1),
+            Instruction::Exit,
+            Instruction::LoadVal(0, 0xFF),
+        ]);
+        for _ in 0..6 {
+            emu.cpu_one_cycle().unwrap();
+        }
+        assert_eq!(emu.regs[0], 0x12);
+        assert_eq!(emu.regs[1], 0x34);
+
+        emu.cpu_one_cycle().unwrap();
+        assert!(emu.exited);
+        assert_eq!(emu.pc_reg, 0x20E);
+
+        emu.cpu_one_cycle().unwrap();
+        assert_eq!(emu.pc_reg, 0x20E);
+        assert_eq!(emu.regs[0], 0x12);
+    }
+
+    #[test]
+    fn test_029_xochip_long_addr() {
+        let mut emu = Emulator::new(DisplaySize::Basic64x32, Quirks::default(), 0);
+        assert_eq!(emu.memory.len(), 0x1000);
+        emu.set_mode(Mode::XoChip);
+        assert_eq!(emu.memory.len(), 0x10000);
+
+        emu.mem_load_instr(vec![
+            Instruction::LoadLongAddr,
+            Instruction::Sys(0xBCD),
+            Instruction::SkipValEq(0, 0),
+            Instruction::LoadLongAddr,
+            Instruction::Sys(0x123),
+            Instruction::LoadVal(1, 0x42),
+            Instruction::StoreRegs(1),
+        ]);
+        emu.cpu_one_cycle().unwrap();
+        assert_eq!(emu.pc_reg, 0x204);
+        assert_eq!(emu.i_reg, 0x0BCD);
+
+        // skipping over F000 NNNN skips 4 bytes
+        emu.cpu_one_cycle().unwrap();
+        assert_eq!(emu.pc_reg, 0x20A);
+
+        emu.i_reg = 0xFF00;
+        emu.cpu_one_cycle().unwrap();
+        emu.cpu_one_cycle().unwrap();
+        assert_eq!(emu.memory[0xFF01], 0x42);
+
+        // I wraps around after the last byte
+        emu.quirks.load_store_increments_i = true;
+        emu.mem_load_bin(vec![0xF0, 0x00, 0xFF, 0xF0, 0xFF, 0x65, 0xF0, 0x00, 0xFF, 0xF0, 0xFF, 0x55]);
+        emu.memory[0xFFF0] = 0x24;
+        emu.pc_reg = 0x200;
+        emu.cpu_one_cycle().unwrap();
+        emu.cpu_one_cycle().unwrap();
+        assert_eq!(emu.i_reg, 0);
+        assert_eq!(emu.regs[0], 0x24);
+        emu.regs[0xF] = 0x11;
+        emu.cpu_one_cycle().unwrap();
+        emu.cpu_one_cycle().unwrap();
+        assert_eq!(emu.i_reg, 0);
+        assert_eq!(emu.memory[0xFFFF], 0x11);
+    }
+
+    #[test]
+    fn test_030_xochip_save_load_range() {
+        let mut emu = Emulator::new(DisplaySize::Basic64x32, Quirks::default(), 0);
+        emu.mem_load_instr(vec![
+            Instruction::LoadVal(2, 0x22),
+            Instruction::LoadVal(3, 0x33),
+            Instruction::LoadVal(4, 0x44),
+            Instruction::LoadAddr(0x600),
+            Instruction::SaveRange(2, 4),
+            Instruction::LoadAddr(0x700),
+            Instruction::SaveRange(4, 2),
+            Instruction::LoadAddr(0x600),
+            Instruction::LoadRange(7, 5),
+        ]);
+        for _ in 0..5 {
+            emu.cpu_one_cycle().unwrap();
+        }
+        assert_eq!(emu.memory[0x600..0x604], [0x22, 0x33, 0x44, 0x00]);
+        assert_eq!(emu.i_reg, 0x600);
+
+        emu.cpu_one_cycle().unwrap();
+        emu.cpu_one_cycle().unwrap();
+        assert_eq!(emu.memory[0x700..0x703], [0x44, 0x33, 0x22]);
+
+        emu.cpu_one_cycle().unwrap();
+        emu.cpu_one_cycle().unwrap();
+        assert_eq!(emu.regs[7], 0x22);
+        assert_eq!(emu.regs[6], 0x33);
+        assert_eq!(emu.regs[5], 0x44);
+    }
+
+    #[test]
+    fn test_031_xochip_planes() {
+        let mut emu = Emulator::new(DisplaySize::Basic64x32, Quirks::default(), 0);
+        emu.set_mode(Mode::XoChip);
+        emu.mem_load_instr(vec![
+            Instruction::LoadAddr(0x300),
+            Instruction::SelectPlanes(2),
+            Instruction::Draw(0, 0, 1),
+            Instruction::SelectPlanes(3),
+            Instruction::Draw(0, 0, 1),
+            Instruction::SelectPlanes(1),
+            Instruction::Cls,
+            Instruction::SelectPlanes(2),
+            Instruction::ScrollDown(1),
+        ]);
+        emu.memory[0x300] = 0xC0;
+        emu.memory[0x301] = 0xA0;
+
+        emu.cpu_one_cycle().unwrap();
+        emu.cpu_one_cycle().unwrap();
+        emu.cpu_one_cycle().unwrap();
+        assert_eq!(emu.screen.get(0, 0), 2);
+        assert_eq!(emu.screen.get(1, 0), 2);
+        assert_eq!(emu.screen.get(2, 0), 0);
+
+        // plane 1 uses 0x300, plane 2 uses 0x301
+        emu.cpu_one_cycle().unwrap();
+        emu.cpu_one_cycle().unwrap();
+        assert_eq!(emu.regs[0xF], 1);
+        assert_eq!(emu.screen.get(0, 0), 1);
+        assert_eq!(emu.screen.get(1, 0), 3);
+        assert_eq!(emu.screen.get(2, 0), 2);
+
+        // only the selected plane is cleared
+        emu.cpu_one_cycle().unwrap();
+        emu.cpu_one_cycle().unwrap();
+        assert_eq!(emu.screen.get(0, 0), 0);
+        assert_eq!(emu.screen.get(1, 0), 2);
+        assert_eq!(emu.screen.get(2, 0), 2);
+
+        emu.cpu_one_cycle().unwrap();
+        emu.cpu_one_cycle().unwrap();
+        assert_eq!(emu.screen.get(1, 0), 0);
+        assert_eq!(emu.screen.get(1, 1), 2);
+        assert_eq!(emu.screen.get(2, 1), 2);
+    }
+
+    #[test]
+    fn test_032_xochip_audio() {
+        let mut emu = Emulator::new(DisplaySize::Basic64x32, Quirks::default(), 0);
+        emu.mem_load_instr(vec![
+            Instruction::LoadAddr(0x300),
+            Instruction::LoadAudio,
+            Instruction::LoadVal(1, 112),
+            Instruction::SetPitch(1),
+        ]);
+        for x in 0..16 {
+            emu.memory[0x300 + x] = x as u8;
+        }
+        assert_eq!(emu.audio_rate(), 4000.0);
+
+        emu.cpu_one_cycle().unwrap();
+        emu.cpu_one_cycle().unwrap();
+        assert_eq!(emu.audio_pattern()[0], 0);
+        assert_eq!(emu.audio_pattern()[15], 15);
+
+        emu.cpu_one_cycle().unwrap();
+        emu.cpu_one_cycle().unwrap();
+        assert_eq!(emu.audio_rate(), 8000.0);
+    }
+
+    #[test]
+    fn test_033_invalid_opcode() {
+        let mut emu = Emulator::new(DisplaySize::Basic64x32, Quirks::default(), 0);
+        emu.mem_load_bin(vec![0x60, 0x01, 0x80, 0x1F]);
+        emu.cpu_one_cycle().unwrap();
+        let err = emu.cpu_one_cycle().unwrap_err();
+        assert_eq!(err, EmulatorError::InvalidOpcode { pc: 0x202, opcode: 0x801F });
+        assert_eq!(err.pc(), 0x202);
+        assert_eq!(err.opcode(), 0x801F);
+        assert_eq!(err.to_string(), "invalid opcode 801F at 202");
+    }
+
+    #[test]
+    fn test_034_stack_overflow_underflow() {
+        let mut emu = Emulator::new(DisplaySize::Basic64x32, Quirks::default(), 0);
+        emu.mem_load_instr(vec![
+            Instruction::Call(0x200),
+        ]);
+        for _ in 0..16 {
+            emu.cpu_one_cycle().unwrap();
+        }
+        assert_eq!(emu.sp_reg, 16);
+        assert_eq!(emu.cpu_one_cycle(), Err(EmulatorError::StackOverflow { pc: 0x200, opcode: 0x2200 }));
+
+        let mut emu = Emulator::new(DisplaySize::Basic64x32, Quirks::default(), 0);
+        emu.mem_load_instr(vec![
+            Instruction::Ret,
+        ]);
+        assert_eq!(emu.cpu_one_cycle(), Err(EmulatorError::StackUnderflow { pc: 0x200, opcode: 0x00EE }));
+    }
+
+    #[test]
+    fn test_035_memory_out_of_range() {
+        let mut emu = Emulator::new(DisplaySize::Basic64x32, Quirks::default(), 0);
+        emu.mem_load_instr(vec![
+            Instruction::LoadAddr(0xFFE),
+            Instruction::Bcd(0),
+            Instruction::StoreRegs(1),
+            Instruction::StoreRegs(2),
+            Instruction::Draw(0, 0, 3),
+            Instruction::Jump(0xFFF),
+        ]);
+        emu.cpu_one_cycle().unwrap();
+        assert_eq!(emu.cpu_one_cycle(), Err(EmulatorError::MemoryOutOfRange { pc: 0x202, opcode: 0xF033, addr: 0x1000 }));
+        assert_eq!(emu.memory[0xFFE], 0);
+
+        emu.cpu_one_cycle().unwrap();
+        assert_eq!(emu.cpu_one_cycle(), Err(EmulatorError::MemoryOutOfRange { pc: 0x206, opcode: 0xF255, addr: 0x1000 }));
+        assert_eq!(emu.cpu_one_cycle(), Err(EmulatorError::MemoryOutOfRange { pc: 0x208, opcode: 0xD003, addr: 0x1000 }));
+
+        // the instruction itself cannot be fetched
+        emu.cpu_one_cycle().unwrap();
+        assert_eq!(emu.cpu_one_cycle(), Err(EmulatorError::MemoryOutOfRange { pc: 0xFFF, opcode: 0, addr: 0x1000 }));
+    }
+
+    #[test]
+    fn test_036_random() {
+        let run = |seed, mask| {
+            let mut emu = Emulator::new(DisplaySize::Basic64x32, Quirks::default(), seed);
+            emu.mem_load_instr(vec![Instruction::Random(3, mask), Instruction::Jump(0x200)]);
+            (0..16).map(|_| {
+                emu.cpu_one_cycle().unwrap();
+                emu.cpu_one_cycle().unwrap();
+                emu.regs[3]
+            }).collect::<Vec<u8>>()
+        };
+
+        assert_eq!(run(1, 0xFF), run(1, 0xFF));
+        assert_ne!(run(1, 0xFF), run(2, 0xFF));
+        assert!(run(1, 0x0F).iter().all(|val| *val <= 0x0F));
+    }
+
+    #[test]
+    fn test_037_timers_follow_clock() {
+        let clock = ManualClock::new();
+        clock.advance(100);
+        let mut emu = Emulator::new(DisplaySize::Basic64x32, Quirks::default(), 0);
+        emu.set_clock(Box::new(clock.clone()));
+        emu.mem_load_instr(vec![
+            Instruction::LoadVal(0, 10),
+            Instruction::SetDelayTimer(0),
+            Instruction::SetSoundTimer(0),
+            Instruction::Jump(0x206),
+        ]);
+        for _ in 0..3 {
+            emu.cpu_one_cycle().unwrap();
+        }
+        assert_eq!((emu.dt_reg, emu.st_reg), (10, 10));
+
+        clock.advance(1);
+        emu.cpu_one_cycle().unwrap();
+        emu.cpu_one_cycle().unwrap();
+        assert_eq!((emu.dt_reg, emu.st_reg), (9, 9));
+
+        clock.advance(4);
+        emu.cpu_one_cycle().unwrap();
+        assert_eq!((emu.dt_reg, emu.st_reg), (5, 5));
+
+        clock.advance(300);
+        emu.cpu_one_cycle().unwrap();
+        assert_eq!((emu.dt_reg, emu.st_reg), (0, 0));
+    }
+
+    #[test]
+    fn test_038_run_frame() {
+        let mut emu = Emulator::new(DisplaySize::Basic64x32, Quirks::default(), 0);
+        emu.instructions_per_frame = 4;
+        emu.mem_load_instr(vec![
+            Instruction::LoadVal(0, 2),
+            Instruction::SetDelayTimer(0),
+            Instruction::SetSoundTimer(0),
+            Instruction::LoadSprite(0),
+            Instruction::Draw(0, 0, 5),     // 0x208
+            Instruction::LoadDelayTimer(1),
+            Instruction::LoadKey(2),        // 0x20C
+            Instruction::Exit,
+        ]);
+
+        let frame = emu.run_frame().unwrap();
+        assert_eq!(frame, Frame { redraw: false, sound: true, waiting_for_key: false });
+        assert_eq!(emu.pc_reg, 0x208);
+        assert_eq!((emu.dt_reg, emu.st_reg), (1, 1));
+
+        let frame = emu.run_frame().unwrap();
+        assert_eq!(frame, Frame { redraw: true, sound: true, waiting_for_key: true });
+        assert_eq!(emu.regs[1], 1);
+        assert_eq!((emu.dt_reg, emu.st_reg), (0, 0));
+
+        let frame = emu.run_frame().unwrap();
+        assert_eq!(frame, Frame { redraw: false, sound: false, waiting_for_key: true });
+        assert_eq!(emu.pc_reg, 0x20C);
+
+        emu.keys[7] = true;
+        let frame = emu.run_frame().unwrap();
+        assert_eq!(frame, Frame { redraw: false, sound: false, waiting_for_key: false });
+        assert_eq!(emu.regs[2], 7);
+        assert!(emu.exited);
+    }
+
+    #[test]
+    fn test_039_vip_timing() {
+        let mut emu = Emulator::new(DisplaySize::Basic64x32, Quirks::default(), 0);
+        emu.timing = Timing::Vip;
+        emu.mem_load_instr(vec![
+            Instruction::AddVal(0, 1),
+            Instruction::Jump(0x200),
+        ]);
+        // 2598 cycles, 50 for each add and 52 for each jump
+        emu.run_frame().unwrap();
+        assert_eq!(emu.regs[0], 26);
+        assert_eq!(emu.vip_cycles, -2);
+        emu.run_frame().unwrap();
+        assert_eq!(emu.regs[0], 51);
+
+        // one sprite per frame, drawn after the display interrupt
+        let mut emu = Emulator::new(DisplaySize::Basic64x32, Quirks::default(), 0);
+        emu.timing = Timing::Vip;
+        emu.mem_load_instr(vec![
+            Instruction::LoadSprite(0),
+            Instruction::Draw(0, 0, 5),     // 0x202
+            Instruction::AddVal(1, 1),
+            Instruction::Jump(0x202),
+        ]);
+        emu.run_frame().unwrap();
+        assert_eq!(emu.pc_reg, 0x202);
+        assert_eq!(emu.screen.get(0, 0), 0);
+        for frame in 1..4 {
+            emu.run_frame().unwrap();
+            assert_eq!(emu.pc_reg, 0x202);
+            assert_eq!(emu.regs[1], frame);
+            assert_eq!(emu.screen.get(0, 0), frame % 2);
+        }
+    }
+
+    #[test]
+    fn test_040_machine_code() {
+        let program = vec![
+            0x02, 0x06,         // SYS 0x206
+            0x12, 0x02,         // JP 0x202
+            0x00, 0x00,
+            0xF8, 0xF5, 0xA6,   // LDI 0xF5, PLO R6
+            0xF8, 0x0E, 0xB6,   // LDI 0x0E, PHI R6
+            0xF8, 0x2A, 0x56,   // LDI 0x2A, STR R6: V5 = 0x2A
+            0xF8, 0x0F, 0xB7,   // LDI 0x0F, PHI R7
+            0xF8, 0x00, 0xA7,   // LDI 0x00, PLO R7
+            0xF8, 0x80, 0x57,   // LDI 0x80, STR R7: top left pixel
+            0xF8, 0x34, 0xAA,   // LDI 0x34, PLO RA: I = 0x34
+            0xD4,               // SEP R4
+        ];
+        let mut emu = Emulator::new(DisplaySize::Basic64x32, Quirks::default(), 0);
+        emu.mem_load_bin(program.clone());
+        emu.cpu_one_cycle().unwrap();
+        assert_eq!((emu.pc_reg, emu.regs[5], emu.i_reg), (0x202, 0, 0));
+
+        let quirks = Quirks { machine_code: true, ..Quirks::default() };
+        let mut emu = Emulator::new(DisplaySize::Basic64x32, quirks, 0);
+        emu.mem_load_bin(program);
+        emu.cpu_one_cycle().unwrap();
+        assert_eq!((emu.pc_reg, emu.regs[5], emu.i_reg), (0x202, 0x2A, 0x34));
+        assert_eq!(emu.screen.get(0, 0), 1);
+        assert!(emu.redraw);
+
+        // BR to itself never returns
+        let mut emu = Emulator::new(DisplaySize::Basic64x32, quirks, 0);
+        emu.mem_load_bin(vec![0x02, 0x06, 0x00, 0x00, 0x00, 0x00, 0x30, 0x06]);
+        assert_eq!(emu.cpu_one_cycle(), Err(EmulatorError::MachineCodeTimeout { pc: 0x200, opcode: 0x0206 }));
+
+        // no room for the VIP registers and screen
+        let mut emu = Emulator::builder().memory_size(0x800).rom(&[0x02, 0x06]).build().unwrap();
+        emu.quirks.machine_code = true;
+        assert_eq!(emu.cpu_one_cycle(), Err(EmulatorError::MemoryOutOfRange { pc: 0x200, opcode: 0x0206, addr: 0x800 }));
+    }
+
+    #[test]
+    fn test_041_hires_chip8() {
+        let mut rom = vec![0x12, 0x60];
+        rom.resize(0xC0, 0);
+        rom.extend(Instruction::assemble(&[
+            Instruction::LoadVal(1, 40),
+            Instruction::LoadSprite(0),
+            Instruction::Draw(0, 1, 5),
+            Instruction::Sys(0x230),
+        ]));
+        assert_eq!(Mode::detect(&rom), Mode::HiresChip8);
+        assert_eq!(Mode::detect(&[0x00, 0xE0]), Mode::Chip8);
+
+        let mut emu = Emulator::new(DisplaySize::Basic64x32, Quirks::default(), 0);
+        emu.set_mode(Mode::HiresChip8);
+        emu.load_rom(&rom);
+        assert_eq!((emu.pc_reg, emu.resolution), (0x2C0, (64, 64)));
+        for _ in 0..3 {
+            emu.cpu_one_cycle().unwrap();
+        }
+        assert_eq!(emu.screen.get(0, 40), 1);
+        emu.cpu_one_cycle().unwrap();
+        assert_eq!(emu.screen.get(0, 40), 0);
+
+        emu.reset();
+        assert_eq!(emu.pc_reg, 0x2C0);
+        emu.set_mode(Mode::Chip8);
+        assert_eq!(emu.resolution, (64, 32));
+
+        // plain CHIP-8 just follows the jump and ignores 0230
+        emu.load_rom(&rom);
+        emu.cpu_one_cycle().unwrap();
+        assert_eq!(emu.pc_reg, 0x260);
+        emu.screen.set(0, 0, 1);
+        emu.pc_reg = 0x2C6;
+        emu.cpu_one_cycle().unwrap();
+        assert_eq!(emu.screen.get(0, 0), 1);
+    }
+
+    #[test]
+    fn test_042_profiles() {
+        let mut emu = Emulator::new(DisplaySize::Basic64x32, Quirks::default(), 0);
+        emu.set_profile(Profile::eti660()).unwrap();
+        assert_eq!(emu.resolution, (64, 48));
+        emu.load_rom(&[0x61, 0x01, 0xF1, 0x29, 0x16, 0x04]);
+        assert_eq!(emu.pc_reg, 0x600);
+        assert_eq!(emu.memory[0x200], 0);
+        emu.cpu_one_cycle().unwrap();
+        emu.cpu_one_cycle().unwrap();
+        assert_eq!(emu.i_reg, 5);
+        emu.cpu_one_cycle().unwrap();
+        assert_eq!(emu.pc_reg, 0x604);
+        emu.reset();
+        assert_eq!(emu.pc_reg, 0x600);
+
+        // the fonts move with the profile
+        emu.set_profile(Profile { font_address: 0x50, ..Profile::chip8() }).unwrap();
+        assert_eq!(emu.memory[..5], [0; 5]);
+        assert_eq!(emu.memory[0x55..0x5A], SPRITES[5..10]);
+        emu.regs[1] = 1;
+        emu.exec(Instruction::LoadSprite(1)).unwrap();
+        assert_eq!(emu.i_reg, 0x55);
+        emu.exec(Instruction::LoadBigSprite(1)).unwrap();
+        assert_eq!(emu.i_reg, 0xAA);
+        assert_eq!(emu.memory[0xAA..0xB4], BIG_SPRITES[10..20]);
+
+        assert_eq!(Profile::from_name("eti660"), Some(Profile::eti660()));
+        assert_eq!(Profile::from_name("eti"), None);
+
+        // profiles that don't fit are rejected
+        assert_eq!(emu.set_profile(Profile { font_address: 0xFF0, ..Profile::chip8() }),
+            Err(ConfigError::FontAddress(0xFF0)));
+        assert_eq!(emu.set_profile(Profile { load_address: 0x1000, ..Profile::chip8() }),
+            Err(ConfigError::LoadAddress(0x1000)));
+        assert_eq!(emu.profile().font_address, 0x50);
+
+        // memory shrunk under the load address, nothing is loaded
+        emu.set_mode(Mode::XoChip);
+        emu.set_profile(Profile { load_address: 0x2000, entry_point: 0x200, ..Profile::chip8() }).unwrap();
+        emu.set_mode(Mode::Chip8);
+        emu.load_rom(&[0x12, 0x00]);
+        assert_eq!(emu.pc_reg, 0x200);
+    }
+
+    #[test]
+    fn test_043_dirty_rects() {
+        let mut emu = Emulator::new(DisplaySize::Basic64x32, Quirks::default(), 0);
+        emu.mem_load_instr(vec![
+            Instruction::LoadVal(0, 62),
+            Instruction::LoadVal(1, 4),
+            Instruction::Draw(0, 1, 5),
+            Instruction::Cls,
+        ]);
+        emu.screen.clear_dirty();
+        for _ in 0..3 {
+            emu.cpu_one_cycle().unwrap();
+        }
+        // the sprite wraps around the right edge
+        assert_eq!(emu.screen.dirty_rects().copied().collect::<Vec<_>>(), vec![
+            Rect { x: 62, y: 4, width: 2, height: 5 },
+            Rect { x: 0, y: 4, width: 6, height: 5 },
+        ]);
+        assert_eq!(emu.screen.row(0, 4), &[0xC0, 0, 0, 0, 0, 0, 0, 0x03]);
+
+        emu.screen.clear_dirty();
+        emu.cpu_one_cycle().unwrap();
+        assert_eq!(emu.screen.dirty_rects().copied().collect::<Vec<_>>(), vec![
+            Rect { x: 0, y: 0, width: 64, height: 32 },
+        ]);
+    }
+
+    #[test]
+    fn test_044_self_modifying_code() {
+        let mut emu = Emulator::new(DisplaySize::Basic64x32, Quirks::default(), 0);
+        emu.mem_load_instr(vec![
+            Instruction::LoadVal(0, 0x00),
+            Instruction::LoadVal(1, 0xE0),
+            Instruction::LoadAddr(0x20A),
+            Instruction::Jump(0x20A),
+            Instruction::StoreRegs(1),
+            Instruction::Jump(0x20A),       // becomes CLS
+            Instruction::Jump(0x20C),
+        ]);
+        for _ in 0..5 {
+            emu.cpu_one_cycle().unwrap();
+        }
+        // decoded once as a jump to itself
+        assert_eq!(emu.pc_reg, 0x20A);
+        emu.pc_reg = 0x208;
+        emu.cpu_one_cycle().unwrap();
+        emu.screen.clear_dirty();
+        emu.cpu_one_cycle().unwrap();
+        assert_eq!(emu.pc_reg, 0x20C);
+        assert!(emu.screen.is_dirty());
+    }
 }
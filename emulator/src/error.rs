@@ -0,0 +1,139 @@
+use crate::instruction::Address;
+use std::fmt;
+
+/// Reason the emulator stopped executing a program.
+///
+/// Every variant carries the address of the faulting instruction and its raw
+/// opcode (0 when the instruction itself could not be fetched).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EmulatorError {
+    InvalidOpcode { pc: Address, opcode: u16 },
+    StackOverflow { pc: Address, opcode: u16 },
+    StackUnderflow { pc: Address, opcode: u16 },
+    MemoryOutOfRange { pc: Address, opcode: u16, addr: usize },
+    MachineCodeTimeout { pc: Address, opcode: u16 },
+}
+
+impl EmulatorError {
+    pub fn pc(&self) -> Address {
+        match *self {
+            EmulatorError::InvalidOpcode { pc, .. } => pc,
+            EmulatorError::StackOverflow { pc, .. } => pc,
+            EmulatorError::StackUnderflow { pc, .. } => pc,
+            EmulatorError::MemoryOutOfRange { pc, .. } => pc,
+            EmulatorError::MachineCodeTimeout { pc, .. } => pc,
+        }
+    }
+    pub fn opcode(&self) -> u16 {
+        match *self {
+            EmulatorError::InvalidOpcode { opcode, .. } => opcode,
+            EmulatorError::StackOverflow { opcode, .. } => opcode,
+            EmulatorError::StackUnderflow { opcode, .. } => opcode,
+            EmulatorError::MemoryOutOfRange { opcode, .. } => opcode,
+            EmulatorError::MachineCodeTimeout { opcode, .. } => opcode,
+        }
+    }
+}
+
+impl fmt::Display for EmulatorError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            EmulatorError::InvalidOpcode { pc, opcode } =>
+                write!(f, "invalid opcode {:04X} at {:03X}", opcode, pc),
+            EmulatorError::StackOverflow { pc, opcode } =>
+                write!(f, "stack overflow executing {:04X} at {:03X}", opcode, pc),
+            EmulatorError::StackUnderflow { pc, opcode } =>
+                write!(f, "stack underflow executing {:04X} at {:03X}", opcode, pc),
+            EmulatorError::MemoryOutOfRange { pc, opcode, addr } =>
+                write!(f, "memory access to {:X} out of range executing {:04X} at {:03X}", addr, opcode, pc),
+            EmulatorError::MachineCodeTimeout { pc, opcode } =>
+                write!(f, "machine code called by {:04X} at {:03X} did not return", opcode, pc),
+        }
+    }
+}
+
+impl std::error::Error for EmulatorError {}
+
+/// Configuration rejected by `EmulatorBuilder::build`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConfigError {
+    /// between 1 and 16 levels are supported
+    StackDepth(usize),
+    /// memory must hold the interpreter area and fit 16-bit addresses
+    MemorySize(usize),
+    /// the font doesn't fit in memory
+    FontAddress(Address),
+    /// the load address or entry point is outside memory
+    LoadAddress(Address),
+    EntryPoint(Address),
+    /// the ROM doesn't fit between the load address and the end of memory
+    RomTooLarge { len: usize, space: usize },
+    /// the ROM would be loaded over the font
+    RomOverlapsFont,
+    /// 1802 machine code needs the 4 KiB of a VIP
+    MachineCodeMemory(usize),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ConfigError::StackDepth(depth) => write!(f, "unsupported stack depth {}", depth),
+            ConfigError::MemorySize(size) => write!(f, "unsupported memory size {:X}", size),
+            ConfigError::FontAddress(addr) => write!(f, "font at {:03X} doesn't fit in memory", addr),
+            ConfigError::LoadAddress(addr) => write!(f, "load address {:03X} is outside memory", addr),
+            ConfigError::EntryPoint(addr) => write!(f, "entry point {:03X} is outside memory", addr),
+            ConfigError::RomTooLarge { len, space } =>
+                write!(f, "ROM of {} bytes doesn't fit in the {} bytes available", len, space),
+            ConfigError::RomOverlapsFont => write!(f, "ROM would overwrite the font"),
+            ConfigError::MachineCodeMemory(size) =>
+                write!(f, "machine code needs 4 KiB of memory, only {:X} bytes are available", size),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+/// Failure of a single instruction, before the emulator knows where it happened.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Fault {
+    InvalidOpcode,
+    StackOverflow,
+    StackUnderflow,
+    MemoryOutOfRange(usize),
+    MachineCodeTimeout,
+}
+
+impl Fault {
+    pub(crate) fn at(self, pc: Address, opcode: u16) -> EmulatorError {
+        match self {
+            Fault::InvalidOpcode => EmulatorError::InvalidOpcode { pc, opcode },
+            Fault::StackOverflow => EmulatorError::StackOverflow { pc, opcode },
+            Fault::StackUnderflow => EmulatorError::StackUnderflow { pc, opcode },
+            Fault::MemoryOutOfRange(addr) => EmulatorError::MemoryOutOfRange { pc, opcode, addr },
+            Fault::MachineCodeTimeout => EmulatorError::MachineCodeTimeout { pc, opcode },
+        }
+    }
+}
+
+/// Command line option rejected by `options::configure`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum OptionError {
+    UnknownOption(String),
+    UnknownPreset(String),
+    UnknownProfile(String),
+    /// the database directory couldn't be read or parsed
+    Database(String),
+}
+
+impl fmt::Display for OptionError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            OptionError::UnknownOption(name) => write!(f, "unknown option {}", name),
+            OptionError::UnknownPreset(name) => write!(f, "unknown preset {}", name),
+            OptionError::UnknownProfile(name) => write!(f, "unknown profile {}", name),
+            OptionError::Database(reason) => write!(f, "invalid database: {}", reason),
+        }
+    }
+}
+
+impl std::error::Error for OptionError {}
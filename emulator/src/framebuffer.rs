@@ -0,0 +1,313 @@
+/// XO-CHIP has two bitplanes, CHIP-8 and SUPER-CHIP only use the first
+pub const PLANES: usize = 2;
+
+/* dirty rectangles kept before they are merged into their bounding box */
+const MAX_DIRTY: usize = 16;
+
+/// Area of the screen, in pixels
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rect {
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize,
+}
+
+impl Rect {
+    fn contains(&self, other: &Rect) -> bool {
+        other.x >= self.x && other.y >= self.y
+            && other.x + other.width <= self.x + self.width
+            && other.y + other.height <= self.y + self.height
+    }
+    fn union(&self, other: &Rect) -> Rect {
+        let (x, y) = (self.x.min(other.x), self.y.min(other.y));
+
+        Rect {
+            x,
+            y,
+            width: (self.x + self.width).max(other.x + other.width) - x,
+            height: (self.y + self.height).max(other.y + other.height) - y,
+        }
+    }
+    /// Coordinates of every pixel, row by row
+    pub fn points(&self) -> impl Iterator<Item = (usize, usize)> {
+        let Rect { x, y, width, height } = *self;
+
+        (y..y + height).flat_map(move |row| (x..x + width).map(move |col| (col, row)))
+    }
+}
+
+/// Screen with one bit per pixel in each plane, stored row by row with the
+/// leftmost pixel in the most significant bit like CHIP-8 sprites.
+///
+/// Changes are recorded as dirty rectangles until `clear_dirty`, a new
+/// framebuffer is dirty all over.
+#[derive(Debug, Clone)]
+pub struct Framebuffer {
+    width: usize,
+    height: usize,
+    /* bytes per row */
+    pitch: usize,
+    planes: [Vec<u8>; PLANES],
+    dirty: Vec<Rect>,
+}
+
+impl PartialEq for Framebuffer {
+    /// Same size and pixels, whatever changed since
+    fn eq(&self, other: &Framebuffer) -> bool {
+        self.width == other.width && self.height == other.height && self.planes == other.planes
+    }
+}
+
+impl Eq for Framebuffer {}
+
+impl Framebuffer {
+    pub fn new(width: usize, height: usize) -> Self {
+        let pitch = width.div_ceil(8);
+
+        Framebuffer {
+            width,
+            height,
+            pitch,
+            planes: [vec![0; pitch * height], vec![0; pitch * height]],
+            dirty: vec![Rect { x: 0, y: 0, width, height }],
+        }
+    }
+    pub fn width(&self) -> usize {
+        self.width
+    }
+    pub fn height(&self) -> usize {
+        self.height
+    }
+    fn bit(&self, x: usize, y: usize) -> (usize, u8) {
+        (y * self.pitch + x / 8, 0x80 >> (x % 8))
+    }
+    /// Bitmask of the planes the pixel is lit on
+    pub fn get(&self, x: usize, y: usize) -> u8 {
+        let (idx, bit) = self.bit(x, y);
+
+        (0..PLANES).filter(|plane| self.planes[*plane][idx] & bit != 0)
+            .fold(0, |mask, plane| mask | 1 << plane)
+    }
+    /// Light the pixel on the planes in `mask` only
+    pub fn set(&mut self, x: usize, y: usize, mask: u8) {
+        let (idx, bit) = self.bit(x, y);
+
+        for (plane, bits) in self.planes.iter_mut().enumerate() {
+            if mask & 1 << plane != 0 {
+                bits[idx] |= bit;
+            } else {
+                bits[idx] &= !bit;
+            }
+        }
+        self.mark_dirty(Rect { x, y, width: 1, height: 1 });
+    }
+    /// Flip the pixel on the planes in `mask`, telling if it was lit on any
+    /// of them. The caller marks what it drew as dirty.
+    pub(crate) fn toggle(&mut self, x: usize, y: usize, mask: u8) -> bool {
+        let (idx, bit) = self.bit(x, y);
+        let mut erased = false;
+
+        for (plane, bits) in self.planes.iter_mut().enumerate() {
+            if mask & 1 << plane != 0 {
+                erased |= bits[idx] & bit != 0;
+                bits[idx] ^= bit;
+            }
+        }
+        erased
+    }
+    /// Pixels of one row of a plane, `width` bits rounded up to whole bytes
+    pub fn row(&self, plane: usize, y: usize) -> &[u8] {
+        &self.planes[plane][y * self.pitch..(y + 1) * self.pitch]
+    }
+    /// Replace one row of a plane, bits past the width are dropped
+    pub fn set_row(&mut self, plane: usize, y: usize, bits: &[u8]) {
+        let (start, pitch) = (y * self.pitch, self.pitch);
+        let row = &mut self.planes[plane][start..start + pitch];
+
+        row.copy_from_slice(&bits[..pitch]);
+        if !self.width.is_multiple_of(8) {
+            row[pitch - 1] &= 0xFF << (8 - self.width % 8);
+        }
+        self.mark_dirty(Rect { x: 0, y, width: self.width, height: 1 });
+    }
+    /// Turn off the planes in `mask`
+    pub fn clear(&mut self, mask: u8) {
+        for (plane, bits) in self.planes.iter_mut().enumerate() {
+            if mask & 1 << plane != 0 {
+                bits.iter_mut().for_each(|byte| *byte = 0);
+            }
+        }
+        self.mark_all_dirty();
+    }
+    /// Move the planes in `mask` by (dx, dy), pixels scrolled out are lost
+    pub fn scroll(&mut self, dx: isize, dy: isize, mask: u8) {
+        let (width, height) = (self.width as isize, self.height as isize);
+
+        for plane in 0..PLANES {
+            if mask & 1 << plane == 0 {
+                continue;
+            }
+            let old = self.planes[plane].clone();
+            let mut bits = vec![0; old.len()];
+
+            for y in 0..height {
+                for x in 0..width {
+                    let (src_x, src_y) = (x - dx, y - dy);
+
+                    if src_x >= 0 && src_x < width && src_y >= 0 && src_y < height {
+                        let (src, src_bit) = self.bit(src_x as usize, src_y as usize);
+                        let (dst, dst_bit) = self.bit(x as usize, y as usize);
+
+                        if old[src] & src_bit != 0 {
+                            bits[dst] |= dst_bit;
+                        }
+                    }
+                }
+            }
+            self.planes[plane] = bits;
+        }
+        self.mark_all_dirty();
+    }
+    /// One byte per pixel holding its plane bitmask, row by row. `out`
+    /// must hold `width * height` bytes.
+    pub fn to_indexed(&self, out: &mut [u8]) {
+        for (idx, pixel) in out[..self.width * self.height].iter_mut().enumerate() {
+            *pixel = self.get(idx % self.width, idx / self.width);
+        }
+    }
+    /// RGBA colour of each pixel from its plane bitmask, row by row. `out`
+    /// must hold `width * height * 4` bytes.
+    pub fn to_rgba(&self, palette: &[[u8; 4]; 4], out: &mut [u8]) {
+        let pixels = out[..self.width * self.height * 4].chunks_exact_mut(4);
+
+        for (idx, pixel) in pixels.enumerate() {
+            pixel.copy_from_slice(&palette[self.get(idx % self.width, idx / self.width) as usize]);
+        }
+    }
+    /// Record a change to `rect`, the parts past the right and bottom edges
+    /// wrap around
+    pub fn mark_dirty(&mut self, rect: Rect) {
+        let x_end = rect.x + rect.width;
+        let y_end = rect.y + rect.height;
+        let columns = [(rect.x, x_end.min(self.width)), (0, x_end.saturating_sub(self.width).min(rect.x))];
+        let rows = [(rect.y, y_end.min(self.height)), (0, y_end.saturating_sub(self.height).min(rect.y))];
+
+        for (x, x_end) in columns.iter().filter(|(start, end)| start < end) {
+            for (y, y_end) in rows.iter().filter(|(start, end)| start < end) {
+                self.add_dirty(Rect { x: *x, y: *y, width: x_end - x, height: y_end - y });
+            }
+        }
+    }
+    fn mark_all_dirty(&mut self) {
+        self.dirty.clear();
+        self.dirty.push(Rect { x: 0, y: 0, width: self.width, height: self.height });
+    }
+    fn add_dirty(&mut self, rect: Rect) {
+        if self.dirty.iter().any(|dirty| dirty.contains(&rect)) {
+            return;
+        }
+        self.dirty.retain(|dirty| !rect.contains(dirty));
+        self.dirty.push(rect);
+        if self.dirty.len() > MAX_DIRTY {
+            let all = self.dirty.iter().fold(rect, |all, dirty| all.union(dirty));
+
+            self.dirty.clear();
+            self.dirty.push(all);
+        }
+    }
+    /// Areas changed since the last `clear_dirty`, they may overlap
+    pub fn dirty_rects(&self) -> impl Iterator<Item = &Rect> {
+        self.dirty.iter()
+    }
+    pub fn is_dirty(&self) -> bool {
+        !self.dirty.is_empty()
+    }
+    pub fn clear_dirty(&mut self) {
+        self.dirty.clear();
+    }
+}
+
+#[cfg(test)]
+mod test_framebuffer {
+    use super::*;
+
+    #[test]
+    fn test_pixels() {
+        let mut fb = Framebuffer::new(12, 2);
+        assert_eq!(fb.dirty_rects().collect::<Vec<_>>(), vec![&Rect { x: 0, y: 0, width: 12, height: 2 }]);
+        fb.clear_dirty();
+        assert!(!fb.is_dirty());
+
+        fb.set(1, 1, 0x3);
+        fb.set(9, 1, 0x2);
+        assert_eq!(fb.get(1, 1), 0x3);
+        assert_eq!(fb.row(0, 1), &[0x40, 0x00]);
+        assert_eq!(fb.row(1, 1), &[0x40, 0x40]);
+        assert!(fb.toggle(1, 1, 0x1));
+        assert!(!fb.toggle(2, 1, 0x1));
+        assert_eq!(fb.get(1, 1), 0x2);
+        assert_eq!(fb.dirty_rects().count(), 2);
+
+        // bits past the width are dropped
+        fb.set_row(0, 0, &[0xFF, 0xFF]);
+        assert_eq!(fb.row(0, 0), &[0xFF, 0xF0]);
+
+        let mut indexed = [0; 24];
+        fb.to_indexed(&mut indexed);
+        assert_eq!(indexed[12..], [0, 2, 1, 0, 0, 0, 0, 0, 0, 2, 0, 0]);
+
+        let palette = [[0; 4], [1; 4], [2; 4], [3; 4]];
+        let mut rgba = [9; 24 * 4];
+        fb.to_rgba(&palette, &mut rgba);
+        assert_eq!(rgba[..4], [1; 4]);
+        assert_eq!(rgba[13 * 4..14 * 4], [2; 4]);
+    }
+
+    #[test]
+    fn test_scroll() {
+        let mut fb = Framebuffer::new(8, 4);
+        fb.set(0, 0, 0x3);
+        fb.set(7, 3, 0x1);
+        fb.scroll(1, 2, 0x1);
+
+        assert_eq!(fb.get(0, 0), 0x2);
+        assert_eq!(fb.get(1, 2), 0x1);
+        assert_eq!(fb.row(0, 3), &[0]);
+        fb.clear(0x2);
+        assert_eq!(fb.get(0, 0), 0);
+        assert_eq!(fb.get(1, 2), 0x1);
+    }
+
+    #[test]
+    fn test_dirty() {
+        let mut fb = Framebuffer::new(64, 32);
+        fb.clear_dirty();
+
+        // sprites wrapping around the corner
+        fb.mark_dirty(Rect { x: 60, y: 30, width: 8, height: 4 });
+        assert_eq!(fb.dirty_rects().copied().collect::<Vec<_>>(), vec![
+            Rect { x: 60, y: 30, width: 4, height: 2 },
+            Rect { x: 60, y: 0, width: 4, height: 2 },
+            Rect { x: 0, y: 30, width: 4, height: 2 },
+            Rect { x: 0, y: 0, width: 4, height: 2 },
+        ]);
+
+        fb.clear_dirty();
+        fb.mark_dirty(Rect { x: 8, y: 8, width: 8, height: 5 });
+        fb.set(10, 10, 0x1);
+        fb.mark_dirty(Rect { x: 0, y: 0, width: 32, height: 16 });
+        assert_eq!(fb.dirty_rects().copied().collect::<Vec<_>>(), vec![Rect { x: 0, y: 0, width: 32, height: 16 }]);
+
+        // too many rectangles become their bounding box
+        fb.clear_dirty();
+        for x in 0..=MAX_DIRTY {
+            fb.set(x * 2, 1, 0x1);
+        }
+        assert_eq!(fb.dirty_rects().copied().collect::<Vec<_>>(), vec![
+            Rect { x: 0, y: 1, width: MAX_DIRTY * 2 + 1, height: 1 },
+        ]);
+        assert_eq!(Rect { x: 1, y: 2, width: 2, height: 2 }.points().collect::<Vec<_>>(),
+            vec![(1, 2), (2, 2), (1, 3), (2, 3)]);
+    }
+}
@@ -0,0 +1,76 @@
+use crate::instruction::Address;
+use crate::Emulator;
+use std::ops::Range;
+
+/// Copy of the registers at one point of the execution
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CpuState {
+    pub pc: Address,
+    pub i: Address,
+    pub regs: [u8; 16],
+    pub delay_timer: u8,
+    pub sound_timer: u8,
+    /// number of active calls
+    pub sp: u8,
+    /// return addresses, only the first `sp` are in use
+    pub stack: [Address; 16],
+}
+
+impl Emulator {
+    pub fn cpu_state(&self) -> CpuState {
+        CpuState {
+            pc: self.pc_reg,
+            i: self.i_reg,
+            regs: self.regs,
+            delay_timer: self.dt_reg,
+            sound_timer: self.st_reg,
+            sp: self.sp_reg,
+            stack: self.stack,
+        }
+    }
+    /// Memory in `range`, cut at the end of memory
+    pub fn memory_range(&self, range: Range<usize>) -> &[u8] {
+        let end = range.end.min(self.memory.len());
+
+        &self.memory[range.start.min(end)..end]
+    }
+    /// Return addresses of the active calls, innermost last
+    pub fn stack_frames(&self) -> &[Address] {
+        &self.stack[..self.sp_reg as usize]
+    }
+}
+
+#[cfg(test)]
+mod test_inspect {
+    use super::*;
+    use crate::instruction::Instruction;
+    use crate::quirks::Quirks;
+    use crate::DisplaySize;
+
+    #[test]
+    fn test_inspect() {
+        let mut emu = Emulator::new(DisplaySize::Basic64x32, Quirks::default(), 0);
+        emu.load_rom(&[
+            0x60, 0x2A,     // LD V0, 0x2A
+            0xA3, 0x00,     // LD I, 0x300
+            0x22, 0x08,     // CALL 0x208
+            0x00, 0x00,
+            0xF0, 0x15,     // LD DT, V0
+        ]);
+        for _ in 0..3 {
+            emu.cpu_one_cycle().unwrap();
+        }
+        let state = emu.cpu_state();
+        assert_eq!((state.pc, state.i, state.regs[0], state.sp), (0x208, 0x300, 0x2A, 1));
+        assert_eq!(state.delay_timer, 0);
+        assert_eq!(emu.stack_frames(), &[0x206]);
+        assert_eq!(emu.next_instruction(), Instruction::SetDelayTimer(0));
+
+        emu.cpu_one_cycle().unwrap();
+        assert_eq!(emu.cpu_state().delay_timer, 0x2A);
+
+        assert_eq!(emu.memory_range(0x200..0x204), &[0x60, 0x2A, 0xA3, 0x00]);
+        assert_eq!(emu.memory_range(0xFFE..0x1002).len(), 2);
+        assert!(emu.memory_range(0x2000..0x2010).is_empty());
+    }
+}
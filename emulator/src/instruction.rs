@@ -1,8 +1,10 @@
+use std::fmt;
+
 pub type Register = usize;
 pub type Address = u16;
 pub type Value = u8;
 
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Instruction {
     Invalid,
     Sys(Address),
@@ -21,9 +23,9 @@ pub enum Instruction {
     Xor(Register, Register),
     Add(Register, Register),
     Sub(Register, Register),
-    ShiftRight(Register),
+    ShiftRight(Register, Register),
     SubN(Register, Register),
-    ShiftLeft(Register),
+    ShiftLeft(Register, Register),
     SkipNotEq(Register, Register),
     LoadAddr(Address),
     JumpRel(Address),
@@ -40,6 +42,24 @@ pub enum Instruction {
     Bcd(Register),
     StoreRegs(Register),
     LoadRegs(Register),
+    /* SUPER-CHIP 1.1 */
+    ScrollDown(Value),
+    ScrollRight,
+    ScrollLeft,
+    Exit,
+    LowRes,
+    HighRes,
+    LoadBigSprite(Register),
+    StoreFlags(Register),
+    LoadFlags(Register),
+    /* XO-CHIP */
+    ScrollUp(Value),
+    SaveRange(Register, Register),
+    LoadRange(Register, Register),
+    LoadLongAddr,
+    SelectPlanes(Value),
+    LoadAudio,
+    SetPitch(Register),
 }
 
 fn instr_ptr(pfx: u8, ptr: Address) -> [u8; 2] {
@@ -85,6 +105,10 @@ macro_rules! nibble {
 }
 
 impl Instruction {
+    /// Opcodes of `instrs`, one after the other
+    pub fn assemble(instrs: &[Instruction]) -> Vec<u8> {
+        instrs.iter().flat_map(|instr| instr.asm()).collect()
+    }
     pub fn asm(&self) -> [u8; 2] {
         match self {
             Instruction::Invalid => [ 0x00, 0x00 ],
@@ -104,9 +128,9 @@ impl Instruction {
             Instruction::Xor(reg1, reg2) => instr_reg(0x8, *reg1, *reg2, 0x3),      // Tested
             Instruction::Add(reg1, reg2) => instr_reg(0x8, *reg1, *reg2, 0x4),      // Tested
             Instruction::Sub(reg1, reg2) => instr_reg(0x8, *reg1, *reg2, 0x5),      // Tested
-            Instruction::ShiftRight(reg) => instr_reg(0x8, *reg, 0, 0x6),           // Tested
+            Instruction::ShiftRight(reg1, reg2) => instr_reg(0x8, *reg1, *reg2, 0x6), // Tested
             Instruction::SubN(reg1, reg2) => instr_reg(0x8, *reg1, *reg2, 0x7),     // Tested
-            Instruction::ShiftLeft(reg) => instr_reg(0x8, *reg, 0, 0xE),            // Tested
+            Instruction::ShiftLeft(reg1, reg2) => instr_reg(0x8, *reg1, *reg2, 0xE),  // Tested
             Instruction::SkipNotEq(reg1, reg2) => instr_reg(0x9, *reg1, *reg2, 0),  // Tested
             Instruction::LoadAddr(addr) => instr_ptr(0xA, *addr),                   // Tested
             Instruction::JumpRel(addr) => instr_ptr(0xB, *addr),
@@ -123,19 +147,45 @@ impl Instruction {
             Instruction::Bcd(reg) => instr_val(0xF, *reg, 0x33),                    // Tested
             Instruction::StoreRegs(reg) => instr_val(0xF, *reg, 0x55),              // Tested
             Instruction::LoadRegs(reg) => instr_val(0xF, *reg, 0x65),               // Tested
+            Instruction::ScrollDown(n) => instr_reg(0x0, 0x0, 0xC, *n),             // Tested
+            Instruction::ScrollRight => [ 0x00, 0xFB ],                             // Tested
+            Instruction::ScrollLeft => [ 0x00, 0xFC ],                              // Tested
+            Instruction::Exit => [ 0x00, 0xFD ],                                    // Tested
+            Instruction::LowRes => [ 0x00, 0xFE ],                                  // Tested
+            Instruction::HighRes => [ 0x00, 0xFF ],                                 // Tested
+            Instruction::LoadBigSprite(reg) => instr_val(0xF, *reg, 0x30),          // Tested
+            Instruction::StoreFlags(reg) => instr_val(0xF, *reg, 0x75),             // Tested
+            Instruction::LoadFlags(reg) => instr_val(0xF, *reg, 0x85),              // Tested
+            Instruction::ScrollUp(n) => instr_reg(0x0, 0x0, 0xD, *n),               // Tested
+            Instruction::SaveRange(x, y) => instr_reg(0x5, *x, *y, 0x2),            // Tested
+            Instruction::LoadRange(x, y) => instr_reg(0x5, *x, *y, 0x3),            // Tested
+            Instruction::LoadLongAddr => [ 0xF0, 0x00 ],                            // Tested
+            Instruction::SelectPlanes(n) => instr_val(0xF, *n as Register, 0x01),   // Tested
+            Instruction::LoadAudio => [ 0xF0, 0x02 ],                               // Tested
+            Instruction::SetPitch(reg) => instr_val(0xF, *reg, 0x3A),               // Tested
         }
     }
 
+    /// Decode an opcode, unknown opcodes decode to `Instruction::Invalid`
     pub fn from(instr: u16) -> Instruction {
         match instr {
             0x00E0 => Instruction::Cls,
             0x00EE => Instruction::Ret,
+            0x00C0..=0x00CF => Instruction::ScrollDown(nibble!(instr)),
+            0x00D0..=0x00DF => Instruction::ScrollUp(nibble!(instr)),
+            0x00FB => Instruction::ScrollRight,
+            0x00FC => Instruction::ScrollLeft,
+            0x00FD => Instruction::Exit,
+            0x00FE => Instruction::LowRes,
+            0x00FF => Instruction::HighRes,
             0x0000..=0x0FFF if addr!(instr) != 0x0E0 && addr!(instr) != 0x0EE => Instruction::Sys(addr!(instr)),
             0x1000..=0x1FFF => Instruction::Jump(addr!(instr)),
             0x2000..=0x2FFF => Instruction::Call(addr!(instr)),
             0x3000..=0x3FFF => Instruction::SkipValEq(reg1!(instr), val!(instr)),
             0x4000..=0x4FFF => Instruction::SkipValNotEq(reg1!(instr), val!(instr)),
             0x5000..=0x5FFF if instr & 0x000F == 0 => Instruction::SkipEq(reg1!(instr), reg2!(instr)),
+            0x5000..=0x5FFF if instr & 0x000F == 2 => Instruction::SaveRange(reg1!(instr), reg2!(instr)),
+            0x5000..=0x5FFF if instr & 0x000F == 3 => Instruction::LoadRange(reg1!(instr), reg2!(instr)),
             0x6000..=0x6FFF => Instruction::LoadVal(reg1!(instr), val!(instr)),
             0x7000..=0x7FFF => Instruction::AddVal(reg1!(instr), val!(instr)),
             0x8000..=0x8FFF => match instr & 0x000F {
@@ -145,10 +195,10 @@ impl Instruction {
                 0x3 => Instruction::Xor(reg1!(instr), reg2!(instr)),
                 0x4 => Instruction::Add(reg1!(instr), reg2!(instr)),
                 0x5 => Instruction::Sub(reg1!(instr), reg2!(instr)),
-                0x6 => Instruction::ShiftRight(reg1!(instr)),
+                0x6 => Instruction::ShiftRight(reg1!(instr), reg2!(instr)),
                 0x7 => Instruction::SubN(reg1!(instr), reg2!(instr)),
-                0xE => Instruction::ShiftLeft(reg1!(instr)),
-                _ => panic!("Invalid 0x8... instruction"),
+                0xE => Instruction::ShiftLeft(reg1!(instr), reg2!(instr)),
+                _ => Instruction::Invalid,
             },
             0x9000..=0x9FFF if instr & 0x000F == 0 => {
                 Instruction::SkipNotEq(reg1!(instr), reg2!(instr))
@@ -159,16 +209,83 @@ impl Instruction {
             0xD000..=0xDFFF => Instruction::Draw(reg1!(instr), reg2!(instr), nibble!(instr)),
             0xE000..=0xEFFF if instr & 0xFF == 0x9E => Instruction::SkipKeyPressed(reg1!(instr)),
             0xE000..=0xEFFF if instr & 0xFF == 0xA1 => Instruction::SkipKeyNotPressed(reg1!(instr)),
+            0xF000 => Instruction::LoadLongAddr,
+            0xF002 => Instruction::LoadAudio,
+            0xF000..=0xFFFF if instr & 0xFF == 0x01 => Instruction::SelectPlanes(reg1!(instr) as Value),
             0xF000..=0xFFFF if instr & 0xFF == 0x07 => Instruction::LoadDelayTimer(reg1!(instr)),
             0xF000..=0xFFFF if instr & 0xFF == 0x0A => Instruction::LoadKey(reg1!(instr)),
             0xF000..=0xFFFF if instr & 0xFF == 0x15 => Instruction::SetDelayTimer(reg1!(instr)),
             0xF000..=0xFFFF if instr & 0xFF == 0x18 => Instruction::SetSoundTimer(reg1!(instr)),
             0xF000..=0xFFFF if instr & 0xFF == 0x1E => Instruction::AddI(reg1!(instr)),
             0xF000..=0xFFFF if instr & 0xFF == 0x29 => Instruction::LoadSprite(reg1!(instr)),
+            0xF000..=0xFFFF if instr & 0xFF == 0x30 => Instruction::LoadBigSprite(reg1!(instr)),
             0xF000..=0xFFFF if instr & 0xFF == 0x33 => Instruction::Bcd(reg1!(instr)),
+            0xF000..=0xFFFF if instr & 0xFF == 0x3A => Instruction::SetPitch(reg1!(instr)),
             0xF000..=0xFFFF if instr & 0xFF == 0x55 => Instruction::StoreRegs(reg1!(instr)),
             0xF000..=0xFFFF if instr & 0xFF == 0x65 => Instruction::LoadRegs(reg1!(instr)),
-            _ => panic!("Invalid instruction {:x}", instr)
+            0xF000..=0xFFFF if instr & 0xFF == 0x75 => Instruction::StoreFlags(reg1!(instr)),
+            0xF000..=0xFFFF if instr & 0xFF == 0x85 => Instruction::LoadFlags(reg1!(instr)),
+            _ => Instruction::Invalid,
+        }
+    }
+}
+
+/// Cowgod's mnemonics, with the SUPER-CHIP and XO-CHIP extensions
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Instruction::Invalid => write!(f, "INVALID"),
+            Instruction::Sys(addr) => write!(f, "SYS 0x{:03X}", addr),
+            Instruction::Cls => write!(f, "CLS"),
+            Instruction::Ret => write!(f, "RET"),
+            Instruction::Jump(addr) => write!(f, "JP 0x{:03X}", addr),
+            Instruction::Call(addr) => write!(f, "CALL 0x{:03X}", addr),
+            Instruction::SkipValEq(reg, val) => write!(f, "SE V{:X}, 0x{:02X}", reg, val),
+            Instruction::SkipValNotEq(reg, val) => write!(f, "SNE V{:X}, 0x{:02X}", reg, val),
+            Instruction::SkipEq(reg1, reg2) => write!(f, "SE V{:X}, V{:X}", reg1, reg2),
+            Instruction::LoadVal(reg, val) => write!(f, "LD V{:X}, 0x{:02X}", reg, val),
+            Instruction::AddVal(reg, val) => write!(f, "ADD V{:X}, 0x{:02X}", reg, val),
+            Instruction::Load(reg1, reg2) => write!(f, "LD V{:X}, V{:X}", reg1, reg2),
+            Instruction::Or(reg1, reg2) => write!(f, "OR V{:X}, V{:X}", reg1, reg2),
+            Instruction::And(reg1, reg2) => write!(f, "AND V{:X}, V{:X}", reg1, reg2),
+            Instruction::Xor(reg1, reg2) => write!(f, "XOR V{:X}, V{:X}", reg1, reg2),
+            Instruction::Add(reg1, reg2) => write!(f, "ADD V{:X}, V{:X}", reg1, reg2),
+            Instruction::Sub(reg1, reg2) => write!(f, "SUB V{:X}, V{:X}", reg1, reg2),
+            Instruction::ShiftRight(reg1, reg2) => write!(f, "SHR V{:X}, V{:X}", reg1, reg2),
+            Instruction::SubN(reg1, reg2) => write!(f, "SUBN V{:X}, V{:X}", reg1, reg2),
+            Instruction::ShiftLeft(reg1, reg2) => write!(f, "SHL V{:X}, V{:X}", reg1, reg2),
+            Instruction::SkipNotEq(reg1, reg2) => write!(f, "SNE V{:X}, V{:X}", reg1, reg2),
+            Instruction::LoadAddr(addr) => write!(f, "LD I, 0x{:03X}", addr),
+            Instruction::JumpRel(addr) => write!(f, "JP V0, 0x{:03X}", addr),
+            Instruction::Random(reg, val) => write!(f, "RND V{:X}, 0x{:02X}", reg, val),
+            Instruction::Draw(x, y, n) => write!(f, "DRW V{:X}, V{:X}, {}", x, y, n),
+            Instruction::SkipKeyPressed(reg) => write!(f, "SKP V{:X}", reg),
+            Instruction::SkipKeyNotPressed(reg) => write!(f, "SKNP V{:X}", reg),
+            Instruction::LoadDelayTimer(reg) => write!(f, "LD V{:X}, DT", reg),
+            Instruction::LoadKey(reg) => write!(f, "LD V{:X}, K", reg),
+            Instruction::SetDelayTimer(reg) => write!(f, "LD DT, V{:X}", reg),
+            Instruction::SetSoundTimer(reg) => write!(f, "LD ST, V{:X}", reg),
+            Instruction::AddI(reg) => write!(f, "ADD I, V{:X}", reg),
+            Instruction::LoadSprite(reg) => write!(f, "LD F, V{:X}", reg),
+            Instruction::Bcd(reg) => write!(f, "LD B, V{:X}", reg),
+            Instruction::StoreRegs(reg) => write!(f, "LD [I], V{:X}", reg),
+            Instruction::LoadRegs(reg) => write!(f, "LD V{:X}, [I]", reg),
+            Instruction::ScrollDown(n) => write!(f, "SCD {}", n),
+            Instruction::ScrollRight => write!(f, "SCR"),
+            Instruction::ScrollLeft => write!(f, "SCL"),
+            Instruction::Exit => write!(f, "EXIT"),
+            Instruction::LowRes => write!(f, "LOW"),
+            Instruction::HighRes => write!(f, "HIGH"),
+            Instruction::LoadBigSprite(reg) => write!(f, "LD HF, V{:X}", reg),
+            Instruction::StoreFlags(reg) => write!(f, "LD R, V{:X}", reg),
+            Instruction::LoadFlags(reg) => write!(f, "LD V{:X}, R", reg),
+            Instruction::ScrollUp(n) => write!(f, "SCU {}", n),
+            Instruction::SaveRange(x, y) => write!(f, "SAVE V{:X}-V{:X}", x, y),
+            Instruction::LoadRange(x, y) => write!(f, "LOAD V{:X}-V{:X}", x, y),
+            Instruction::LoadLongAddr => write!(f, "LD I, LONG"),
+            Instruction::SelectPlanes(n) => write!(f, "PLANE {}", n),
+            Instruction::LoadAudio => write!(f, "AUDIO"),
+            Instruction::SetPitch(reg) => write!(f, "PITCH V{:X}", reg),
         }
     }
 }
@@ -197,9 +314,9 @@ mod test_instruction {
             (Instruction::Xor(1, 2), [0x81, 0x23]),
             (Instruction::Add(1, 2), [0x81, 0x24]),
             (Instruction::Sub(1, 2), [0x81, 0x25]),
-            (Instruction::ShiftRight(1), [0x81, 0x06]),
+            (Instruction::ShiftRight(1, 2), [0x81, 0x26]),
             (Instruction::SubN(1, 2), [0x81, 0x27]),
-            (Instruction::ShiftLeft(1), [0x81, 0x0E]),
+            (Instruction::ShiftLeft(1, 2), [0x81, 0x2E]),
             (Instruction::SkipNotEq(0xA, 0xB), [0x9A, 0xB0]),
             (Instruction::LoadAddr(0xBCD), [0xAB, 0xCD]),
             (Instruction::JumpRel(0xCDE), [0xBC, 0xDE]),
@@ -216,11 +333,58 @@ mod test_instruction {
             (Instruction::Bcd(1), [0xF1, 0x33]),
             (Instruction::StoreRegs(1), [0xF1, 0x55]),
             (Instruction::LoadRegs(1), [0xF1, 0x65]),
+            (Instruction::ScrollDown(4), [0x00, 0xC4]),
+            (Instruction::ScrollRight, [0x00, 0xFB]),
+            (Instruction::ScrollLeft, [0x00, 0xFC]),
+            (Instruction::Exit, [0x00, 0xFD]),
+            (Instruction::LowRes, [0x00, 0xFE]),
+            (Instruction::HighRes, [0x00, 0xFF]),
+            (Instruction::Draw(1, 2, 0), [0xD1, 0x20]),
+            (Instruction::LoadBigSprite(2), [0xF2, 0x30]),
+            (Instruction::StoreFlags(7), [0xF7, 0x75]),
+            (Instruction::LoadFlags(7), [0xF7, 0x85]),
+            (Instruction::ScrollUp(4), [0x00, 0xD4]),
+            (Instruction::SaveRange(1, 4), [0x51, 0x42]),
+            (Instruction::LoadRange(4, 1), [0x54, 0x13]),
+            (Instruction::LoadLongAddr, [0xF0, 0x00]),
+            (Instruction::SelectPlanes(3), [0xF3, 0x01]),
+            (Instruction::LoadAudio, [0xF0, 0x02]),
+            (Instruction::SetPitch(5), [0xF5, 0x3A]),
         ];
 
         for (instr, ops) in tests {
             assert_eq!(instr.asm(), ops);
             assert_eq!(Instruction::from(((ops[0] as u16) << 8) | (ops[1] as u16)), instr);
         }
+        assert_eq!(Instruction::assemble(&[Instruction::Cls, Instruction::Jump(0x200)]), [0x00, 0xE0, 0x12, 0x00]);
+    }
+
+    #[test]
+    fn test_invalid_instructions() {
+        for instr in &[0x5001, 0x800F, 0x8008, 0x9001, 0xE000, 0xF0FF] {
+            assert_eq!(Instruction::from(*instr), Instruction::Invalid);
+        }
+    }
+
+    #[test]
+    fn test_display() {
+        let tests = vec![
+            (Instruction::LoadVal(3, 12), "LD V3, 0x0C"),
+            (Instruction::Jump(0x208), "JP 0x208"),
+            (Instruction::JumpRel(0x300), "JP V0, 0x300"),
+            (Instruction::ShiftLeft(0xA, 0xB), "SHL VA, VB"),
+            (Instruction::Draw(1, 2, 5), "DRW V1, V2, 5"),
+            (Instruction::StoreRegs(0xF), "LD [I], VF"),
+            (Instruction::LoadRegs(2), "LD V2, [I]"),
+            (Instruction::Bcd(4), "LD B, V4"),
+            (Instruction::LoadBigSprite(1), "LD HF, V1"),
+            (Instruction::ScrollDown(4), "SCD 4"),
+            (Instruction::SaveRange(1, 4), "SAVE V1-V4"),
+            (Instruction::Invalid, "INVALID"),
+        ];
+
+        for (instr, text) in tests {
+            assert_eq!(instr.to_string(), text);
+        }
     }
 }
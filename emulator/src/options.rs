@@ -0,0 +1,90 @@
+use crate::analyzer::Analysis;
+use crate::builder::EmulatorBuilder;
+use crate::error::OptionError;
+use crate::preset::Preset;
+use crate::profile::Profile;
+use crate::romdb::{RomDatabase, RomInfo};
+use crate::{Emulator, Mode};
+use std::fs;
+use std::path::Path;
+
+/// programs.json and sha1-hashes.json in `dir`
+fn read_database(dir: &Path) -> Result<RomDatabase, OptionError> {
+    let read = |file: &str| fs::read_to_string(dir.join(file))
+        .map_err(|reason| OptionError::Database(format!("failed to read {}: {}", file, reason)));
+
+    RomDatabase::from_json(&read("programs.json")?, &read("sha1-hashes.json")?)
+        .map_err(|reason| OptionError::Database(reason.to_string()))
+}
+
+/// Builder for `rom` as the frontends' options given after it say:
+/// `--preset NAME|auto`, `--profile chip8|hires|eti660` and `--database DIR`
+/// holding programs.json and sha1-hashes.json. What the database knows
+/// about the ROM is applied first, the options override it.
+pub fn configure(options: &[String], rom: &[u8]) -> Result<(EmulatorBuilder, Option<RomInfo>), OptionError> {
+    let mut preset = None;
+    let mut profile = None;
+    let mut database = None;
+
+    for option in options.chunks(2) {
+        match option {
+            [name, value] if name == "--preset" => match Preset::from_name(value) {
+                Some(val) => preset = Some(val),
+                None if value == "auto" => preset = Analysis::new(rom).preset(),
+                None => return Err(OptionError::UnknownPreset(value.clone())),
+            },
+            [name, value] if name == "--profile" => match Profile::from_name(value) {
+                Some(val) => profile = Some(val),
+                None => return Err(OptionError::UnknownProfile(value.clone())),
+            },
+            [name, value] if name == "--database" => database = Some(read_database(Path::new(value))?),
+            _ => return Err(OptionError::UnknownOption(option[0].clone())),
+        }
+    }
+
+    let mut builder = Emulator::builder().mode(Mode::detect(rom));
+    let info = database.and_then(|database| database.lookup(rom));
+    if let Some(info) = &info {
+        builder = builder.rom_info(info);
+    }
+    if let Some(preset) = preset {
+        builder = builder.preset(preset);
+    }
+    if let Some(profile) = profile {
+        builder = builder.profile(profile);
+    }
+    Ok((builder.rom(rom), info))
+}
+
+#[cfg(test)]
+mod test_options {
+    use super::*;
+    use crate::instruction::Instruction;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn test_configure() {
+        let rom = Instruction::assemble(&[Instruction::ScrollUp(1)]);
+
+        let (builder, info) = configure(&[], &rom).unwrap();
+        let emu = builder.build().unwrap();
+        assert_eq!((emu.mode(), emu.profile()), (Mode::Chip8, Profile::chip8()));
+        assert_eq!(info, None);
+
+        let options = args(&["--preset", "auto", "--profile", "eti660"]);
+        let emu = configure(&options, &rom).unwrap().0.build().unwrap();
+        assert_eq!(emu.mode(), Mode::XoChip);
+        assert_eq!(emu.profile(), Profile::eti660());
+        assert_eq!(emu.pc_reg, 0x600);
+
+        let error = |options: &[&str]| configure(&args(options), &rom).err();
+        assert_eq!(error(&["--preset", "chip-8"]), Some(OptionError::UnknownPreset("chip-8".to_string())));
+        assert_eq!(error(&["--profile", "vip"]), Some(OptionError::UnknownProfile("vip".to_string())));
+        assert_eq!(error(&["--speed", "10"]), Some(OptionError::UnknownOption("--speed".to_string())));
+        assert_eq!(error(&["--preset"]), Some(OptionError::UnknownOption("--preset".to_string())));
+        assert!(matches!(error(&["--database", "/nonexistent"]), Some(OptionError::Database(_))));
+    }
+}
@@ -0,0 +1,158 @@
+use crate::profile::{Font, Profile};
+use crate::quirks::Quirks;
+use crate::{Emulator, Mode};
+
+/// Machines CHIP-8 programs were written for, each one fixing everything
+/// that differs between them
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Preset {
+    VipChip8,
+    Chip48,
+    Schip10,
+    Schip11,
+    /// SUPER-CHIP as implemented by Octo and most modern interpreters
+    ModernSchip,
+    XoChip,
+    Eti660,
+}
+
+impl Preset {
+    pub const ALL: [Preset; 7] = [
+        Preset::VipChip8,
+        Preset::Chip48,
+        Preset::Schip10,
+        Preset::Schip11,
+        Preset::ModernSchip,
+        Preset::XoChip,
+        Preset::Eti660,
+    ];
+
+    /// Name used on command lines
+    pub fn name(self) -> &'static str {
+        match self {
+            Preset::VipChip8 => "vip",
+            Preset::Chip48 => "chip48",
+            Preset::Schip10 => "schip10",
+            Preset::Schip11 => "schip11",
+            Preset::ModernSchip => "schip",
+            Preset::XoChip => "xochip",
+            Preset::Eti660 => "eti660",
+        }
+    }
+    pub fn from_name(name: &str) -> Option<Self> {
+        Preset::ALL.iter().copied().find(|preset| preset.name() == name)
+    }
+    pub fn mode(self) -> Mode {
+        match self {
+            Preset::XoChip => Mode::XoChip,
+            _ => Mode::Chip8,
+        }
+    }
+    pub fn profile(self) -> Profile {
+        let font = match self {
+            Preset::VipChip8 | Preset::Chip48 | Preset::Eti660 => Font::Chip8,
+            Preset::Schip10 | Preset::Schip11 => Font::Schip,
+            Preset::ModernSchip | Preset::XoChip => Font::Octo,
+        };
+        match self {
+            Preset::Eti660 => Profile { font, ..Profile::eti660() },
+            _ => Profile { font, ..Profile::chip8() },
+        }
+    }
+    pub fn quirks(self) -> Quirks {
+        let chip48 = Quirks {
+            load_store_adds_x: true,
+            jump_uses_vx: true,
+            clip_sprites: true,
+            ..Quirks::default()
+        };
+        // Octo's SUPER-CHIP, without the wait for the display interrupt
+        let modern_schip = Quirks {
+            jump_uses_vx: true,
+            clip_sprites: true,
+            ..Quirks::default()
+        };
+        match self {
+            Preset::VipChip8 => Quirks::vip(),
+            Preset::Chip48 => chip48,
+            // low resolution sprites are drawn after the display interrupt
+            Preset::Schip10 => Quirks { display_wait: true, ..chip48 },
+            Preset::Schip11 => Quirks { display_wait: true, ..modern_schip },
+            Preset::ModernSchip => modern_schip,
+            Preset::XoChip => Quirks {
+                shift_uses_vy: true,
+                load_store_increments_i: true,
+                ..Quirks::default()
+            },
+            Preset::Eti660 => Quirks { machine_code: false, ..Quirks::vip() },
+        }
+    }
+    pub fn instructions_per_frame(self) -> usize {
+        match self {
+            Preset::VipChip8 | Preset::Eti660 => 15,
+            Preset::Chip48 | Preset::Schip10 | Preset::Schip11 | Preset::ModernSchip => 30,
+            Preset::XoChip => 1000,
+        }
+    }
+}
+
+impl Emulator {
+    /// Configure the machine as the preset says, the ROM should be loaded
+    /// afterwards
+    pub fn apply_preset(&mut self, preset: Preset) {
+        self.set_mode(preset.mode());
+        self.replace_profile(preset.profile());
+        self.quirks = preset.quirks();
+        self.instructions_per_frame = preset.instructions_per_frame();
+    }
+}
+
+#[cfg(test)]
+mod test_preset {
+    use super::*;
+    use crate::DisplaySize;
+
+    #[test]
+    fn test_names() {
+        for preset in Preset::ALL.iter() {
+            assert_eq!(Preset::from_name(preset.name()), Some(*preset));
+        }
+        assert_eq!(Preset::from_name("chip-8"), None);
+    }
+
+    #[test]
+    fn test_apply() {
+        let mut emu = Emulator::new(DisplaySize::Basic64x32, Quirks::default(), 0);
+
+        emu.apply_preset(Preset::XoChip);
+        assert_eq!(emu.mode(), Mode::XoChip);
+        assert_eq!(emu.memory.len(), 0x10000);
+        assert_eq!(emu.instructions_per_frame, 1000);
+        assert!(emu.quirks.shift_uses_vy);
+
+        emu.apply_preset(Preset::Eti660);
+        assert_eq!(emu.mode(), Mode::Chip8);
+        assert_eq!(emu.memory.len(), 0x1000);
+        assert_eq!(emu.resolution, (64, 48));
+        emu.load_rom(&[0x00, 0xE0]);
+        assert_eq!(emu.pc_reg, 0x600);
+
+        // only the SUPER-CHIP digits are there
+        emu.apply_preset(Preset::Schip11);
+        assert_eq!(emu.memory[80..80 + 100], crate::BIG_SPRITES[..100]);
+        assert_eq!(emu.memory[180..240], [0; 60]);
+        assert!(emu.quirks.jump_uses_vx);
+
+        // the SUPER-CHIP family only shares a font
+        let schip = [Preset::Chip48, Preset::Schip10, Preset::Schip11, Preset::ModernSchip];
+        for (idx, preset) in schip.iter().enumerate() {
+            for other in schip[idx + 1..].iter() {
+                assert_ne!(preset.quirks(), other.quirks(), "{:?} and {:?}", preset, other);
+            }
+        }
+        assert!(Preset::Schip10.quirks().load_store_adds_x);
+        assert!(!Preset::Schip11.quirks().load_store_adds_x);
+        assert!(Preset::Schip11.quirks().display_wait);
+        assert!(!Preset::ModernSchip.quirks().display_wait);
+    }
+}
@@ -0,0 +1,96 @@
+use crate::error::ConfigError;
+use crate::instruction::Address;
+use crate::{DisplaySize, FONT_SIZE};
+
+/// Built-in fonts, they all have the same small 4x5 digits
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Font {
+    /// small digits only
+    Chip8,
+    /// SUPER-CHIP 8x10 digits 0-9
+    Schip,
+    /// 8x10 digits 0-F as in Octo
+    Octo,
+}
+
+impl Font {
+    /// Number of big digits
+    pub fn big_glyphs(self) -> usize {
+        match self {
+            Font::Chip8 => 0,
+            Font::Schip => 10,
+            Font::Octo => 16,
+        }
+    }
+}
+
+/// Memory layout and screen of a machine running CHIP-8
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Profile {
+    /// where ROMs are loaded
+    pub load_address: Address,
+    /// where execution starts after loading or resetting
+    pub entry_point: Address,
+    pub display: DisplaySize,
+    /// small font, followed by the big font
+    pub font_address: Address,
+    pub font: Font,
+}
+
+impl Profile {
+    /// COSMAC VIP and most later interpreters
+    pub fn chip8() -> Self {
+        Profile {
+            load_address: 0x200,
+            entry_point: 0x200,
+            display: DisplaySize::Basic64x32,
+            font_address: 0x000,
+            font: Font::Octo,
+        }
+    }
+    /// Two-page hires CHIP-8, the interpreter patch takes 0x200-0x2BF
+    pub fn hires_chip8() -> Self {
+        Profile {
+            entry_point: 0x2C0,
+            display: DisplaySize::Eti64x64,
+            ..Profile::chip8()
+        }
+    }
+    /// ETI-660, its interpreter takes the first 0x600 bytes
+    pub fn eti660() -> Self {
+        Profile {
+            load_address: 0x600,
+            entry_point: 0x600,
+            display: DisplaySize::Eti64x48,
+            ..Profile::chip8()
+        }
+    }
+    /// Font, load address and entry point all fit in `memory_size` bytes
+    pub fn check(&self, memory_size: usize) -> Result<(), ConfigError> {
+        if self.font_address as usize + FONT_SIZE > memory_size {
+            return Err(ConfigError::FontAddress(self.font_address));
+        }
+        if self.load_address as usize >= memory_size {
+            return Err(ConfigError::LoadAddress(self.load_address));
+        }
+        if self.entry_point as usize + 2 > memory_size {
+            return Err(ConfigError::EntryPoint(self.entry_point));
+        }
+        Ok(())
+    }
+    /// Profile from its command line name
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "chip8" => Some(Profile::chip8()),
+            "hires" => Some(Profile::hires_chip8()),
+            "eti660" => Some(Profile::eti660()),
+            _ => None,
+        }
+    }
+}
+
+impl Default for Profile {
+    fn default() -> Self {
+        Profile::chip8()
+    }
+}
@@ -0,0 +1,40 @@
+/// Behaviour of the opcodes that CHIP-8 interpreters historically disagree on.
+///
+/// The default matches what this emulator has always done (CHIP-48 style shifts
+/// and loads, wrapping sprites).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Quirks {
+    /// 8XY6/8XYE shift VY into VX instead of shifting VX in place
+    pub shift_uses_vy: bool,
+    /// FX55/FX65 leave I pointing after the last register accessed
+    pub load_store_increments_i: bool,
+    /// FX55/FX65 leave I pointing at the last register accessed, as on
+    /// CHIP-48 and SUPER-CHIP 1.0, when load_store_increments_i is off
+    pub load_store_adds_x: bool,
+    /// BXNN jumps to XNN + VX instead of NNN + V0
+    pub jump_uses_vx: bool,
+    /// 8XY1/8XY2/8XY3 reset VF to 0
+    pub logic_resets_vf: bool,
+    /// sprites are clipped at the screen edges instead of wrapping around
+    pub clip_sprites: bool,
+    /// DXYN waits for the next 60Hz tick before drawing
+    pub display_wait: bool,
+    /// 0NNN runs 1802 machine code instead of being ignored
+    pub machine_code: bool,
+}
+
+impl Quirks {
+    /// Original COSMAC VIP interpreter behaviour.
+    pub fn vip() -> Self {
+        Quirks {
+            shift_uses_vy: true,
+            load_store_increments_i: true,
+            load_store_adds_x: false,
+            jump_uses_vx: false,
+            logic_resets_vf: true,
+            clip_sprites: true,
+            display_wait: true,
+            machine_code: true,
+        }
+    }
+}
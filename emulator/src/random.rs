@@ -0,0 +1,83 @@
+/// Source of the random numbers returned by CXNN.
+///
+/// The state is kept in save states, so restoring a state replays the same
+/// random numbers.
+pub trait RandomSource {
+    fn next_u8(&mut self) -> u8;
+    /// Serialized internal state
+    fn state(&self) -> Vec<u8>;
+    /// Restore a state returned by `state`, returns false and leaves the
+    /// source untouched if it is invalid
+    fn set_state(&mut self, state: &[u8]) -> bool;
+}
+
+/// xorshift64* generator, small and good enough for games
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct XorShift {
+    state: u64,
+}
+
+impl XorShift {
+    pub fn new(seed: u64) -> Self {
+        // splitmix64 spreads similar seeds apart and never gives 0
+        let mut z = seed.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^= z >> 31;
+
+        XorShift { state: if z == 0 { 1 } else { z } }
+    }
+}
+
+impl RandomSource for XorShift {
+    fn next_u8(&mut self) -> u8 {
+        self.state ^= self.state >> 12;
+        self.state ^= self.state << 25;
+        self.state ^= self.state >> 27;
+        (self.state.wrapping_mul(0x2545_F491_4F6C_DD1D) >> 56) as u8
+    }
+    fn state(&self) -> Vec<u8> {
+        self.state.to_le_bytes().to_vec()
+    }
+    fn set_state(&mut self, state: &[u8]) -> bool {
+        let mut bytes = [0; 8];
+
+        if state.len() != bytes.len() {
+            return false;
+        }
+        bytes.copy_from_slice(state);
+        match u64::from_le_bytes(bytes) {
+            0 => false,
+            val => {
+                self.state = val;
+                true
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod test_random {
+    use super::*;
+
+    #[test]
+    fn test_xorshift() {
+        let values = |seed| {
+            let mut rng = XorShift::new(seed);
+            (0..32).map(|_| rng.next_u8()).collect::<Vec<u8>>()
+        };
+
+        assert_eq!(values(1), values(1));
+        assert_ne!(values(1), values(2));
+
+        let mut rng = XorShift::new(0);
+        rng.next_u8();
+        let state = rng.state();
+        let next = rng.next_u8();
+        assert!(rng.set_state(&state));
+        assert_eq!(rng.next_u8(), next);
+
+        assert!(!rng.set_state(&[0; 8]));
+        assert!(!rng.set_state(&[1, 2, 3]));
+    }
+}
@@ -0,0 +1,247 @@
+use crate::Emulator;
+use std::collections::VecDeque;
+
+/// Ring buffer of save states used to step back in time.
+///
+/// Only the newest snapshot is kept whole, every older one is stored as a
+/// run-length encoded XOR against the snapshot that follows it. Dropping the
+/// oldest snapshot is then just dropping the first delta.
+pub struct Rewind {
+    /* snapshot every `interval` recorded frames */
+    interval: usize,
+    capacity: usize,
+    frames: usize,
+    newest: Option<Vec<u8>>,
+    deltas: VecDeque<Vec<u8>>,
+}
+
+impl Rewind {
+    /// Keep up to `capacity` snapshots, taken every `interval` frames
+    pub fn new(interval: usize, capacity: usize) -> Self {
+        assert!(interval > 0);
+        assert!(capacity > 0);
+
+        Rewind {
+            interval,
+            capacity,
+            frames: 0,
+            newest: None,
+            deltas: VecDeque::new(),
+        }
+    }
+    /// Call once per frame, before running it
+    pub fn record(&mut self, emu: &Emulator) {
+        self.frames += 1;
+        if self.frames < self.interval {
+            return;
+        }
+        self.frames = 0;
+
+        let state = emu.save_state();
+        if let Some(previous) = self.newest.take() {
+            self.deltas.push_back(encode(&state, &previous));
+        }
+        self.newest = Some(state);
+        while self.len() > self.capacity {
+            self.deltas.pop_front();
+        }
+    }
+    /// Restore the newest snapshot and forget it, going `interval` frames
+    /// back. Returns false when there is nothing left to rewind to.
+    pub fn step_back(&mut self, emu: &mut Emulator) -> bool {
+        let state = match self.newest.take() {
+            Some(state) => state,
+            None => return false,
+        };
+        if emu.load_state(&state).is_err() {
+            // the emulator changed mode, snapshots are useless now
+            self.clear();
+            return false;
+        }
+        self.newest = self.deltas.pop_back().map(|delta| decode(&state, &delta));
+        self.frames = 0;
+        true
+    }
+    pub fn len(&self) -> usize {
+        self.newest.as_ref().map_or(0, |_| self.deltas.len() + 1)
+    }
+    pub fn is_empty(&self) -> bool {
+        self.newest.is_none()
+    }
+    pub fn clear(&mut self) {
+        self.frames = 0;
+        self.newest = None;
+        self.deltas.clear();
+    }
+    /// Bytes used by the stored snapshots
+    pub fn memory_usage(&self) -> usize {
+        self.newest.as_ref().map_or(0, |state| state.len())
+            + self.deltas.iter().map(|delta| delta.len()).sum::<usize>()
+    }
+}
+
+fn push_varint(out: &mut Vec<u8>, mut val: usize) {
+    while val >= 0x80 {
+        out.push((val as u8 & 0x7F) | 0x80);
+        val >>= 7;
+    }
+    out.push(val as u8);
+}
+
+fn read_varint(data: &[u8], pos: &mut usize) -> usize {
+    let mut val = 0;
+    let mut shift = 0;
+
+    loop {
+        let byte = data[*pos];
+        *pos += 1;
+        val |= ((byte & 0x7F) as usize) << shift;
+        if byte & 0x80 == 0 {
+            return val;
+        }
+        shift += 7;
+    }
+}
+
+/// Encode `target` relative to `base` as the target length followed by
+/// (unchanged run, changed run, XORed bytes) chunks
+fn encode(base: &[u8], target: &[u8]) -> Vec<u8> {
+    let xor = |idx: usize| target[idx] ^ base.get(idx).copied().unwrap_or(0);
+    let mut out = Vec::new();
+    let mut idx = 0;
+
+    push_varint(&mut out, target.len());
+    while idx < target.len() {
+        let start = idx;
+        while idx < target.len() && xor(idx) == 0 {
+            idx += 1;
+        }
+        let skip = idx - start;
+        if idx == target.len() {
+            break;
+        }
+        let start = idx;
+        while idx < target.len() && xor(idx) != 0 {
+            idx += 1;
+        }
+        push_varint(&mut out, skip);
+        push_varint(&mut out, idx - start);
+        out.extend((start..idx).map(xor));
+    }
+    out
+}
+
+fn decode(base: &[u8], delta: &[u8]) -> Vec<u8> {
+    let mut pos = 0;
+    let len = read_varint(delta, &mut pos);
+    let mut out: Vec<u8> = (0..len).map(|idx| base.get(idx).copied().unwrap_or(0)).collect();
+    let mut idx = 0;
+
+    while pos < delta.len() {
+        idx += read_varint(delta, &mut pos);
+        let changed = read_varint(delta, &mut pos);
+        for byte in &delta[pos..pos + changed] {
+            out[idx] ^= byte;
+            idx += 1;
+        }
+        pos += changed;
+    }
+    out
+}
+
+#[cfg(test)]
+mod test_rewind {
+    use super::*;
+    use crate::DisplaySize;
+    use crate::instruction::Instruction;
+    use crate::quirks::Quirks;
+
+    const CYCLES_PER_FRAME: usize = 10;
+
+    /// Bounce a sprite around, counting frames in V5
+    fn bouncing_sprite() -> Emulator {
+        let mut emu = Emulator::new(DisplaySize::Basic64x32, Quirks::default(), 0);
+        emu.mem_load_instr(vec![
+            Instruction::LoadSprite(0),
+            Instruction::Draw(1, 2, 5),     // 0x202
+            Instruction::AddVal(1, 3),
+            Instruction::AddVal(2, 1),
+            Instruction::AddVal(5, 1),
+            Instruction::Cls,
+            Instruction::Jump(0x202),
+        ]);
+        emu
+    }
+
+    fn run_frame(emu: &mut Emulator) {
+        for _ in 0..CYCLES_PER_FRAME {
+            emu.cpu_one_cycle().unwrap();
+        }
+    }
+
+    #[test]
+    fn test_encode_decode() {
+        let base = vec![1, 2, 3, 4, 5, 6];
+        for target in &[vec![1, 2, 3, 4, 5, 6], vec![1, 0, 3, 4, 9, 9], vec![1, 2], vec![7; 10], vec![]] {
+            assert_eq!(&decode(&base, &encode(&base, target)), target);
+        }
+        assert_eq!(encode(&base, &base), vec![6]);
+    }
+
+    #[test]
+    fn test_rewind_then_replay() {
+        let mut emu = bouncing_sprite();
+        let mut rewind = Rewind::new(1, 120);
+        let mut screens = Vec::new();
+
+        for _ in 0..120 {
+            rewind.record(&emu);
+            screens.push(emu.screen.clone());
+            run_frame(&mut emu);
+        }
+        assert_eq!(rewind.len(), 120);
+
+        // rewind 60 frames
+        for _ in 0..60 {
+            assert!(rewind.step_back(&mut emu));
+        }
+        assert_eq!(emu.screen, screens[60]);
+        assert_eq!(emu.regs[5] as usize, 60 * CYCLES_PER_FRAME / 6);
+        assert_eq!(rewind.len(), 60);
+
+        // replaying gives the same frames again
+        for screen in &screens[60..] {
+            assert_eq!(&emu.screen, screen);
+            rewind.record(&emu);
+            run_frame(&mut emu);
+        }
+        assert_eq!(rewind.len(), 120);
+    }
+
+    #[test]
+    fn test_interval_and_capacity() {
+        let mut emu = bouncing_sprite();
+        let mut rewind = Rewind::new(4, 10);
+        let mut screens = Vec::new();
+
+        for _ in 0..100 {
+            rewind.record(&emu);
+            screens.push(emu.screen.clone());
+            run_frame(&mut emu);
+        }
+        assert_eq!(rewind.len(), 10);
+        // snapshots only hold deltas, except the newest
+        assert!(rewind.memory_usage() < 2 * emu.save_state().len());
+
+        assert!(rewind.step_back(&mut emu));
+        assert_eq!(emu.screen, screens[99]);
+        assert!(rewind.step_back(&mut emu));
+        assert_eq!(emu.screen, screens[95]);
+        for _ in 0..8 {
+            assert!(rewind.step_back(&mut emu));
+        }
+        assert_eq!(emu.screen, screens[63]);
+        assert!(!rewind.step_back(&mut emu));
+        assert!(rewind.is_empty());
+    }
+}
@@ -0,0 +1,211 @@
+use crate::builder::EmulatorBuilder;
+use crate::preset::Preset;
+use crate::Emulator;
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashMap};
+
+#[derive(Debug, Deserialize)]
+struct Program {
+    title: String,
+    description: Option<String>,
+    release: Option<String>,
+    #[serde(default)]
+    authors: Vec<String>,
+    #[serde(default)]
+    roms: HashMap<String, Rom>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Rom {
+    #[serde(default)]
+    platforms: Vec<String>,
+    tickrate: Option<usize>,
+    #[serde(default)]
+    keys: BTreeMap<String, u8>,
+    colors: Option<Colors>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Colors {
+    #[serde(default)]
+    pixels: Vec<String>,
+    buzzer: Option<String>,
+    silence: Option<String>,
+}
+
+/// What the database knows about a ROM
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RomInfo {
+    pub title: String,
+    pub authors: Vec<String>,
+    pub description: Option<String>,
+    pub release: Option<String>,
+    /// preset of the first supported platform the emulator has one for
+    pub preset: Option<Preset>,
+    pub instructions_per_frame: Option<usize>,
+    /// CHIP-8 key for each action, like "up" or "a"
+    pub keys: BTreeMap<String, u8>,
+    /// RGB colour of each plane combination, background first
+    pub colors: Vec<[u8; 3]>,
+    pub buzzer_color: Option<[u8; 3]>,
+    pub silence_color: Option<[u8; 3]>,
+}
+
+/// SHA-1 of a ROM as lowercase hex, the database key
+pub fn sha1(rom: &[u8]) -> String {
+    sha1_smol::Sha1::from(rom).digest().to_string()
+}
+
+fn platform_preset(platform: &str) -> Option<Preset> {
+    match platform {
+        "originalChip8" | "hybridVIP" => Some(Preset::VipChip8),
+        "chip48" => Some(Preset::Chip48),
+        "superchip1" => Some(Preset::Schip10),
+        "superchip" => Some(Preset::Schip11),
+        "xochip" => Some(Preset::XoChip),
+        _ => None,
+    }
+}
+
+/// `#RRGGBB` colour
+fn parse_color(color: &str) -> Option<[u8; 3]> {
+    let hex = color.strip_prefix('#')?;
+    if hex.len() != 6 {
+        return None;
+    }
+    let val = u32::from_str_radix(hex, 16).ok()?;
+    Some([(val >> 16) as u8, (val >> 8) as u8, val as u8])
+}
+
+/// Contents of the community chip-8-database (programs.json and
+/// sha1-hashes.json), none is shipped with the emulator
+pub struct RomDatabase {
+    programs: Vec<Program>,
+    /* ROM hash to index in programs */
+    hashes: HashMap<String, usize>,
+}
+
+impl RomDatabase {
+    /// Parse the contents of programs.json and sha1-hashes.json
+    pub fn from_json(programs: &str, hashes: &str) -> Result<Self, serde_json::Error> {
+        Ok(RomDatabase {
+            programs: serde_json::from_str(programs)?,
+            hashes: serde_json::from_str(hashes)?,
+        })
+    }
+    pub fn len(&self) -> usize {
+        self.hashes.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.hashes.is_empty()
+    }
+    pub fn lookup(&self, rom: &[u8]) -> Option<RomInfo> {
+        let hash = sha1(rom);
+        let program = self.programs.get(*self.hashes.get(&hash)?)?;
+        let entry = program.roms.get(&hash);
+        let colors = entry.and_then(|entry| entry.colors.as_ref());
+
+        Some(RomInfo {
+            title: program.title.clone(),
+            authors: program.authors.clone(),
+            description: program.description.clone(),
+            release: program.release.clone(),
+            preset: entry.and_then(|entry| entry.platforms.iter().find_map(|platform| platform_preset(platform))),
+            instructions_per_frame: entry.and_then(|entry| entry.tickrate),
+            keys: entry.map(|entry| entry.keys.clone()).unwrap_or_default(),
+            colors: colors.map_or_else(Vec::new, |colors| {
+                colors.pixels.iter().filter_map(|color| parse_color(color)).collect()
+            }),
+            buzzer_color: colors.and_then(|colors| colors.buzzer.as_deref()).and_then(parse_color),
+            silence_color: colors.and_then(|colors| colors.silence.as_deref()).and_then(parse_color),
+        })
+    }
+}
+
+impl Emulator {
+    /// Use the preset and speed the database gives, the ROM should be
+    /// loaded afterwards
+    pub fn apply_rom_info(&mut self, info: &RomInfo) {
+        if let Some(preset) = info.preset {
+            self.apply_preset(preset);
+        }
+        if let Some(instructions) = info.instructions_per_frame {
+            self.instructions_per_frame = instructions;
+        }
+    }
+}
+
+impl EmulatorBuilder {
+    /// Same as `Emulator::apply_rom_info`
+    pub fn rom_info(self, info: &RomInfo) -> Self {
+        let builder = match info.preset {
+            Some(preset) => self.preset(preset),
+            None => self,
+        };
+        match info.instructions_per_frame {
+            Some(instructions) => builder.instructions_per_frame(instructions),
+            None => builder,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test_romdb {
+    use super::*;
+    use crate::quirks::Quirks;
+    use crate::{DisplaySize, Mode};
+
+    const ROM: [u8; 4] = [0x00, 0xE0, 0x12, 0x02];
+
+    fn database() -> RomDatabase {
+        let programs = format!(r##"[
+            {{ "title": "Unrelated", "roms": {{}} }},
+            {{
+                "title": "Test",
+                "description": "Clears the screen",
+                "release": "2024",
+                "authors": ["Someone"],
+                "roms": {{
+                    "{}": {{
+                        "file": "test.ch8",
+                        "platforms": ["megachip8", "xochip"],
+                        "tickrate": 200,
+                        "keys": {{ "up": 5, "a": 6 }},
+                        "colors": {{ "pixels": ["#000000", "#FF8000"], "buzzer": "#ffffff" }}
+                    }}
+                }}
+            }}
+        ]"##, sha1(&ROM));
+        let hashes = format!(r#"{{ "{}": 1 }}"#, sha1(&ROM));
+
+        RomDatabase::from_json(&programs, &hashes).unwrap()
+    }
+
+    #[test]
+    fn test_sha1() {
+        assert_eq!(sha1(b"abc"), "a9993e364706816aba3e25717850c26c9cd0d89d");
+    }
+
+    #[test]
+    fn test_lookup() {
+        let db = database();
+        assert_eq!(db.lookup(&[0x00, 0xE0]), None);
+
+        let info = db.lookup(&ROM).unwrap();
+        assert_eq!(info.title, "Test");
+        assert_eq!(info.authors, vec!["Someone".to_string()]);
+        assert_eq!(info.description.as_deref(), Some("Clears the screen"));
+        assert_eq!(info.preset, Some(Preset::XoChip));
+        assert_eq!(info.keys.get("up"), Some(&5));
+        assert_eq!(info.colors, vec![[0, 0, 0], [0xFF, 0x80, 0]]);
+        assert_eq!(info.buzzer_color, Some([0xFF; 3]));
+        assert_eq!(info.silence_color, None);
+
+        let mut emu = Emulator::new(DisplaySize::Basic64x32, Quirks::default(), 0);
+        emu.apply_rom_info(&info);
+        assert_eq!(emu.mode(), Mode::XoChip);
+        assert_eq!(emu.instructions_per_frame, 200);
+
+        assert!(RomDatabase::from_json("[", "{}").is_err());
+    }
+}
@@ -0,0 +1,438 @@
+use crate::framebuffer::{Framebuffer, PLANES};
+use crate::profile::{Font, Profile};
+use crate::{DisplaySize, Emulator, Mode};
+use std::fmt;
+
+/* "CHIP-8 save state" */
+const MAGIC: &[u8; 4] = b"C8SS";
+/// Bumped whenever the layout of a save state changes
+pub const STATE_VERSION: u8 = 5;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StateError {
+    /// Not a save state at all
+    BadMagic,
+    /// Saved by an incompatible version of the emulator
+    UnsupportedVersion(u8),
+    /// Saved while running in another mode
+    ModeMismatch { expected: Mode, found: Mode },
+    /// Saved with another memory layout or font
+    ProfileMismatch { expected: Profile, found: Profile },
+    /// Data ends before the state is complete
+    Truncated,
+    /// A field holds a value that can't be restored
+    Corrupted,
+}
+
+impl fmt::Display for StateError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            StateError::BadMagic => write!(f, "not a save state"),
+            StateError::UnsupportedVersion(version) =>
+                write!(f, "unsupported save state version {} (expected {})", version, STATE_VERSION),
+            StateError::ModeMismatch { expected, found } =>
+                write!(f, "save state is for {:?} mode, emulator is in {:?} mode", found, expected),
+            StateError::ProfileMismatch { expected, found } =>
+                write!(f, "save state is for profile {:?}, emulator uses {:?}", found, expected),
+            StateError::Truncated => write!(f, "save state is truncated"),
+            StateError::Corrupted => write!(f, "save state is corrupted"),
+        }
+    }
+}
+
+impl std::error::Error for StateError {}
+
+fn mode_to_byte(mode: Mode) -> u8 {
+    match mode {
+        Mode::Chip8 => 0,
+        Mode::XoChip => 1,
+        Mode::HiresChip8 => 2,
+    }
+}
+
+fn mode_from_byte(byte: u8) -> Result<Mode, StateError> {
+    match byte {
+        0 => Ok(Mode::Chip8),
+        1 => Ok(Mode::XoChip),
+        2 => Ok(Mode::HiresChip8),
+        _ => Err(StateError::Corrupted),
+    }
+}
+
+fn display_to_byte(display: DisplaySize) -> u8 {
+    match display {
+        DisplaySize::Basic64x32 => 0,
+        DisplaySize::Eti64x48 => 1,
+        DisplaySize::Eti64x64 => 2,
+        DisplaySize::Hp128x64 => 3,
+    }
+}
+
+fn display_from_byte(byte: u8) -> Result<DisplaySize, StateError> {
+    match byte {
+        0 => Ok(DisplaySize::Basic64x32),
+        1 => Ok(DisplaySize::Eti64x48),
+        2 => Ok(DisplaySize::Eti64x64),
+        3 => Ok(DisplaySize::Hp128x64),
+        _ => Err(StateError::Corrupted),
+    }
+}
+
+fn font_to_byte(font: Font) -> u8 {
+    match font {
+        Font::Chip8 => 0,
+        Font::Schip => 1,
+        Font::Octo => 2,
+    }
+}
+
+fn font_from_byte(byte: u8) -> Result<Font, StateError> {
+    match byte {
+        0 => Ok(Font::Chip8),
+        1 => Ok(Font::Schip),
+        2 => Ok(Font::Octo),
+        _ => Err(StateError::Corrupted),
+    }
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8], StateError> {
+        if self.data.len() < len {
+            return Err(StateError::Truncated);
+        }
+        let (head, tail) = self.data.split_at(len);
+        self.data = tail;
+        Ok(head)
+    }
+    fn u8(&mut self) -> Result<u8, StateError> {
+        Ok(self.bytes(1)?[0])
+    }
+    fn u16(&mut self) -> Result<u16, StateError> {
+        let bytes = self.bytes(2)?;
+        Ok(u16::from_le_bytes([bytes[0], bytes[1]]))
+    }
+    fn u32(&mut self) -> Result<u32, StateError> {
+        let bytes = self.bytes(4)?;
+        Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+    fn bool(&mut self) -> Result<bool, StateError> {
+        match self.u8()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(StateError::Corrupted),
+        }
+    }
+}
+
+impl Emulator {
+    /// Serialize the whole machine state
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(self.memory.len() + 256);
+
+        out.extend_from_slice(MAGIC);
+        out.push(STATE_VERSION);
+        out.push(mode_to_byte(self.mode));
+        out.extend_from_slice(&self.profile.load_address.to_le_bytes());
+        out.extend_from_slice(&self.profile.entry_point.to_le_bytes());
+        out.push(display_to_byte(self.profile.display));
+        out.extend_from_slice(&self.profile.font_address.to_le_bytes());
+        out.push(font_to_byte(self.profile.font));
+
+        out.extend_from_slice(&(self.memory.len() as u32).to_le_bytes());
+        out.extend_from_slice(&self.memory);
+        out.extend_from_slice(&self.regs);
+        out.extend_from_slice(&self.i_reg.to_le_bytes());
+        out.extend_from_slice(&self.pc_reg.to_le_bytes());
+        out.push(self.sp_reg);
+        for addr in self.stack.iter() {
+            out.extend_from_slice(&addr.to_le_bytes());
+        }
+        out.push(self.dt_reg);
+        out.push(self.st_reg);
+        out.push(self.vblank as u8);
+        out.extend_from_slice(&self.vip_cycles.to_le_bytes());
+        out.extend_from_slice(&self.rpl);
+        out.extend_from_slice(&self.audio_pattern);
+        out.push(self.pitch);
+
+        out.extend_from_slice(&(self.resolution.0 as u16).to_le_bytes());
+        out.extend_from_slice(&(self.resolution.1 as u16).to_le_bytes());
+        for plane in 0..PLANES {
+            for y in 0..self.resolution.1 {
+                out.extend_from_slice(self.screen.row(plane, y));
+            }
+        }
+        out.push(self.planes);
+        out.push(self.exited as u8);
+        for key in self.keys.iter() {
+            out.push(*key as u8);
+        }
+        let rng = self.rng.state();
+        out.extend_from_slice(&(rng.len() as u16).to_le_bytes());
+        out.extend_from_slice(&rng);
+        out
+    }
+
+    /// Restore a state produced by `save_state`, leaving the emulator
+    /// untouched if the state is rejected
+    pub fn load_state(&mut self, data: &[u8]) -> Result<(), StateError> {
+        let mut reader = Reader { data };
+
+        if reader.bytes(MAGIC.len())? != MAGIC {
+            return Err(StateError::BadMagic);
+        }
+        let version = reader.u8()?;
+        if version != STATE_VERSION {
+            return Err(StateError::UnsupportedVersion(version));
+        }
+        let mode = mode_from_byte(reader.u8()?)?;
+        if mode != self.mode {
+            return Err(StateError::ModeMismatch { expected: self.mode, found: mode });
+        }
+        let profile = Profile {
+            load_address: reader.u16()?,
+            entry_point: reader.u16()?,
+            display: display_from_byte(reader.u8()?)?,
+            font_address: reader.u16()?,
+            font: font_from_byte(reader.u8()?)?,
+        };
+        if profile != self.profile {
+            return Err(StateError::ProfileMismatch { expected: self.profile, found: profile });
+        }
+
+        let memory_len = reader.u32()? as usize;
+        if memory_len != self.memory.len() {
+            return Err(StateError::Corrupted);
+        }
+        let memory = reader.bytes(memory_len)?;
+        let regs = reader.bytes(16)?;
+        let i_reg = reader.u16()?;
+        let pc_reg = reader.u16()?;
+        let sp_reg = reader.u8()?;
+        if sp_reg as usize > self.stack_depth {
+            return Err(StateError::Corrupted);
+        }
+        let mut stack = [0; 16];
+        for addr in stack.iter_mut() {
+            *addr = reader.u16()?;
+        }
+        let dt_reg = reader.u8()?;
+        let st_reg = reader.u8()?;
+        let vblank = reader.bool()?;
+        let vip_cycles = reader.u32()? as i32;
+        let rpl = reader.bytes(16)?;
+        let audio_pattern = reader.bytes(16)?;
+        let pitch = reader.u8()?;
+
+        let resolution = (reader.u16()? as usize, reader.u16()? as usize);
+        let displays = [DisplaySize::Basic64x32, DisplaySize::Eti64x48, DisplaySize::Eti64x64, DisplaySize::Hp128x64];
+        if !displays.iter().any(|display| Emulator::get_resolution(*display) == resolution) {
+            return Err(StateError::Corrupted);
+        }
+        let row_len = resolution.0.div_ceil(8);
+        let bits = reader.bytes(PLANES * resolution.1 * row_len)?;
+        let mut screen = Framebuffer::new(resolution.0, resolution.1);
+        for (idx, row) in bits.chunks(row_len).enumerate() {
+            screen.set_row(idx / resolution.1, idx % resolution.1, row);
+        }
+        let planes = reader.u8()?;
+        if planes > 0x3 {
+            return Err(StateError::Corrupted);
+        }
+        let exited = reader.bool()?;
+        let mut keys = [false; 16];
+        for key in keys.iter_mut() {
+            *key = reader.bool()?;
+        }
+        let rng_len = reader.u16()? as usize;
+        let rng = reader.bytes(rng_len)?;
+        if !reader.data.is_empty() {
+            return Err(StateError::Corrupted);
+        }
+        // last check, the random source is only updated if the state is valid
+        if !self.rng.set_state(rng) {
+            return Err(StateError::Corrupted);
+        }
+
+        self.memory.copy_from_slice(memory);
+        self.memory_replaced();
+        self.regs.copy_from_slice(regs);
+        self.i_reg = i_reg;
+        self.pc_reg = pc_reg;
+        self.sp_reg = sp_reg;
+        self.stack = stack;
+        self.dt_reg = dt_reg;
+        self.st_reg = st_reg;
+        self.vblank = vblank;
+        self.vip_cycles = vip_cycles;
+        self.rpl.copy_from_slice(rpl);
+        self.audio_pattern.copy_from_slice(audio_pattern);
+        self.pitch = pitch;
+        self.resolution = resolution;
+        self.screen = screen;
+        self.planes = planes;
+        self.exited = exited;
+        self.keys = keys;
+        self.redraw = true;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test_state {
+    use super::*;
+    use crate::DisplaySize;
+    use crate::instruction::Instruction;
+    use crate::quirks::Quirks;
+    use crate::timing::Timing;
+
+    fn running_emulator() -> Emulator {
+        let mut emu = Emulator::new(DisplaySize::Basic64x32, Quirks::default(), 0);
+        emu.mem_load_instr(vec![
+            Instruction::LoadVal(1, 0x0A),
+            Instruction::LoadSprite(1),
+            Instruction::HighRes,
+            Instruction::Draw(1, 1, 5),
+            Instruction::Call(0x20C),
+            Instruction::Invalid,
+            Instruction::SetDelayTimer(1),
+            Instruction::LoadAddr(0x600),
+            Instruction::Bcd(1),
+        ]);
+        for _ in 0..8 {
+            emu.cpu_one_cycle().unwrap();
+        }
+        emu.keys[3] = true;
+        emu
+    }
+
+    #[test]
+    fn test_save_load_roundtrip() {
+        let emu = running_emulator();
+        let state = emu.save_state();
+
+        let mut restored = Emulator::new(DisplaySize::Basic64x32, Quirks::default(), 0);
+        restored.load_state(&state).unwrap();
+        assert_eq!(restored.memory, emu.memory);
+        assert_eq!(restored.regs, emu.regs);
+        assert_eq!(restored.i_reg, 0x600);
+        assert_eq!(restored.pc_reg, emu.pc_reg);
+        assert_eq!(restored.sp_reg, 1);
+        assert_eq!(restored.stack, emu.stack);
+        assert_eq!(restored.dt_reg, 0x0A);
+        assert_eq!(restored.resolution, (128, 64));
+        assert_eq!(restored.screen, emu.screen);
+        assert_eq!(restored.keys, emu.keys);
+        assert_eq!(restored.save_state(), state);
+    }
+
+    #[test]
+    fn test_load_continues_execution() {
+        let mut emu = running_emulator();
+        let state = emu.save_state();
+        emu.cpu_one_cycle().unwrap();
+
+        let mut restored = Emulator::new(DisplaySize::Basic64x32, Quirks::default(), 0);
+        restored.load_state(&state).unwrap();
+        restored.cpu_one_cycle().unwrap();
+        assert_eq!(restored.memory[0x600..0x603], [0, 1, 0]);
+        assert_eq!(restored.save_state(), emu.save_state());
+    }
+
+    #[test]
+    fn test_reject_incompatible_states() {
+        let emu = running_emulator();
+        let state = emu.save_state();
+        let mut other = Emulator::new(DisplaySize::Basic64x32, Quirks::default(), 0);
+        let pristine = other.save_state();
+
+        let mut bad_version = state.clone();
+        bad_version[4] = STATE_VERSION + 1;
+        assert_eq!(other.load_state(&bad_version), Err(StateError::UnsupportedVersion(STATE_VERSION + 1)));
+
+        assert_eq!(other.load_state(b"NOPE"), Err(StateError::BadMagic));
+        assert_eq!(other.load_state(&state[..state.len() - 1]), Err(StateError::Truncated));
+
+        // planes, exited, keys, random state
+        let planes = state.len() - emu.rng.state().len() - 20;
+        let mut bad_planes = state.clone();
+        assert_eq!(bad_planes[planes], 0x1);
+        bad_planes[planes] = 0x4;
+        assert_eq!(other.load_state(&bad_planes), Err(StateError::Corrupted));
+
+        // mode, profile, memory, registers, stack, timers, flags and audio
+        let resolution = 6 + 8 + 4 + emu.memory.len() + 16 + 2 + 2 + 1 + 32 + 2 + 1 + 4 + 16 + 16 + 1;
+        assert_eq!(state[resolution..resolution + 4], [128, 0, 64, 0]);
+        let mut bad_resolution = state.clone();
+        bad_resolution[resolution..resolution + 4].copy_from_slice(&[3, 0, 5, 0]);
+        assert_eq!(other.load_state(&bad_resolution), Err(StateError::Corrupted));
+
+        other.set_mode(Mode::XoChip);
+        assert_eq!(other.load_state(&state),
+                   Err(StateError::ModeMismatch { expected: Mode::XoChip, found: Mode::Chip8 }));
+        other.set_mode(Mode::Chip8);
+
+        other.set_profile(Profile::eti660()).unwrap();
+        assert_eq!(other.load_state(&state),
+                   Err(StateError::ProfileMismatch { expected: Profile::eti660(), found: Profile::chip8() }));
+        other.set_profile(Profile::chip8()).unwrap();
+        assert_eq!(other.save_state(), pristine);
+    }
+
+    #[test]
+    fn test_random_replay() {
+        let mut emu = Emulator::new(DisplaySize::Basic64x32, Quirks::default(), 42);
+        emu.mem_load_instr(vec![Instruction::Random(0, 0xFF), Instruction::Jump(0x200)]);
+        let state = emu.save_state();
+        let mut values = Vec::new();
+        for _ in 0..8 {
+            emu.cpu_one_cycle().unwrap();
+            emu.cpu_one_cycle().unwrap();
+            values.push(emu.regs[0]);
+        }
+
+        let mut restored = Emulator::new(DisplaySize::Basic64x32, Quirks::default(), 7);
+        restored.load_state(&state).unwrap();
+        for val in values {
+            restored.cpu_one_cycle().unwrap();
+            restored.cpu_one_cycle().unwrap();
+            assert_eq!(restored.regs[0], val);
+        }
+
+        let mut bad_rng = state.clone();
+        let len = bad_rng.len();
+        bad_rng[len - 8..].copy_from_slice(&[0; 8]);
+        assert_eq!(restored.load_state(&bad_rng), Err(StateError::Corrupted));
+    }
+
+    #[test]
+    fn test_vip_timing_replay() {
+        let vip = || {
+            let mut emu = Emulator::new(DisplaySize::Basic64x32, Quirks::vip(), 0);
+            emu.timing = Timing::Vip;
+            emu
+        };
+        let mut emu = vip();
+        emu.mem_load_instr(vec![
+            Instruction::Cls,
+            Instruction::AddVal(0, 1),
+            Instruction::Jump(0x200),
+        ]);
+        emu.run_frame().unwrap();
+        // the frame ended in the middle of a CLS
+        assert!(emu.vip_cycles < 0);
+        let state = emu.save_state();
+
+        let mut restored = vip();
+        restored.load_state(&state).unwrap();
+        for _ in 0..10 {
+            emu.run_frame().unwrap();
+            restored.run_frame().unwrap();
+            assert_eq!(restored.save_state(), emu.save_state());
+        }
+    }
+}
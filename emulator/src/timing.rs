@@ -0,0 +1,56 @@
+use crate::instruction::Instruction;
+
+/// How much code `run_frame` executes
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Timing {
+    /// a fixed number of instructions, see `instructions_per_frame`
+    #[default]
+    Instructions,
+    /// as many instructions as the COSMAC VIP interpreter would run in a
+    /// frame, sprites being drawn right after the display interrupt
+    Vip,
+}
+
+// the 1.76MHz CDP1802 runs 3668 machine cycles per 60Hz frame
+const VIP_FRAME_CYCLES: i32 = 3668;
+// the CDP1861 steals 8 cycles for each of its 128 lines, plus its interrupt routine
+const VIP_DISPLAY_CYCLES: i32 = 8 * 128 + 46;
+/// Machine cycles left to the interpreter in each frame
+pub const VIP_CYCLES_PER_FRAME: i32 = VIP_FRAME_CYCLES - VIP_DISPLAY_CYCLES;
+
+// fetching an instruction and jumping to its handler
+const VIP_FETCH_CYCLES: i32 = 40;
+
+/// Approximate number of 1802 machine cycles the VIP interpreter spends on
+/// an instruction, including fetching it
+pub fn vip_cycles(instr: Instruction) -> i32 {
+    let cycles = match instr {
+        Instruction::Cls => 3078,
+        Instruction::Ret => 10,
+        Instruction::Sys(_) => 26,
+        Instruction::Jump(_) => 12,
+        Instruction::Call(_) => 26,
+        Instruction::SkipValEq(_, _) | Instruction::SkipValNotEq(_, _) => 10,
+        Instruction::SkipEq(_, _) | Instruction::SkipNotEq(_, _) => 14,
+        Instruction::LoadVal(_, _) => 6,
+        Instruction::AddVal(_, _) => 10,
+        Instruction::Load(_, _) | Instruction::Or(_, _) | Instruction::And(_, _) |
+        Instruction::Xor(_, _) | Instruction::Add(_, _) | Instruction::Sub(_, _) |
+        Instruction::ShiftRight(_, _) | Instruction::SubN(_, _) |
+        Instruction::ShiftLeft(_, _) => 44,
+        Instruction::LoadAddr(_) => 12,
+        Instruction::JumpRel(_) => 22,
+        Instruction::Random(_, _) => 36,
+        Instruction::Draw(_, _, n) => 170 + 46 * n as i32,
+        Instruction::SkipKeyPressed(_) | Instruction::SkipKeyNotPressed(_) => 14,
+        Instruction::LoadDelayTimer(_) | Instruction::LoadKey(_) |
+        Instruction::SetDelayTimer(_) | Instruction::SetSoundTimer(_) => 10,
+        Instruction::AddI(_) => 16,
+        Instruction::LoadSprite(_) => 20,
+        Instruction::Bcd(_) => 364,
+        Instruction::StoreRegs(reg) | Instruction::LoadRegs(reg) => 14 + 14 * (reg as i32 + 1),
+        // not available on the VIP
+        _ => 0,
+    };
+    VIP_FETCH_CYCLES + cycles
+}